@@ -0,0 +1,115 @@
+#ifndef TINYCRDT_H
+#define TINYCRDT_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A text document, created by `tinycrdt_doc_new`.
+typedef struct TinycrdtDoc TinycrdtDoc;
+
+// Bytes handed out to C. Free with [`tinycrdt_buffer_free`].
+//
+// `data` is null, and `len` zero, when the call producing the buffer failed.
+typedef struct TinycrdtBuffer {
+  uint8_t *data;
+  size_t len;
+} TinycrdtBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates an empty document. Free it with [`tinycrdt_doc_free`].
+TinycrdtDoc *tinycrdt_doc_new(uint64_t client_id);
+
+// Frees a document. Does nothing if `doc` is null.
+//
+// # Safety
+//
+// `doc` must be null or come from [`tinycrdt_doc_new`], and not have been
+// freed yet.
+void tinycrdt_doc_free(TinycrdtDoc *doc);
+
+// Inserts the NUL-terminated UTF-8 string `text` at character position
+// `pos`. Returns `false`, leaving the document unchanged, if `text` is null
+// or not valid UTF-8, and also if inserting failed.
+//
+// # Safety
+//
+// `doc` must be a live document and `text` null or a NUL-terminated string.
+bool tinycrdt_doc_insert(TinycrdtDoc *doc, size_t pos, const char *text);
+
+// Deletes `len` characters starting at character position `pos`. Returns
+// `false` if deleting failed.
+//
+// # Safety
+//
+// `doc` must be a live document.
+bool tinycrdt_doc_delete(TinycrdtDoc *doc, size_t pos, size_t len);
+
+// Returns the text as a NUL-terminated UTF-8 string. Free it with
+// [`tinycrdt_string_free`].
+//
+// A NUL character in the text ends the returned string early. Returns null
+// if reading the text failed.
+//
+// # Safety
+//
+// `doc` must be a live document.
+char *tinycrdt_doc_value(const TinycrdtDoc *doc);
+
+// Frees a string returned by [`tinycrdt_doc_value`]. Does nothing if `value`
+// is null.
+//
+// # Safety
+//
+// `value` must be null or come from [`tinycrdt_doc_value`], and not have been
+// freed yet.
+void tinycrdt_string_free(char *value);
+
+// Encodes the document's state vector, to send to a peer so it can answer
+// with [`tinycrdt_doc_diff`]. Returns a null buffer if encoding failed.
+//
+// # Safety
+//
+// `doc` must be a live document.
+struct TinycrdtBuffer tinycrdt_doc_state_vector(const TinycrdtDoc *doc);
+
+// Encodes the changes a peer with the encoded state vector at
+// `state_vector` is missing. Pass a null `state_vector` to encode the whole
+// document. Returns a null buffer if the state vector is malformed or
+// encoding failed.
+//
+// # Safety
+//
+// `doc` must be a live document and `state_vector` null or point to `len`
+// readable bytes.
+struct TinycrdtBuffer tinycrdt_doc_diff(const TinycrdtDoc *doc,
+                                        const uint8_t *state_vector,
+                                        size_t len);
+
+// Applies the encoded update at `update`. Returns `false`, leaving the
+// document unchanged, if the update is malformed, and also if applying it
+// failed: the update goes into a copy of the document, which replaces it
+// only once applying succeeded.
+//
+// # Safety
+//
+// `doc` must be a live document and `update` point to `len` readable bytes.
+bool tinycrdt_doc_apply_update(TinycrdtDoc *doc, const uint8_t *update, size_t len);
+
+// Frees a buffer returned by this library. Does nothing for a null buffer.
+//
+// # Safety
+//
+// `buffer` must come from this library and not have been freed yet.
+void tinycrdt_buffer_free(struct TinycrdtBuffer buffer);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TINYCRDT_H */
@@ -0,0 +1,288 @@
+//! C API for [`tinycrdt`], declared in `include/tinycrdt.h`. Regenerate the
+//! header with `cbindgen --output include/tinycrdt.h` in this directory after
+//! changing it.
+//!
+//! Builds `libtinycrdt_c` as a static and a dynamic library. A panic never
+//! unwinds into C: the call fails instead, as it does for bad input.
+
+use std::ffi::{CStr, CString, c_char};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use tinycrdt::{BinaryEncode, Crdt, Doc, SequenceCrdt, StateVector, Update};
+
+/// Bytes handed out to C. Free with [`tinycrdt_buffer_free`].
+///
+/// `data` is null, and `len` zero, when the call producing the buffer failed.
+#[repr(C)]
+pub struct Buffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl Buffer {
+    const NULL: Buffer = Buffer {
+        data: ptr::null_mut(),
+        len: 0,
+    };
+
+    fn new(bytes: Vec<u8>) -> Self {
+        let bytes = Box::into_raw(bytes.into_boxed_slice());
+        Buffer {
+            data: bytes.cast(),
+            len: bytes.len(),
+        }
+    }
+}
+
+/// Borrows `len` bytes at `data`, allowing a null `data` for an empty slice.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }
+    }
+}
+
+/// Runs `f`, returning `failed` instead if it panics.
+fn guard<T>(failed: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(failed)
+}
+
+/// Creates an empty document. Free it with [`tinycrdt_doc_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn tinycrdt_doc_new(client_id: u64) -> *mut Doc {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(Doc::new(client_id)))
+    })
+}
+
+/// Frees a document. Does nothing if `doc` is null.
+///
+/// # Safety
+///
+/// `doc` must be null or come from [`tinycrdt_doc_new`], and not have been
+/// freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tinycrdt_doc_free(doc: *mut Doc) {
+    if !doc.is_null() {
+        drop(unsafe { Box::from_raw(doc) });
+    }
+}
+
+/// Inserts the NUL-terminated UTF-8 string `text` at character position
+/// `pos`. Returns `false`, leaving the document unchanged, if `text` is null
+/// or not valid UTF-8, and also if inserting failed.
+///
+/// # Safety
+///
+/// `doc` must be a live document and `text` null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tinycrdt_doc_insert(
+    doc: *mut Doc,
+    pos: usize,
+    text: *const c_char,
+) -> bool {
+    if text.is_null() {
+        return false;
+    }
+    let Ok(text) = unsafe { CStr::from_ptr(text) }.to_str() else {
+        return false;
+    };
+    guard(false, || {
+        unsafe { &mut *doc }.insert(pos, text);
+        true
+    })
+}
+
+/// Deletes `len` characters starting at character position `pos`. Returns
+/// `false` if deleting failed.
+///
+/// # Safety
+///
+/// `doc` must be a live document.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tinycrdt_doc_delete(doc: *mut Doc, pos: usize, len: usize) -> bool {
+    guard(false, || {
+        unsafe { &mut *doc }.delete(pos, len);
+        true
+    })
+}
+
+/// Returns the text as a NUL-terminated UTF-8 string. Free it with
+/// [`tinycrdt_string_free`].
+///
+/// A NUL character in the text ends the returned string early. Returns null
+/// if reading the text failed.
+///
+/// # Safety
+///
+/// `doc` must be a live document.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tinycrdt_doc_value(doc: *const Doc) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let mut value = unsafe { &*doc }.value().into_bytes();
+        if let Some(nul) = value.iter().position(|&b| b == 0) {
+            value.truncate(nul);
+        }
+        CString::new(value)
+            .expect("NUL characters were cut off")
+            .into_raw()
+    })
+}
+
+/// Frees a string returned by [`tinycrdt_doc_value`]. Does nothing if `value`
+/// is null.
+///
+/// # Safety
+///
+/// `value` must be null or come from [`tinycrdt_doc_value`], and not have been
+/// freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tinycrdt_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(unsafe { CString::from_raw(value) });
+    }
+}
+
+/// Encodes the document's state vector, to send to a peer so it can answer
+/// with [`tinycrdt_doc_diff`]. Returns a null buffer if encoding failed.
+///
+/// # Safety
+///
+/// `doc` must be a live document.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tinycrdt_doc_state_vector(doc: *const Doc) -> Buffer {
+    guard(Buffer::NULL, || {
+        Buffer::new(unsafe { &*doc }.state_vector().encode())
+    })
+}
+
+/// Encodes the changes a peer with the encoded state vector at
+/// `state_vector` is missing. Pass a null `state_vector` to encode the whole
+/// document. Returns a null buffer if the state vector is malformed or
+/// encoding failed.
+///
+/// # Safety
+///
+/// `doc` must be a live document and `state_vector` null or point to `len`
+/// readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tinycrdt_doc_diff(
+    doc: *const Doc,
+    state_vector: *const u8,
+    len: usize,
+) -> Buffer {
+    guard(Buffer::NULL, || {
+        let remote = if state_vector.is_null() {
+            StateVector::new()
+        } else {
+            match StateVector::decode(unsafe { bytes(state_vector, len) }) {
+                Some(remote) => remote,
+                None => return Buffer::NULL,
+            }
+        };
+        Buffer::new(unsafe { &*doc }.diff(&remote).encode())
+    })
+}
+
+/// Applies the encoded update at `update`. Returns `false`, leaving the
+/// document unchanged, if the update is malformed, and also if applying it
+/// failed: the update goes into a copy of the document, which replaces it
+/// only once applying succeeded.
+///
+/// # Safety
+///
+/// `doc` must be a live document and `update` point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tinycrdt_doc_apply_update(
+    doc: *mut Doc,
+    update: *const u8,
+    len: usize,
+) -> bool {
+    guard(false, || {
+        let Some(update) = Update::decode(unsafe { bytes(update, len) }) else {
+            return false;
+        };
+        let doc = unsafe { &mut *doc };
+        let mut applied = doc.clone();
+        applied.apply(update);
+        *doc = applied;
+        true
+    })
+}
+
+/// Frees a buffer returned by this library. Does nothing for a null buffer.
+///
+/// # Safety
+///
+/// `buffer` must come from this library and not have been freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tinycrdt_buffer_free(buffer: Buffer) {
+    if !buffer.data.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Copies a buffer's bytes and frees it.
+    fn take(buffer: Buffer) -> Vec<u8> {
+        let bytes = unsafe { bytes(buffer.data, buffer.len) }.to_vec();
+        unsafe { tinycrdt_buffer_free(buffer) };
+        bytes
+    }
+
+    fn value(doc: *const Doc) -> String {
+        let value = unsafe { tinycrdt_doc_value(doc) };
+        let string = unsafe { CStr::from_ptr(value) }
+            .to_str()
+            .unwrap()
+            .to_owned();
+        unsafe { tinycrdt_string_free(value) };
+        string
+    }
+
+    #[test]
+    fn docs_sync_through_c_api() {
+        let a = tinycrdt_doc_new(1);
+        let b = tinycrdt_doc_new(2);
+        unsafe {
+            assert!(tinycrdt_doc_insert(a, 0, c"hello world".as_ptr()));
+            tinycrdt_doc_delete(a, 5, 6);
+
+            let sv = take(tinycrdt_doc_state_vector(b));
+            let update = take(tinycrdt_doc_diff(a, sv.as_ptr(), sv.len()));
+            assert!(tinycrdt_doc_apply_update(b, update.as_ptr(), update.len()));
+        }
+
+        assert_eq!(value(a), "hello");
+        assert_eq!(value(b), "hello");
+        unsafe {
+            tinycrdt_doc_free(a);
+            tinycrdt_doc_free(b);
+        }
+    }
+
+    #[test]
+    fn bad_input_is_rejected() {
+        let doc = tinycrdt_doc_new(1);
+        unsafe {
+            assert!(!tinycrdt_doc_insert(doc, 0, ptr::null()));
+            assert!(!tinycrdt_doc_insert(doc, 0, c"\xff".as_ptr()));
+            assert!(!tinycrdt_doc_apply_update(doc, [1, 2, 3].as_ptr(), 3));
+            assert!(tinycrdt_doc_diff(doc, [5].as_ptr(), 1).data.is_null());
+            tinycrdt_buffer_free(Buffer::NULL);
+        }
+
+        assert_eq!(value(doc), "");
+        unsafe { tinycrdt_doc_free(doc) };
+    }
+
+    #[test]
+    fn panics_become_failures() {
+        assert!(!guard(false, || -> bool { panic!("boom") }));
+        assert!(guard(ptr::null_mut::<Doc>(), || panic!("boom")).is_null());
+    }
+}
@@ -0,0 +1,41 @@
+//! Compiles `tests/ffi/sync.c` against `include/tinycrdt.h` and the static
+//! library, and runs it. Needs a C compiler, `cc` unless `CC` says otherwise.
+
+#![cfg(unix)]
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The directory cargo put `libtinycrdt_c.a` in, which is `deps/` next to this
+/// test binary.
+fn lib_dir() -> PathBuf {
+    let exe = env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn c_program_syncs_docs() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let exe = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi-sync");
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+
+    let status = Command::new(cc)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-I"])
+        .arg(root.join("include"))
+        .arg(root.join("tests/ffi/sync.c"))
+        .arg("-o")
+        .arg(&exe)
+        .arg(lib_dir().join("libtinycrdt_c.a"))
+        .args(["-lpthread", "-ldl", "-lm"])
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(status.success(), "compiling sync.c failed");
+
+    let output = Command::new(&exe).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
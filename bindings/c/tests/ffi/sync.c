@@ -0,0 +1,75 @@
+// Syncs two documents through the C API. Exits non-zero on the first failed
+// check.
+
+#include "tinycrdt.h"
+
+#include <stdio.h>
+#include <string.h>
+
+#define CHECK(cond)                                                       \
+    do {                                                                  \
+        if (!(cond)) {                                                    \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,        \
+                    __LINE__, #cond);                                     \
+            return 1;                                                     \
+        }                                                                 \
+    } while (0)
+
+// Sends `from` the changes `to` is missing.
+static int sync(TinycrdtDoc *from, TinycrdtDoc *to) {
+    TinycrdtBuffer sv = tinycrdt_doc_state_vector(to);
+    TinycrdtBuffer update = tinycrdt_doc_diff(from, sv.data, sv.len);
+    CHECK(update.data != NULL);
+    CHECK(tinycrdt_doc_apply_update(to, update.data, update.len));
+    tinycrdt_buffer_free(update);
+    tinycrdt_buffer_free(sv);
+    return 0;
+}
+
+// Checks that `doc` reads `expected`.
+static int expect(const TinycrdtDoc *doc, const char *expected) {
+    char *value = tinycrdt_doc_value(doc);
+    int equal = strcmp(value, expected) == 0;
+    if (!equal) {
+        fprintf(stderr, "expected \"%s\", got \"%s\"\n", expected, value);
+    }
+    tinycrdt_string_free(value);
+    return equal ? 0 : 1;
+}
+
+int main(void) {
+    TinycrdtDoc *a = tinycrdt_doc_new(1);
+    TinycrdtDoc *b = tinycrdt_doc_new(2);
+
+    CHECK(tinycrdt_doc_insert(a, 0, "hello"));
+    CHECK(sync(a, b) == 0);
+    CHECK(expect(b, "hello") == 0);
+
+    // Concurrent edits on both sides
+    CHECK(tinycrdt_doc_insert(a, 5, " world"));
+    CHECK(tinycrdt_doc_insert(b, 0, "> "));
+    tinycrdt_doc_delete(b, 2, 1);
+    CHECK(sync(a, b) == 0);
+    CHECK(sync(b, a) == 0);
+    CHECK(expect(a, "> ello world") == 0);
+    CHECK(expect(b, "> ello world") == 0);
+
+    // A fresh peer catches up from the whole document
+    TinycrdtDoc *c = tinycrdt_doc_new(3);
+    TinycrdtBuffer all = tinycrdt_doc_diff(a, NULL, 0);
+    CHECK(tinycrdt_doc_apply_update(c, all.data, all.len));
+    tinycrdt_buffer_free(all);
+    CHECK(expect(c, "> ello world") == 0);
+
+    // Bad input is rejected without changing anything
+    const uint8_t garbage[] = {1, 2, 3};
+    CHECK(!tinycrdt_doc_apply_update(c, garbage, sizeof garbage));
+    CHECK(tinycrdt_doc_diff(c, garbage, 1).data == NULL);
+    CHECK(!tinycrdt_doc_insert(c, 0, "\xff"));
+    CHECK(expect(c, "> ello world") == 0);
+
+    tinycrdt_doc_free(a);
+    tinycrdt_doc_free(b);
+    tinycrdt_doc_free(c);
+    return 0;
+}
@@ -0,0 +1,96 @@
+//! Python bindings for [`tinycrdt::Doc`].
+//!
+//! Updates and state vectors cross the boundary as `bytes` in the same binary
+//! encoding the Rust side uses, so a script and a server exchange them as
+//! they are.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use tinycrdt::{BinaryEncode, Crdt, SequenceCrdt, StateVector, Update};
+
+/// A text document.
+#[pyclass(module = "tinycrdt")]
+struct Doc {
+    doc: tinycrdt::Doc,
+}
+
+#[pymethods]
+impl Doc {
+    /// Creates an empty document. `client_id` must be unique among all peers
+    /// editing the document.
+    #[new]
+    fn new(client_id: u64) -> Self {
+        Doc {
+            doc: tinycrdt::Doc::new(client_id),
+        }
+    }
+
+    #[getter]
+    fn client_id(&self) -> u64 {
+        self.doc.client_id
+    }
+
+    /// Inserts `text` at character position `index`.
+    fn insert(&mut self, index: usize, text: &str) {
+        self.doc.insert(index, text);
+    }
+
+    /// Deletes `length` characters starting at character position `index`.
+    fn delete(&mut self, index: usize, length: usize) {
+        self.doc.delete(index, length);
+    }
+
+    /// Returns the text.
+    fn value(&self) -> String {
+        self.doc.value()
+    }
+
+    /// The encoded state vector, to send to a peer so it can answer with
+    /// `diff`.
+    fn state_vector(&self) -> Vec<u8> {
+        self.doc.state_vector().encode()
+    }
+
+    /// Encodes the changes a peer with the given encoded state vector is
+    /// missing. Without one, encodes the whole document.
+    #[pyo3(signature = (state_vector=None))]
+    fn diff(&self, state_vector: Option<&[u8]>) -> PyResult<Vec<u8>> {
+        let remote = match state_vector {
+            Some(bytes) => StateVector::decode(bytes)
+                .ok_or_else(|| PyValueError::new_err("malformed state vector"))?,
+            None => StateVector::new(),
+        };
+        Ok(self.doc.diff(&remote).encode())
+    }
+
+    /// Applies an encoded update from a peer.
+    fn apply(&mut self, update: &[u8]) -> PyResult<()> {
+        let update =
+            Update::decode(update).ok_or_else(|| PyValueError::new_err("malformed update"))?;
+        self.doc.apply(update);
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.doc.len()
+    }
+
+    fn __str__(&self) -> String {
+        self.doc.value()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Doc(client_id={}, value={:?})",
+            self.doc.client_id,
+            self.doc.value()
+        )
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "tinycrdt")]
+mod module {
+    #[pymodule_export]
+    use super::Doc;
+}
@@ -0,0 +1,57 @@
+# Run from bindings/python with `maturin develop && pytest`, in a virtualenv
+# with maturin and pytest installed.
+
+import pytest
+
+from tinycrdt import Doc
+
+
+def sync(source, target):
+    target.apply(source.diff(target.state_vector()))
+
+
+def test_edits_change_the_text():
+    doc = Doc(1)
+    doc.insert(0, "hello world")
+    doc.delete(0, 6)
+    doc.insert(5, "!")
+
+    assert doc.value() == "world!"
+    assert str(doc) == "world!"
+    assert len(doc) == 6
+    assert doc.client_id == 1
+
+
+def test_docs_sync_through_diffs():
+    a = Doc(1)
+    b = Doc(2)
+    a.insert(0, "hello")
+    sync(a, b)
+
+    a.insert(5, " world")
+    b.insert(0, ">")
+    sync(a, b)
+    sync(b, a)
+
+    assert a.value() == ">hello world"
+    assert b.value() == a.value()
+
+
+def test_full_diff_restores_a_doc():
+    a = Doc(1)
+    a.insert(0, "snapshot")
+    update = a.diff()
+    assert isinstance(update, bytes)
+
+    b = Doc(2)
+    b.apply(update)
+    assert b.value() == "snapshot"
+
+
+def test_malformed_input_is_rejected():
+    doc = Doc(1)
+
+    with pytest.raises(ValueError):
+        doc.apply(b"\x01\x02\x03")
+    with pytest.raises(ValueError):
+        doc.diff(b"\x05")
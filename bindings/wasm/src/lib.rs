@@ -0,0 +1,141 @@
+//! JavaScript bindings for [`tinycrdt::Doc`].
+//!
+//! Updates and state vectors cross the boundary as `Uint8Array`s in the same
+//! binary encoding the Rust side uses, so a browser and a server exchange them
+//! as they are.
+
+use js_sys::{Function, Uint8Array};
+use tinycrdt::{BinaryEncode, ChangeTracker, Crdt, SequenceCrdt, StateVector, Update};
+use wasm_bindgen::prelude::*;
+
+/// A text document. Every change made through it, local or remote, is passed
+/// to observers as an encoded update.
+#[wasm_bindgen]
+pub struct Doc {
+    doc: tinycrdt::Doc,
+    observers: Vec<(u32, Function)>,
+    next_observer: u32,
+}
+
+#[wasm_bindgen]
+impl Doc {
+    /// Creates an empty document. `clientId` is a `BigInt` and must be
+    /// unique among all peers editing the document.
+    #[wasm_bindgen(constructor)]
+    pub fn new(client_id: u64) -> Doc {
+        Doc {
+            doc: tinycrdt::Doc::new(client_id),
+            observers: Vec::new(),
+            next_observer: 0,
+        }
+    }
+
+    /// The client ID as a `BigInt`, since IDs of the Rust side take up to 64
+    /// bits.
+    #[wasm_bindgen(getter, js_name = clientId)]
+    pub fn client_id(&self) -> u64 {
+        self.doc.client_id
+    }
+
+    /// Number of characters (Unicode scalar values, not UTF-16 units).
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> u32 {
+        self.doc.len() as u32
+    }
+
+    pub fn insert(&mut self, index: u32, text: &str) {
+        let before = self.before();
+        self.doc.insert(index as usize, text);
+        self.notify(before);
+    }
+
+    pub fn delete(&mut self, index: u32, length: u32) {
+        let before = self.before();
+        self.doc.delete(index as usize, length as usize);
+        self.notify(before);
+    }
+
+    /// Replaces the text with `text`, keeping the parts the two have in common.
+    #[wasm_bindgen(js_name = setValue)]
+    pub fn set_value(&mut self, text: &str) {
+        let before = self.before();
+        self.doc.set_value(text);
+        self.notify(before);
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn value(&self) -> String {
+        self.doc.value()
+    }
+
+    /// The encoded state vector, to send to a peer so it can answer with
+    /// [`encodeDiff`](Doc::encode_diff).
+    #[wasm_bindgen(js_name = stateVector)]
+    pub fn state_vector(&self) -> Vec<u8> {
+        self.doc.state_vector().encode()
+    }
+
+    /// Encodes the changes a peer with the given encoded state vector is
+    /// missing. Without one, encodes the whole document.
+    #[wasm_bindgen(js_name = encodeDiff)]
+    pub fn encode_diff(&self, state_vector: Option<Vec<u8>>) -> Result<Vec<u8>, JsError> {
+        let remote = match state_vector {
+            Some(bytes) => {
+                StateVector::decode(&bytes).ok_or_else(|| JsError::new("malformed state vector"))?
+            }
+            None => StateVector::new(),
+        };
+        Ok(self.doc.diff(&remote).encode())
+    }
+
+    /// Applies an encoded update from a peer.
+    #[wasm_bindgen(js_name = applyUpdate)]
+    pub fn apply_update(&mut self, update: &[u8]) -> Result<(), JsError> {
+        let update = Update::decode(update).ok_or_else(|| JsError::new("malformed update"))?;
+        let before = self.before();
+        self.doc.apply(update);
+        self.notify(before);
+        Ok(())
+    }
+
+    /// Calls `callback` with a `Uint8Array` holding the encoded update of
+    /// every following change. Returns an id for `unobserve`.
+    pub fn observe(&mut self, callback: Function) -> u32 {
+        let id = self.next_observer;
+        self.next_observer += 1;
+        self.observers.push((id, callback));
+        id
+    }
+
+    /// Removes an observer. Returns `false` if it wasn't registered.
+    pub fn unobserve(&mut self, id: u32) -> bool {
+        let len = self.observers.len();
+        self.observers.retain(|(observer, _)| *observer != id);
+        self.observers.len() != len
+    }
+}
+
+impl Doc {
+    /// Remembers what the document looked like before a change, to tell what
+    /// the change did.
+    fn before(&self) -> ChangeTracker {
+        ChangeTracker::new(&self.doc)
+    }
+
+    /// Passes what changed since `before` to the observers.
+    fn notify(&self, before: ChangeTracker) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let update = before.changes(&self.doc);
+        if update.is_empty() {
+            return;
+        }
+
+        let bytes = Uint8Array::from(update.encode().as_slice());
+        for (_, observer) in &self.observers {
+            // An observer throwing must not keep the others from seeing the change
+            let _ = observer.call1(&JsValue::NULL, &bytes);
+        }
+    }
+}
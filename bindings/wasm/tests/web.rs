@@ -0,0 +1,82 @@
+//! Run with `cargo test -p tinycrdt-wasm --target wasm32-unknown-unknown`,
+//! which needs `wasm-bindgen-test-runner` from wasm-bindgen-cli and node. The
+//! workspace's `.cargo/config.toml` sets it as the runner.
+
+#![cfg(target_arch = "wasm32")]
+
+use js_sys::{Array, Function, Uint8Array};
+use tinycrdt_wasm::Doc;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+/// Returns a JS function pushing its argument onto `array`.
+fn collector(array: &Array) -> Function {
+    Function::new_with_args("array", "return value => array.push(value)")
+        .call1(&JsValue::NULL, array)
+        .unwrap()
+        .unchecked_into()
+}
+
+#[wasm_bindgen_test]
+fn edits_change_the_text() {
+    let mut doc = Doc::new(1);
+    doc.insert(0, "hello world");
+    doc.delete(0, 6);
+    doc.insert(5, "!");
+
+    assert_eq!(doc.value(), "world!");
+    assert_eq!(doc.length(), 6);
+}
+
+#[wasm_bindgen_test]
+fn client_ids_take_64_bits() {
+    let doc = Doc::new(u64::MAX - 1);
+    assert_eq!(doc.client_id(), u64::MAX - 1);
+}
+
+#[wasm_bindgen_test]
+fn docs_sync_through_diffs() {
+    let mut a = Doc::new(1);
+    let mut b = Doc::new(2);
+    a.insert(0, "hello");
+    b.apply_update(&a.encode_diff(Some(b.state_vector())).unwrap())
+        .unwrap();
+
+    a.insert(5, " world");
+    b.insert(0, ">");
+    b.apply_update(&a.encode_diff(Some(b.state_vector())).unwrap())
+        .unwrap();
+    a.apply_update(&b.encode_diff(Some(a.state_vector())).unwrap())
+        .unwrap();
+
+    assert_eq!(a.value(), ">hello world");
+    assert_eq!(b.value(), a.value());
+}
+
+#[wasm_bindgen_test]
+fn observers_receive_updates() {
+    let mut a = Doc::new(1);
+    let updates = Array::new();
+    let id = a.observe(collector(&updates));
+
+    a.insert(0, "hi");
+    a.set_value("hi");
+    assert_eq!(updates.length(), 1);
+
+    let mut b = Doc::new(2);
+    b.apply_update(&Uint8Array::new(&updates.get(0)).to_vec())
+        .unwrap();
+    assert_eq!(b.value(), "hi");
+
+    assert!(a.unobserve(id));
+    a.insert(0, "x");
+    assert_eq!(updates.length(), 1);
+}
+
+#[wasm_bindgen_test]
+fn malformed_updates_are_rejected() {
+    let mut doc = Doc::new(1);
+
+    assert!(doc.apply_update(&[1, 2, 3]).is_err());
+    assert!(doc.encode_diff(Some(vec![5])).is_err());
+}
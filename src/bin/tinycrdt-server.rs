@@ -0,0 +1,123 @@
+//! Hosts tinycrdt documents over TCP and/or a Unix socket.
+//!
+//! ```text
+//! tinycrdt-server --data DIR [--tcp ADDR] [--unix PATH] [--ws ADDR]
+//! ```
+//!
+//! `--ws` needs the `websocket` feature.
+
+use std::net::TcpListener;
+use std::process::ExitCode;
+use std::thread;
+use tinycrdt::Server;
+
+const USAGE: &str = "usage: tinycrdt-server --data DIR [--tcp ADDR] [--unix PATH] [--ws ADDR]";
+
+struct Options {
+    data: String,
+    tcp: Option<String>,
+    unix: Option<String>,
+    ws: Option<String>,
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let (mut data, mut tcp, mut unix, mut ws) = (None, None, None, None);
+    while let Some(arg) = args.next() {
+        let slot = match arg.as_str() {
+            "--data" => &mut data,
+            "--tcp" => &mut tcp,
+            "--unix" => &mut unix,
+            "--ws" => &mut ws,
+            _ => return Err(format!("unknown argument {arg:?}")),
+        };
+        *slot = Some(args.next().ok_or(format!("{arg} needs a value"))?);
+    }
+
+    let data = data.ok_or("--data is required")?;
+    if tcp.is_none() && unix.is_none() && ws.is_none() {
+        return Err("at least one of --tcp, --unix and --ws is required".into());
+    }
+    Ok(Options {
+        data,
+        tcp,
+        unix,
+        ws,
+    })
+}
+
+fn main() -> ExitCode {
+    let options = match parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("tinycrdt-server: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: Options) -> std::io::Result<()> {
+    let server = Server::new(&options.data)?;
+    let mut listeners = Vec::new();
+
+    if let Some(addr) = options.tcp {
+        let listener = TcpListener::bind(&addr)?;
+        eprintln!("listening on {}", listener.local_addr()?);
+        let server = server.clone();
+        listeners.push(thread::spawn(move || server.serve_tcp(listener)));
+    }
+
+    if let Some(path) = options.unix {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            // A socket left behind by a previous run would make bind fail
+            if std::fs::metadata(&path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                std::fs::remove_file(&path)?;
+            }
+            let listener = std::os::unix::net::UnixListener::bind(&path)?;
+            eprintln!("listening on {path}");
+            let server = server.clone();
+            listeners.push(thread::spawn(move || server.serve_unix(listener)));
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unix sockets aren't supported on this platform",
+            ));
+        }
+    }
+
+    if let Some(addr) = options.ws {
+        #[cfg(feature = "websocket")]
+        {
+            let listener = TcpListener::bind(&addr)?;
+            eprintln!("listening on ws://{}", listener.local_addr()?);
+            let server = server.clone();
+            listeners.push(thread::spawn(move || server.serve_websocket(listener)));
+        }
+        #[cfg(not(feature = "websocket"))]
+        {
+            let _ = addr;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "built without the websocket feature",
+            ));
+        }
+    }
+
+    for listener in listeners {
+        listener.join().expect("listener thread panicked")?;
+    }
+    Ok(())
+}
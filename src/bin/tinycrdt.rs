@@ -0,0 +1,430 @@
+//! Inspects and manipulates files holding encoded updates.
+//!
+//! ```text
+//! tinycrdt decode FILE
+//! tinycrdt merge FILE... [-o OUT]
+//! tinycrdt diff FILE --state CLIENT:CLOCK[,CLIENT:CLOCK...] [-o OUT]
+//! tinycrdt text FILE...
+//! tinycrdt apply FILE... --client ID EDIT [-o OUT]
+//! ```
+//!
+//! `EDIT` is one of `--insert POS TEXT`, `--delete POS LEN` or `--set TEXT`.
+//! Commands producing an update write it to `OUT`, or to stdout without `-o`.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::process::ExitCode;
+use tinycrdt::{
+    Anchor, BinaryEncode, ChangeTracker, Crdt, Doc, ID, SequenceCrdt, StateVector, Update,
+    diff_update, merge_updates,
+};
+
+const USAGE: &str = "usage:
+  tinycrdt decode FILE
+  tinycrdt merge FILE... [-o OUT]
+  tinycrdt diff FILE --state CLIENT:CLOCK[,CLIENT:CLOCK...] [-o OUT]
+  tinycrdt text FILE...
+  tinycrdt apply FILE... --client ID (--insert POS TEXT | --delete POS LEN | --set TEXT) [-o OUT]";
+
+#[derive(Debug)]
+enum Error {
+    /// The command line was malformed, the usage is printed.
+    Usage(String),
+    Failed(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+fn usage(message: impl Into<String>) -> Error {
+    Error::Usage(message.into())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args, &mut io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Usage(message)) => {
+            eprintln!("{message}\n{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(Error::Failed(message)) => {
+            eprintln!("tinycrdt: {message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The parsed arguments of a subcommand: input files, options and the output.
+#[derive(Default)]
+struct Args {
+    files: Vec<String>,
+    output: Option<String>,
+    state: Option<String>,
+    client: Option<String>,
+    edit: Option<Edit>,
+}
+
+enum Edit {
+    Insert(usize, String),
+    Delete(usize, usize),
+    Set(String),
+}
+
+fn parse(args: &[String]) -> Result<Args> {
+    let mut parsed = Args::default();
+    let mut args = args.iter();
+    let value = |args: &mut std::slice::Iter<String>, flag: &str| {
+        args.next()
+            .cloned()
+            .ok_or_else(|| usage(format!("{flag} needs a value")))
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => parsed.output = Some(value(&mut args, arg)?),
+            "--state" => parsed.state = Some(value(&mut args, arg)?),
+            "--client" => parsed.client = Some(value(&mut args, arg)?),
+            "--insert" => {
+                let pos = number(&value(&mut args, arg)?)?;
+                parsed.edit = Some(Edit::Insert(pos, value(&mut args, arg)?));
+            }
+            "--delete" => {
+                let pos = number(&value(&mut args, arg)?)?;
+                parsed.edit = Some(Edit::Delete(pos, number(&value(&mut args, arg)?)?));
+            }
+            "--set" => parsed.edit = Some(Edit::Set(value(&mut args, arg)?)),
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(usage(format!("unknown option {flag}")));
+            }
+            _ => parsed.files.push(arg.clone()),
+        }
+    }
+    Ok(parsed)
+}
+
+fn number<T: std::str::FromStr>(arg: &str) -> Result<T> {
+    arg.parse()
+        .map_err(|_| usage(format!("{arg:?} is not a number")))
+}
+
+fn run(args: &[String], out: &mut impl Write) -> Result<()> {
+    let Some((command, args)) = args.split_first() else {
+        return Err(usage("missing command"));
+    };
+    let args = parse(args)?;
+
+    match command.as_str() {
+        "decode" => {
+            let [file] = args.files.as_slice() else {
+                return Err(usage("decode takes one file"));
+            };
+            write_text(out, &describe(&read_update(file)?))
+        }
+        "merge" => {
+            if args.files.is_empty() {
+                return Err(usage("merge needs at least one file"));
+            }
+            let updates = read_updates(&args.files)?;
+            write_update(out, args.output.as_deref(), &merge_updates(&updates))
+        }
+        "diff" => {
+            let [file] = args.files.as_slice() else {
+                return Err(usage("diff takes one file"));
+            };
+            let state = args
+                .state
+                .as_deref()
+                .ok_or_else(|| usage("diff needs --state"))?;
+            let diff = diff_update(&read_update(file)?, &parse_state_vector(state)?);
+            write_update(out, args.output.as_deref(), &diff)
+        }
+        "text" => {
+            let doc = load(&args.files, 0)?;
+            write_text(out, &format!("{}\n", doc.value()))
+        }
+        "apply" => {
+            let client = args
+                .client
+                .as_deref()
+                .ok_or_else(|| usage("apply needs --client"))?;
+            let edit = args.edit.ok_or_else(|| usage("apply needs an edit"))?;
+            let mut doc = load(&args.files, number(client)?)?;
+
+            let tracker = ChangeTracker::new(&doc);
+            match edit {
+                Edit::Insert(pos, text) => doc.insert(pos, &text),
+                Edit::Delete(pos, len) => doc.delete(pos, len),
+                Edit::Set(text) => doc.set_value(&text),
+            }
+            let update = tracker.changes(&doc);
+            if update.is_empty() {
+                return Err(Error::Failed("the edit changes nothing".into()));
+            }
+            write_update(out, args.output.as_deref(), &update)
+        }
+        _ => Err(usage(format!("unknown command {command:?}"))),
+    }
+}
+
+fn read_update(path: &str) -> Result<Update> {
+    let bytes = fs::read(path).map_err(|err| Error::Failed(format!("{path}: {err}")))?;
+    Update::decode(&bytes).ok_or_else(|| Error::Failed(format!("{path}: not an encoded update")))
+}
+
+fn read_updates(paths: &[String]) -> Result<Vec<Update>> {
+    paths.iter().map(|path| read_update(path)).collect()
+}
+
+/// Builds a document from update files. `client_id` only matters if the
+/// document is going to be edited.
+fn load(paths: &[String], client_id: u64) -> Result<Doc> {
+    if paths.is_empty() {
+        return Err(usage("no update files given"));
+    }
+    let mut doc = Doc::new(client_id);
+    doc.apply(merge_updates(&read_updates(paths)?));
+    Ok(doc)
+}
+
+/// Parses `CLIENT:CLOCK[,CLIENT:CLOCK...]`. An empty string is the empty state.
+fn parse_state_vector(arg: &str) -> Result<StateVector> {
+    arg.split(',')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (client, clock) = entry
+                .split_once(':')
+                .ok_or_else(|| usage(format!("{entry:?} is not CLIENT:CLOCK")))?;
+            Ok((number(client)?, number(clock)?))
+        })
+        .collect()
+}
+
+fn write_text(out: &mut impl Write, text: &str) -> Result<()> {
+    out.write_all(text.as_bytes())
+        .map_err(|err| Error::Failed(err.to_string()))
+}
+
+fn write_update(out: &mut impl Write, path: Option<&str>, update: &Update) -> Result<()> {
+    let bytes = update.encode();
+    match path {
+        Some(path) => fs::write(path, bytes).map_err(|err| Error::Failed(format!("{path}: {err}"))),
+        None => out
+            .write_all(&bytes)
+            .map_err(|err| Error::Failed(err.to_string())),
+    }
+}
+
+fn id(id: &ID) -> String {
+    format!("{}:{}", id.client, id.clock)
+}
+
+fn origin(origin: &Option<ID>) -> String {
+    origin.as_ref().map_or("-".into(), id)
+}
+
+fn anchor(anchor: &Anchor) -> String {
+    match anchor {
+        Anchor::Before(char_id) => format!("before {}", id(char_id)),
+        Anchor::After(char_id) => format!("after {}", id(char_id)),
+        Anchor::End => "end".into(),
+    }
+}
+
+/// Renders an update for reading.
+fn describe(update: &Update) -> String {
+    let mut text = String::new();
+
+    let _ = writeln!(text, "items ({}):", update.items.len());
+    for item in &update.items {
+        let _ = writeln!(
+            text,
+            "  {} {:?} origin_left={} origin_right={}",
+            id(&item.id),
+            item.content,
+            origin(&item.origin_left),
+            origin(&item.origin_right),
+        );
+    }
+
+    let mut ranges: Vec<_> = update.delete_set.iter().collect();
+    ranges.sort_unstable();
+    let _ = writeln!(text, "delete set ({}):", ranges.len());
+    for (client, clock, len) in ranges {
+        let _ = writeln!(text, "  {client}:{clock} len {len}");
+    }
+
+    let _ = writeln!(text, "marks ({}):", update.marks.len());
+    for mark in &update.marks {
+        let value = mark
+            .value
+            .as_deref()
+            .map_or("null".into(), |v| format!("{v:?}"));
+        let _ = writeln!(
+            text,
+            "  {} {}={} from {} to {}",
+            id(&mark.id),
+            mark.key,
+            value,
+            anchor(&mark.start),
+            anchor(&mark.end),
+        );
+    }
+
+    let _ = writeln!(text, "moves ({}):", update.moves.len());
+    for mv in &update.moves {
+        let _ = writeln!(
+            text,
+            "  {} after={} last={} target={}",
+            id(&mv.id),
+            origin(&mv.after),
+            id(&mv.last),
+            id(&mv.target),
+        );
+    }
+
+    let _ = writeln!(text, "subdocs ({}):", update.subdocs.len());
+    for subdoc in &update.subdocs {
+        let _ = writeln!(text, "  {} {:?}", id(&subdoc.id), subdoc.guid);
+    }
+
+    let _ = writeln!(text, "counters ({}):", update.counters.len());
+    for (name, counter) in &update.counters {
+        let _ = writeln!(text, "  {name} = {}", counter.value());
+    }
+
+    let _ = writeln!(text, "registers ({}):", update.registers.len());
+    for (name, register) in &update.registers {
+        let values: Vec<_> = register.values.iter().map(|(_, value)| value).collect();
+        let _ = writeln!(text, "  {name} = {values:?}");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Deref;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// The path of a file in the system temp directory, removed once dropped.
+    struct TempFile(String);
+
+    impl Deref for TempFile {
+        type Target = str;
+
+        fn deref(&self) -> &str {
+            &self.0
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Writes `update` to a fresh file in the system temp directory.
+    fn temp_file(update: &Update) -> TempFile {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("tinycrdt-cli-{}-{}.update", std::process::id(), n));
+        fs::write(&path, update.encode()).unwrap();
+        TempFile(path.to_string_lossy().into_owned())
+    }
+
+    fn run_args(args: &[&str]) -> Result<Vec<u8>> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut out = Vec::new();
+        run(&args, &mut out).map(|()| out)
+    }
+
+    /// Two updates: "hello" from client 1, then " world" with the "h" deleted.
+    fn history() -> (Doc, TempFile, TempFile) {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        let first = temp_file(&doc.diff(&StateVector::new()));
+        let sv = doc.state_vector();
+        doc.insert(5, " world");
+        doc.delete(0, 1);
+        let second = temp_file(&doc.diff(&sv));
+        (doc, first, second)
+    }
+
+    #[test]
+    fn text_renders_the_document() {
+        let (_, first, second) = history();
+
+        let out = run_args(&["text", &first, &second]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "ello world\n");
+    }
+
+    #[test]
+    fn decode_lists_items_and_deletes() {
+        let (_, first, second) = history();
+
+        let out = run_args(&["decode", &second]).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains(r#"1:5 " world" origin_left=1:4 origin_right=-"#));
+        assert!(text.contains("delete set (1):\n  1:0 len 1"));
+
+        let out = run_args(&["decode", &first]).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains(r#"1:0 "hello""#));
+    }
+
+    #[test]
+    fn merge_and_diff_produce_updates() {
+        let (doc, first, second) = history();
+
+        let merged = run_args(&["merge", &first, &second]).unwrap();
+        let merged = Update::decode(&merged).unwrap();
+        let mut replica = Doc::new(2);
+        replica.apply(merged.clone());
+        assert_eq!(replica.value(), doc.value());
+
+        let merged = temp_file(&merged);
+        let diff = run_args(&["diff", &merged, "--state", "1:4"]).unwrap();
+        let diff = Update::decode(&diff).unwrap();
+        assert_eq!(diff.items.len(), 1);
+        assert_eq!(diff.items[0].content, " world");
+    }
+
+    #[test]
+    fn apply_emits_the_edit() {
+        let (_, first, second) = history();
+
+        let edit = run_args(&[
+            "apply", &first, &second, "--client", "7", "--insert", "0", "H",
+        ])
+        .unwrap();
+        let edit = Update::decode(&edit).unwrap();
+        assert_eq!(edit.items.len(), 1);
+        assert!(edit.delete_set.is_empty());
+
+        let out = run_args(&["text", &first, &second, &temp_file(&edit)]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Hello world\n");
+
+        assert!(matches!(
+            run_args(&["apply", &first, "--client", "7", "--delete", "9", "1"]),
+            Err(Error::Failed(_))
+        ));
+    }
+
+    #[test]
+    fn bad_arguments_are_usage_errors() {
+        let (_, first, _) = history();
+
+        assert!(matches!(run_args(&[]), Err(Error::Usage(_))));
+        assert!(matches!(run_args(&["frobnicate"]), Err(Error::Usage(_))));
+        assert!(matches!(run_args(&["diff", &first]), Err(Error::Usage(_))));
+        assert!(matches!(
+            run_args(&["diff", &first, "--state", "1"]),
+            Err(Error::Usage(_))
+        ));
+        assert!(matches!(
+            run_args(&["text", "/nonexistent"]),
+            Err(Error::Failed(_))
+        ));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{ConflictResolver, Counter, DeleteSet, Doc, Register, StateVector, Update};
+use std::collections::BTreeMap;
+
+/// Remembers the state of a [`Doc`] so that whatever changed afterwards can be
+/// sent or stored as an [`Update`].
+///
+/// [`Crdt::diff`](crate::Crdt::diff) only has a state vector to go by, so it
+/// sends every deletion, mark and counter the document has. The update from
+/// [`changes`](Self::changes) holds the new items, deletions, marks, moves and
+/// sub-documents, plus the counters and registers that changed, so its size
+/// depends on the change rather than on the history.
+#[derive(Debug, Clone)]
+pub struct ChangeTracker {
+    state_vector: StateVector,
+    delete_set: DeleteSet,
+    marks: usize,
+    moves: usize,
+    counters: BTreeMap<String, Counter>,
+    registers: BTreeMap<String, Register>,
+}
+
+impl ChangeTracker {
+    /// Starts tracking from the current state of `doc`.
+    pub fn new<R: ConflictResolver>(doc: &Doc<R>) -> Self {
+        Self {
+            state_vector: doc.state_vector.clone(),
+            delete_set: doc.delete_set.clone(),
+            marks: doc.marks.len(),
+            moves: doc.moves.len(),
+            counters: doc.counters.clone(),
+            registers: doc.registers.clone(),
+        }
+    }
+
+    /// Returns what changed in `doc` since the tracker was created or last
+    /// [`reset`](Self::reset). The update is empty if nothing did.
+    pub fn changes<R: ConflictResolver>(&self, doc: &Doc<R>) -> Update {
+        Update {
+            items: doc.unseen_items(&self.state_vector),
+            delete_set: doc.delete_set.difference(&self.delete_set),
+            marks: doc.marks[self.marks..].to_vec(),
+            moves: doc.moves[self.moves..].to_vec(),
+            counters: changed(&doc.counters, &self.counters),
+            registers: changed(&doc.registers, &self.registers),
+            // References come with the character holding them
+            subdocs: doc
+                .subdoc_references()
+                .filter(|subdoc| {
+                    self.state_vector
+                        .get(&subdoc.id.client)
+                        .is_none_or(|clock| *clock < subdoc.id.clock)
+                })
+                .collect(),
+        }
+    }
+
+    /// Takes the current state of `doc` as the new starting point.
+    pub fn reset<R: ConflictResolver>(&mut self, doc: &Doc<R>) {
+        *self = Self::new(doc);
+    }
+}
+
+/// Entries of `now` that are new or differ from `before`.
+fn changed<T: Clone + PartialEq>(
+    now: &BTreeMap<String, T>,
+    before: &BTreeMap<String, T>,
+) -> BTreeMap<String, T> {
+    now.iter()
+        .filter(|(name, value)| before.get(*name) != Some(*value))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Attributes, BinaryEncode, Crdt, SequenceCrdt};
+
+    #[test]
+    fn no_changes_give_an_empty_update() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        doc.increment("views", 1);
+
+        assert!(ChangeTracker::new(&doc).changes(&doc).is_empty());
+    }
+
+    #[test]
+    fn changes_leave_out_earlier_history() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.delete(0, 1);
+        let mut bold = Attributes::new();
+        bold.insert("bold".into(), Some("true".into()));
+        doc.format(0, 4, &bold);
+        doc.increment("views", 1);
+        doc.increment("likes", 1);
+
+        let tracker = ChangeTracker::new(&doc);
+        doc.delete(5, 1);
+        doc.increment("likes", 1);
+        let update = tracker.changes(&doc);
+
+        assert!(update.items.is_empty());
+        assert_eq!(
+            update.delete_set.iter().collect::<Vec<_>>(),
+            vec![(1, 6, 1)]
+        );
+        assert!(update.marks.is_empty());
+        assert_eq!(update.counters.keys().collect::<Vec<_>>(), vec!["likes"]);
+        assert!(update.encode().len() < doc.diff(&doc.state_vector()).encode().len());
+    }
+
+    #[test]
+    fn changes_bring_a_replica_up_to_date() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        let mut replica = Doc::new(2);
+        replica.apply(doc.diff(&replica.state_vector()));
+
+        let mut tracker = ChangeTracker::new(&doc);
+        doc.insert(5, " world");
+        doc.delete(0, 1);
+        doc.set_register("title", "Hi");
+        replica.apply(tracker.changes(&doc));
+        tracker.reset(&doc);
+
+        assert_eq!(replica.value(), "ello world");
+        assert_eq!(replica.register("title"), vec!["Hi"]);
+        assert!(tracker.changes(&doc).is_empty());
+    }
+
+    #[test]
+    fn changes_hold_only_new_subdoc_references() {
+        let mut doc = Doc::new(1);
+        doc.insert_subdoc(0, "intro");
+
+        let tracker = ChangeTracker::new(&doc);
+        doc.insert_subdoc(1, "outro");
+        let update = tracker.changes(&doc);
+
+        let guids: Vec<_> = update.subdocs.iter().map(|s| s.guid.as_str()).collect();
+        assert_eq!(guids, ["outro"]);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::protocol::{Connection, Link, Message, read_message};
+use crate::{ConflictResolver, SharedDoc, YataResolver};
+use std::io::{self, BufReader};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Keeps a [`SharedDoc`] in sync with a document on a
+/// [`Server`](crate::Server).
+///
+/// Local changes are sent to the server as they happen and remote ones are
+/// applied in the background until the client is closed or dropped.
+pub struct Client<R: ConflictResolver = YataResolver> {
+    doc: SharedDoc<R>,
+    link: Arc<Link<R>>,
+    connection: Box<dyn Connection>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl<R: ConflictResolver + Send + Sync + 'static> Client<R> {
+    /// Connects `doc` to the document called `name` on the server at `addr`.
+    ///
+    /// Returns once the server has sent its state, so `doc` already holds the
+    /// server's content.
+    pub fn connect_tcp(
+        addr: impl ToSocketAddrs,
+        name: &str,
+        doc: SharedDoc<R>,
+    ) -> io::Result<Self> {
+        Self::connect(TcpStream::connect(addr)?, name, doc)
+    }
+
+    /// Like [`connect_tcp`](Self::connect_tcp), over a Unix socket.
+    #[cfg(unix)]
+    pub fn connect_unix(
+        path: impl AsRef<std::path::Path>,
+        name: &str,
+        doc: SharedDoc<R>,
+    ) -> io::Result<Self> {
+        Self::connect(std::os::unix::net::UnixStream::connect(path)?, name, doc)
+    }
+
+    fn connect(connection: impl Connection, name: &str, doc: SharedDoc<R>) -> io::Result<Self> {
+        let mut reader = BufReader::new(connection.try_clone()?);
+        let greeting = vec![
+            Message::Hello(name.to_owned()),
+            Message::SyncStep1(doc.state_vector()),
+        ];
+        let link = Arc::new(Link::new(doc.clone(), connection.try_clone()?, greeting));
+
+        // Wait for the server's half of the handshake
+        let handshake = (|| loop {
+            let message = read_message(&mut reader)?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed during handshake",
+                )
+            })?;
+            let synced = matches!(message, Message::SyncStep2(_));
+            link.handle(message)?;
+            if synced {
+                return Ok(());
+            }
+        })();
+        if let Err(err) = handshake {
+            link.finish();
+            let _ = connection.shutdown();
+            return Err(err);
+        }
+
+        let reading = Arc::clone(&link);
+        let reader = thread::spawn(move || {
+            while let Ok(Some(message)) = read_message(&mut reader) {
+                if reading.handle(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            doc,
+            link,
+            connection: Box::new(connection),
+            reader: Some(reader),
+        })
+    }
+}
+
+impl<R: ConflictResolver> Client<R> {
+    pub fn doc(&self) -> &SharedDoc<R> {
+        &self.doc
+    }
+
+    /// Sends any changes not yet written and disconnects. Dropping the client
+    /// does the same.
+    pub fn close(self) {}
+}
+
+impl<R: ConflictResolver> Drop for Client<R> {
+    fn drop(&mut self) {
+        self.link.finish();
+        let _ = self.connection.shutdown();
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
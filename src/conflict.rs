@@ -1,16 +1,803 @@
+use crate::index::ClockIndex;
 use crate::{ID, Item};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub trait ConflictResolver {
-    fn resolve(&self, a: &Item, b: &Item, doc: &HashMap<ID, Item>) -> Ordering;
+    /// Orders two concurrent insertions. `Less` puts `a` first.
+    fn resolve(&self, a: &Item, b: &Item, doc: &DocView) -> Ordering;
+
+    /// How a remote insertion is placed among concurrent insertions at the
+    /// same place. [`resolve`](Self::resolve) only breaks the remaining ties.
+    fn integration(&self) -> Integration {
+        Integration::Yata
+    }
+
+    /// Picks the item a remote `item` goes directly after: `left`, the item
+    /// ending with its left origin (`None` for the start of the document), or
+    /// one of the items between `left` and `right`, the item starting with its
+    /// right origin (`None` for the end). The items in between are all
+    /// concurrent with `item`, or were inserted after something that is.
+    ///
+    /// Every peer must pick the same place for the same items, whatever order
+    /// they arrive in, or the peers won't converge. Defers to
+    /// [`integration`](Self::integration) by default.
+    fn place(&self, item: &Item, left: Option<ID>, right: Option<ID>, doc: &DocView) -> Option<ID> {
+        self.integration().place(self, item, left, right, doc)
+    }
+}
+
+/// Algorithms for placing a remote insertion among concurrent ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integration {
+    /// YATA, as in Yjs. Concurrent runs typed at the same place are kept
+    /// apart, but when three or more peers are involved, concurrent text can
+    /// end up between a character and one typed directly before it.
+    Yata,
+    /// FugueMax. Concurrent text never ends up inside a run typed forwards or
+    /// backwards, so merges are never interleaved.
+    Fugue,
+    /// RGA. An insertion goes directly after its left origin, ahead of the
+    /// insertions there that [`resolve`](ConflictResolver::resolve) orders
+    /// after it. Ignores right origins.
+    Rga,
 }
 
-#[derive(Debug)]
+impl Integration {
+    /// Picks the item a remote `item` goes directly after, as described for
+    /// [`ConflictResolver::place`], using `resolver` to break ties.
+    pub fn place<R: ConflictResolver + ?Sized>(
+        self,
+        resolver: &R,
+        item: &Item,
+        left: Option<ID>,
+        right: Option<ID>,
+        doc: &DocView,
+    ) -> Option<ID> {
+        match self {
+            Integration::Yata => yata(resolver, item, left, right, doc),
+            Integration::Fugue => fugue(resolver, item, left, right, doc),
+            Integration::Rga => rga(resolver, item, left, doc),
+        }
+    }
+}
+
+/// A read-only view of a document's items, deleted ones included, in
+/// document order.
+#[derive(Debug, Clone, Copy)]
+pub struct DocView<'a> {
+    items: &'a HashMap<ID, Item>,
+    starts: &'a ClockIndex,
+    timestamps: &'a HashMap<ID, u64>,
+    head: Option<ID>,
+}
+
+impl<'a> DocView<'a> {
+    pub(crate) fn new(
+        items: &'a HashMap<ID, Item>,
+        starts: &'a ClockIndex,
+        timestamps: &'a HashMap<ID, u64>,
+        head: Option<ID>,
+    ) -> Self {
+        Self {
+            items,
+            starts,
+            timestamps,
+            head,
+        }
+    }
+
+    /// Returns the item starting with the character `id`.
+    pub fn get(&self, id: &ID) -> Option<&'a Item> {
+        self.items.get(id)
+    }
+
+    /// Returns the item containing the character `id`.
+    pub fn item_containing(&self, id: ID) -> Option<&'a Item> {
+        self.starts
+            .find(id)
+            .and_then(|start| self.items.get(&start))
+            .filter(|item| item.contains(&id))
+    }
+
+    /// Returns the first item of the document.
+    pub fn first(&self) -> Option<&'a Item> {
+        self.head.and_then(|id| self.items.get(&id))
+    }
+
+    /// Returns the item after `item`, or `None` if it is the last one or
+    /// isn't an item of this document.
+    pub fn next(&self, item: &Item) -> Option<&'a Item> {
+        item.right.and_then(|id| self.items.get(&id))
+    }
+
+    /// Returns the item before `item`, or `None` if it is the first one or
+    /// isn't an item of this document.
+    pub fn prev(&self, item: &Item) -> Option<&'a Item> {
+        item.left.and_then(|id| self.items.get(&id))
+    }
+
+    /// Iterates over the items after the item `left` and before the item
+    /// `right`, from the start or to the end of the document for `None`.
+    ///
+    /// Yields nothing if `left` isn't the start of an item. If `right` never
+    /// comes up, e.g. because it comes before `left` or isn't the start of an
+    /// item, the iteration runs to the end of the document.
+    pub fn between(
+        &self,
+        left: Option<ID>,
+        right: Option<ID>,
+    ) -> impl Iterator<Item = &'a Item> + use<'a> {
+        let items = self.items;
+        let first = match left {
+            Some(id) => items.get(&id).and_then(|item| item.right),
+            None => self.head,
+        };
+        std::iter::successors(first.and_then(|id| items.get(&id)), move |item| {
+            item.right.and_then(|id| items.get(&id))
+        })
+        .take_while(move |item| Some(item.id) != right)
+    }
+
+    /// Iterates over all items.
+    pub fn iter(&self) -> impl Iterator<Item = &'a Item> + use<'a> {
+        self.between(None, None)
+    }
+}
+
+/// YATA's [`Integration::place`].
+fn yata<R: ConflictResolver + ?Sized>(
+    resolver: &R,
+    item: &Item,
+    mut left: Option<ID>,
+    right: Option<ID>,
+    doc: &DocView,
+) -> Option<ID> {
+    let mut current = match left {
+        Some(lid) => doc.items[&lid].right,
+        None => doc.head,
+    };
+    let mut seen = HashSet::new();
+    let mut conflicting = HashSet::new();
+
+    while let Some(id) = current
+        && current != right
+    {
+        seen.insert(id);
+        conflicting.insert(id);
+
+        let other = &doc.items[&id];
+        if other.origin_left == item.origin_left {
+            // Concurrent insertion after the same character
+            if resolver.resolve(other, item, doc) == Ordering::Less {
+                left = Some(id);
+                conflicting.clear();
+            } else if other.origin_right == item.origin_right {
+                break;
+            }
+        } else if let Some(origin) = other
+            .origin_left
+            .and_then(|o| doc.item_containing(o))
+            .map(|o| o.id)
+            && seen.contains(&origin)
+        {
+            // `other` hangs off an item we've already passed
+            if !conflicting.contains(&origin) {
+                left = Some(id);
+                conflicting.clear();
+            }
+        } else {
+            break;
+        }
+
+        current = other.right;
+    }
+
+    left
+}
+
+/// FugueMax's [`Integration::place`].
+///
+/// Unlike YATA, an item only goes after a concurrent sibling whose right
+/// origin comes before its own if it also goes after everything inserted
+/// between that sibling and its right origin, so text typed directly before
+/// a character is never separated from it.
+fn fugue<R: ConflictResolver + ?Sized>(
+    resolver: &R,
+    item: &Item,
+    left: Option<ID>,
+    right: Option<ID>,
+    doc: &DocView,
+) -> Option<ID> {
+    let mut between = Vec::new();
+    let mut current = match left {
+        Some(lid) => doc.items[&lid].right,
+        None => doc.head,
+    };
+    while let Some(id) = current
+        && current != right
+    {
+        between.push(id);
+        current = doc.items[&id].right;
+    }
+    let position: HashMap<ID, usize> = between.iter().enumerate().map(|(i, &id)| (id, i)).collect();
+    let position_of = |origin: Option<ID>| {
+        origin
+            .and_then(|o| doc.item_containing(o))
+            .map(|o| o.id)
+            .and_then(|o| position.get(&o).copied())
+    };
+
+    let mut dest = left;
+    let mut scanning = false;
+    for (i, id) in between.iter().enumerate() {
+        if !scanning {
+            dest = i.checked_sub(1).map_or(left, |prev| Some(between[prev]));
+        }
+
+        let other = &doc.items[id];
+        if other.origin_left == item.origin_left {
+            if other.origin_right == item.origin_right {
+                // Concurrent insertion between the same two characters
+                if resolver.resolve(other, item, doc) != Ordering::Less {
+                    return dest;
+                }
+                scanning = false;
+            } else {
+                // Only go before `other` if we also go before the items
+                // inserted between it and its right origin
+                scanning = position_of(other.origin_right).is_some();
+            }
+        } else if position_of(other.origin_left).is_none_or(|p| p >= i) {
+            // `other` hangs off something before `left`
+            return dest;
+        }
+    }
+
+    if scanning {
+        dest
+    } else {
+        between.last().copied().or(left)
+    }
+}
+
+/// RGA's [`Integration::place`].
+///
+/// The item goes before the first concurrent sibling the resolver orders
+/// after it, skipping the siblings ordered before it along with everything
+/// inserted after them.
+fn rga<R: ConflictResolver + ?Sized>(
+    resolver: &R,
+    item: &Item,
+    mut left: Option<ID>,
+    doc: &DocView,
+) -> Option<ID> {
+    let mut current = match left {
+        Some(lid) => doc.items[&lid].right,
+        None => doc.head,
+    };
+    let mut skipped = HashSet::new();
+
+    while let Some(id) = current {
+        let other = &doc.items[&id];
+        let skip = if other.origin_left == item.origin_left {
+            resolver.resolve(other, item, doc) == Ordering::Less
+        } else {
+            // Skip whatever hangs off a sibling we've already skipped
+            other
+                .origin_left
+                .and_then(|o| doc.item_containing(o))
+                .map(|o| o.id)
+                .is_some_and(|origin| skipped.contains(&origin))
+        };
+        if !skip {
+            break;
+        }
+
+        skipped.insert(id);
+        left = Some(id);
+        current = other.right;
+    }
+
+    left
+}
+
+#[derive(Debug, Clone)]
 pub struct YataResolver;
 
 impl ConflictResolver for YataResolver {
-    fn resolve(&self, a: &Item, b: &Item, _doc: &HashMap<ID, Item>) -> Ordering {
+    fn resolve(&self, a: &Item, b: &Item, _doc: &DocView) -> Ordering {
         a.id.cmp(&b.id)
     }
 }
+
+/// Orders concurrent insertions with [`Integration::Fugue`], breaking ties by
+/// ID like [`YataResolver`].
+#[derive(Debug, Clone)]
+pub struct FugueResolver;
+
+impl ConflictResolver for FugueResolver {
+    fn resolve(&self, a: &Item, b: &Item, _doc: &DocView) -> Ordering {
+        a.id.cmp(&b.id)
+    }
+
+    fn integration(&self) -> Integration {
+        Integration::Fugue
+    }
+}
+
+/// Orders concurrent insertions with [`Integration::Rga`], newest first.
+///
+/// Updates don't carry Lamport timestamps, so an item's timestamp is derived
+/// from its origins instead: one more than the larger timestamp of the two,
+/// counting a missing origin as 0. Like a Lamport timestamp, it is larger than
+/// that of every sibling the inserting peer saw at the insertion point, so a
+/// local insertion keeps its place on every peer. Ties are broken by ID.
+///
+/// A document using this resolver keeps the timestamp of every item it
+/// integrates, so comparing two items only looks at their origins.
+#[derive(Debug, Clone)]
+pub struct RgaResolver;
+
+impl ConflictResolver for RgaResolver {
+    fn resolve(&self, a: &Item, b: &Item, doc: &DocView) -> Ordering {
+        let mut timestamps = HashMap::new();
+        let a_time = item_timestamp(a, doc, &mut timestamps);
+        let b_time = item_timestamp(b, doc, &mut timestamps);
+        b_time.cmp(&a_time).then(b.id.cmp(&a.id))
+    }
+
+    fn integration(&self) -> Integration {
+        Integration::Rga
+    }
+}
+
+/// The RGA timestamp of `item`'s first character. `timestamps` caches those
+/// of the items in `doc` the document doesn't keep, keyed by their IDs.
+pub(crate) fn item_timestamp(item: &Item, doc: &DocView, timestamps: &mut HashMap<ID, u64>) -> u64 {
+    // Work through the origins with a stack, as chains of them can be
+    // arbitrarily long
+    let mut stack = vec![item];
+    let mut result = 0;
+    while let Some(&current) = stack.last() {
+        let mut time = 0;
+        let mut missing = None;
+        for origin in [current.origin_left, current.origin_right]
+            .into_iter()
+            .flatten()
+        {
+            let Some(containing) = doc.item_containing(origin) else {
+                continue;
+            };
+            let known = doc.timestamps.get(&containing.id);
+            match known.or_else(|| timestamps.get(&containing.id)) {
+                Some(t) => time = time.max(t + origin.clock - containing.id.clock),
+                None => missing = Some(containing),
+            }
+        }
+
+        if let Some(origin) = missing {
+            stack.push(origin);
+            continue;
+        }
+        stack.pop();
+        result = time + 1;
+        if doc.get(&current.id).is_some() {
+            timestamps.insert(current.id, result);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sync;
+    use crate::{Crdt, Doc, SequenceCrdt};
+
+    fn fugue(client: u64) -> Doc<FugueResolver> {
+        Doc::with_resolver(client, FugueResolver)
+    }
+
+    /// Types `text` one character at a time, each after the previous one.
+    fn type_forwards<R: ConflictResolver>(doc: &mut Doc<R>, pos: usize, text: &str) {
+        for (i, ch) in text.chars().enumerate() {
+            doc.insert(pos + i, &ch.to_string());
+        }
+    }
+
+    /// Types `text` one character at a time, each before the previous one,
+    /// as when typing with the cursor moved back after every character.
+    fn type_backwards<R: ConflictResolver>(doc: &mut Doc<R>, pos: usize, text: &str) {
+        for ch in text.chars().rev() {
+            doc.insert(pos, &ch.to_string());
+        }
+    }
+
+    fn assert_not_interleaved(value: &str, runs: &[&str]) {
+        let mut orders = Vec::new();
+        permute(runs.to_vec(), 0, &mut orders);
+        assert!(
+            orders.contains(&value.to_owned()),
+            "{value:?} interleaves {runs:?}"
+        );
+    }
+
+    fn permute(mut runs: Vec<&str>, k: usize, orders: &mut Vec<String>) {
+        if k == runs.len() {
+            orders.push(runs.concat());
+            return;
+        }
+        for i in k..runs.len() {
+            runs.swap(k, i);
+            permute(runs.clone(), k + 1, orders);
+            runs.swap(k, i);
+        }
+    }
+
+    #[test]
+    fn forward_runs_are_not_interleaved() {
+        let mut a = fugue(1);
+        let mut b = fugue(2);
+        type_forwards(&mut a, 0, "alice");
+        type_forwards(&mut b, 0, "bob");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        assert_not_interleaved(&a.value(), &["alice", "bob"]);
+    }
+
+    #[test]
+    fn backward_runs_are_not_interleaved() {
+        let mut a = fugue(1);
+        let mut b = fugue(2);
+        a.insert(0, "[]");
+        sync(&mut a, &mut b);
+
+        type_backwards(&mut a, 1, "alice");
+        type_backwards(&mut b, 1, "bob");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        let inner = a.value();
+        assert_not_interleaved(&inner[1..inner.len() - 1], &["alice", "bob"]);
+    }
+
+    /// Three peers: one types `m`, another `wx`, both into `[]`. The third sees
+    /// `m`, types `ab` before it, and the first, having seen that, types `n`
+    /// between `b` and `m`.
+    fn three_way<R: ConflictResolver + Clone>(resolver: R) -> String {
+        let mut a = Doc::with_resolver(1, resolver.clone());
+        let mut b = Doc::with_resolver(2, resolver.clone());
+        let mut c = Doc::with_resolver(3, resolver);
+        a.insert(0, "[]");
+        sync(&mut a, &mut b);
+        sync(&mut a, &mut c);
+
+        c.insert(1, "m");
+        b.insert(1, "wx");
+        a.apply(c.diff(&a.state_vector()));
+        a.insert(1, "ab");
+        c.apply(a.diff(&c.state_vector()));
+        c.insert(3, "n");
+
+        sync(&mut a, &mut b);
+        sync(&mut a, &mut c);
+        sync(&mut a, &mut b);
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), c.value());
+        a.value()
+    }
+
+    #[test]
+    fn text_typed_before_a_character_stays_next_to_it() {
+        // YATA puts the concurrent `wx` between `n` and `m`
+        assert_eq!(three_way(YataResolver), "[abnwxm]");
+        assert_eq!(three_way(FugueResolver), "[wxabnm]");
+    }
+
+    #[test]
+    fn mixed_direction_runs_are_not_interleaved() {
+        let mut a = fugue(1);
+        let mut b = fugue(2);
+        let mut c = fugue(3);
+        a.insert(0, "<>");
+        sync(&mut a, &mut b);
+        sync(&mut b, &mut c);
+
+        type_forwards(&mut a, 1, "fwd");
+        type_backwards(&mut b, 1, "back");
+        // A run typed forwards, then continued from its middle
+        type_forwards(&mut c, 1, "mid");
+        type_forwards(&mut c, 2, "MID");
+        sync(&mut a, &mut b);
+        sync(&mut b, &mut c);
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(b.value(), c.value());
+        let value = a.value();
+        assert_not_interleaved(&value[1..value.len() - 1], &["fwd", "back", "mMIDid"]);
+    }
+
+    #[test]
+    fn runs_typed_after_a_shared_prefix_stay_together() {
+        let mut a = fugue(1);
+        let mut b = fugue(2);
+        a.insert(0, "Shopping: ");
+        sync(&mut a, &mut b);
+
+        type_backwards(&mut a, 10, "milk, ");
+        type_forwards(&mut b, 10, "eggs, ");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        assert_not_interleaved(&a.value()[10..], &["milk, ", "eggs, "]);
+    }
+
+    /// Makes random concurrent edits on three docs, syncing random pairs in
+    /// between, and checks that they end up the same.
+    fn random_edits_converge<R: ConflictResolver + Clone>(resolver: R) {
+        let mut seed: u64 = 7;
+        let mut rand = move |max: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((seed >> 33) as usize) % max.max(1)
+        };
+
+        let mut docs = [1, 2, 3].map(|client| Doc::with_resolver(client, resolver.clone()));
+        for _ in 0..100 {
+            for doc in docs.iter_mut() {
+                for _ in 0..3 {
+                    let len = doc.len();
+                    match rand(4) {
+                        0 if len > 0 => doc.delete(rand(len), 1 + rand(3)),
+                        1 => type_backwards(doc, rand(len + 1), ["ab", "cde"][rand(2)]),
+                        _ => type_forwards(doc, rand(len + 1), ["x", "yz", "🦀"][rand(3)]),
+                    }
+                }
+            }
+
+            let [a, b, c] = &mut docs;
+            match rand(3) {
+                0 => sync(a, b),
+                1 => sync(b, c),
+                _ => sync(a, c),
+            }
+        }
+
+        let [a, b, c] = &mut docs;
+        sync(a, b);
+        sync(b, c);
+        sync(a, b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(b.value(), c.value());
+    }
+
+    #[test]
+    fn random_concurrent_edits_converge() {
+        random_edits_converge(FugueResolver);
+        random_edits_converge(RgaResolver);
+    }
+
+    #[test]
+    fn rga_puts_newer_insertions_first() {
+        let mut a = Doc::with_resolver(1, RgaResolver);
+        let mut b = Doc::with_resolver(2, RgaResolver);
+        a.insert(0, "[]");
+        sync(&mut a, &mut b);
+
+        a.insert(1, "a");
+        b.insert(1, "b");
+        // Typed after seeing "b", so newer than both
+        b.insert(1, "c");
+        sync(&mut a, &mut b);
+
+        // "a" and "b" are as new as each other, so the higher ID goes first
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), "[cba]");
+    }
+
+    #[test]
+    fn rga_keeps_local_insertions_in_place() {
+        let mut a = Doc::with_resolver(1, RgaResolver);
+        let mut b = Doc::with_resolver(2, RgaResolver);
+        let mut c = Doc::with_resolver(3, RgaResolver);
+        type_forwards(&mut a, 0, "hello");
+        sync(&mut a, &mut b);
+        sync(&mut a, &mut c);
+
+        b.insert(5, "!");
+        c.insert(5, " world");
+        sync(&mut a, &mut b);
+        a.insert(5, "?");
+        sync(&mut a, &mut c);
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), c.value());
+        assert!(a.value().starts_with("hello?"), "{}", a.value());
+
+        // The timestamps kept along the way match ones worked out from scratch
+        let none = HashMap::new();
+        let view = DocView::new(&a.items, &a.starts, &none, a.head);
+        let mut timestamps = HashMap::new();
+        for (id, item) in &a.items {
+            assert_eq!(
+                item_timestamp(item, &view, &mut timestamps),
+                a.timestamps[id]
+            );
+        }
+        let mut yata = Doc::new(1);
+        yata.insert(0, "hello");
+        assert!(yata.timestamps.is_empty());
+    }
+
+    #[test]
+    fn rga_interleaves_backward_runs() {
+        let mut a = Doc::with_resolver(1, RgaResolver);
+        let mut b = Doc::with_resolver(2, RgaResolver);
+        type_forwards(&mut a, 0, "abc");
+        type_forwards(&mut b, 0, "xyz");
+        sync(&mut a, &mut b);
+        assert_eq!(a.value(), "xyzabc");
+
+        let mut a = Doc::with_resolver(1, RgaResolver);
+        let mut b = Doc::with_resolver(2, RgaResolver);
+        type_backwards(&mut a, 0, "abc");
+        type_backwards(&mut b, 0, "xyz");
+        sync(&mut a, &mut b);
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), "xaybzc");
+    }
+
+    /// Puts editors' insertions before everyone else's.
+    struct EditorsFirst {
+        editors: HashSet<u64>,
+    }
+
+    impl ConflictResolver for EditorsFirst {
+        fn resolve(&self, a: &Item, b: &Item, _doc: &DocView) -> Ordering {
+            let editor = |item: &Item| !self.editors.contains(&item.id.client);
+            editor(a).cmp(&editor(b)).then(a.id.cmp(&b.id))
+        }
+    }
+
+    #[test]
+    fn custom_resolver_can_prioritise_roles() {
+        let editors = || EditorsFirst {
+            editors: HashSet::from([2]),
+        };
+        let mut a = Doc::with_resolver(1, editors());
+        let mut b = Doc::with_resolver(2, editors());
+        a.insert(0, "[]");
+        sync(&mut a, &mut b);
+
+        a.insert(1, "viewer");
+        b.insert(1, "editor");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), "[editorviewer]");
+    }
+
+    /// Records the text of every conflict it places an item in, then defers to
+    /// YATA.
+    #[derive(Default)]
+    struct Recording {
+        conflicts: std::sync::Mutex<Vec<(String, Vec<String>)>>,
+    }
+
+    impl ConflictResolver for Recording {
+        fn resolve(&self, a: &Item, b: &Item, _doc: &DocView) -> Ordering {
+            a.id.cmp(&b.id)
+        }
+
+        fn place(
+            &self,
+            item: &Item,
+            left: Option<ID>,
+            right: Option<ID>,
+            doc: &DocView,
+        ) -> Option<ID> {
+            let between = doc
+                .between(left, right)
+                .map(|i| i.content.clone())
+                .collect();
+            self.conflicts
+                .lock()
+                .unwrap()
+                .push((item.content.clone(), between));
+            self.integration().place(self, item, left, right, doc)
+        }
+    }
+
+    #[test]
+    fn place_sees_all_concurrent_items() {
+        let mut a = Doc::with_resolver(1, Recording::default());
+        let mut b = Doc::with_resolver(2, Recording::default());
+        let mut c = Doc::with_resolver(3, Recording::default());
+        a.insert(0, "[]");
+        sync(&mut a, &mut b);
+        sync(&mut a, &mut c);
+
+        b.insert(1, "b");
+        c.insert(1, "c");
+        sync(&mut b, &mut c);
+        a.insert(1, "a");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        let conflicts = b.resolver.conflicts.lock().unwrap();
+        let (content, between) = conflicts.last().unwrap();
+        assert_eq!(content, "a");
+        assert_eq!(between, &["b", "c"]);
+    }
+
+    #[test]
+    fn view_walks_items_in_order() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        doc.insert(2, "--");
+        doc.delete(0, 1);
+        let view = doc.view();
+
+        let contents: Vec<_> = view.iter().map(|i| i.content.as_str()).collect();
+        assert_eq!(contents, ["h", "e", "--", "llo"]);
+        assert!(view.first().unwrap().is_deleted);
+
+        let dashes = view
+            .item_containing(ID {
+                client: 1,
+                clock: 6,
+            })
+            .unwrap();
+        assert_eq!(dashes.content, "--");
+        assert_eq!(
+            dashes.origin_left,
+            Some(ID {
+                client: 1,
+                clock: 1
+            })
+        );
+        assert_eq!(view.prev(dashes).unwrap().content, "e");
+        assert_eq!(view.next(dashes).unwrap().content, "llo");
+        assert_eq!(view.get(&dashes.id), Some(dashes));
+
+        let first = view.first().map(|i| i.id);
+        let last = view.iter().last().map(|i| i.id);
+        let inner: Vec<_> = view
+            .between(first, last)
+            .map(|i| i.content.as_str())
+            .collect();
+        assert_eq!(inner, ["e", "--"]);
+    }
+
+    #[test]
+    fn view_handles_ids_that_start_no_item() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        let view = doc.view();
+        let inside = ID {
+            client: 1,
+            clock: 2,
+        };
+
+        assert_eq!(view.between(Some(inside), None).count(), 0);
+        let all: Vec<_> = view
+            .between(None, Some(inside))
+            .map(|i| i.content.as_str())
+            .collect();
+        assert_eq!(all, ["hello"]);
+
+        let stray = Item {
+            right: Some(inside),
+            left: Some(inside),
+            ..view.first().unwrap().clone()
+        };
+        assert!(view.next(&stray).is_none());
+        assert!(view.prev(&stray).is_none());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{ConflictResolver, Doc, StateVector};
+
+/// A counter that can go up and down, e.g. for votes or views.
+///
+/// Every client keeps its own running totals of what it added and subtracted.
+/// Totals only grow, so two copies merge by keeping the larger total of each
+/// client, just like state vectors.
+///
+/// Totals stop at `u64::MAX` instead of wrapping, and the value stops at the
+/// bounds of `i64`. [`Crdt::diff`](crate::Crdt::diff) sends every counter;
+/// a [`ChangeTracker`](crate::ChangeTracker) sends only the ones that changed.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Counter {
+    pub increments: StateVector,
+    pub decrements: StateVector,
+}
+
+impl Counter {
+    /// Returns the increments minus the decrements, clamped to `i64`.
+    pub fn value(&self) -> i64 {
+        let sum = |totals: &StateVector| totals.values().map(|total| *total as i128).sum::<i128>();
+        let value = sum(&self.increments) - sum(&self.decrements);
+        value.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    /// Folds `other` into this counter. Merging is idempotent, so counters can
+    /// be sent again without being counted twice.
+    pub fn merge(&mut self, other: &Counter) {
+        merge_totals(&mut self.increments, &other.increments);
+        merge_totals(&mut self.decrements, &other.decrements);
+    }
+}
+
+fn merge_totals(totals: &mut StateVector, other: &StateVector) {
+    for (client, total) in other {
+        let own = totals.entry(*client).or_insert(*total);
+        *own = (*own).max(*total);
+    }
+}
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Adds `amount` to the counter `name`, which starts out at 0. Negative
+    /// amounts count down. This client's totals saturate rather than wrap.
+    pub fn increment(&mut self, name: &str, amount: i64) {
+        let counter = self.counters.entry(name.to_string()).or_default();
+        let totals = if amount < 0 {
+            &mut counter.decrements
+        } else {
+            &mut counter.increments
+        };
+        let total = totals.entry(self.client_id).or_default();
+        *total = total.saturating_add(amount.unsigned_abs());
+    }
+
+    /// Returns the value of the counter `name`, or 0 if it was never changed.
+    pub fn counter(&self, name: &str) -> i64 {
+        self.counters.get(name).map_or(0, Counter::value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ChangeTracker, Crdt, Doc, StateVector};
+
+    #[test]
+    fn counts_up_and_down() {
+        let mut doc = Doc::new(1);
+        doc.increment("votes", 3);
+        doc.increment("votes", -5);
+
+        assert_eq!(doc.counter("votes"), -2);
+        assert_eq!(doc.counter("views"), 0);
+    }
+
+    #[test]
+    fn concurrent_increments_add_up() {
+        let mut a = Doc::new(1);
+        let mut b = Doc::new(2);
+        a.increment("votes", 2);
+        b.increment("votes", 1);
+        b.increment("votes", -4);
+
+        let to_b = a.diff(&b.state_vector());
+        let to_a = b.diff(&a.state_vector());
+        b.apply(to_b.clone());
+        a.apply(to_a);
+        // Receiving the same update twice doesn't count it twice
+        b.apply(to_b);
+
+        assert_eq!(a.counter("votes"), -1);
+        assert_eq!(b.counter("votes"), -1);
+
+        a.increment("votes", 1);
+        b.apply(a.diff(&StateVector::new()));
+        assert_eq!(b.counter("votes"), 0);
+    }
+
+    #[test]
+    fn counters_saturate_instead_of_overflowing() {
+        let mut a = Doc::new(1);
+        let mut b = Doc::new(2);
+        a.increment("views", i64::MAX);
+        a.increment("views", i64::MAX);
+        a.increment("views", i64::MAX);
+        b.increment("views", i64::MAX);
+        a.apply(b.diff(&a.state_vector()));
+        assert_eq!(a.counter("views"), i64::MAX);
+
+        a.increment("debt", i64::MIN);
+        a.increment("debt", i64::MIN);
+        assert_eq!(a.counter("debt"), i64::MIN);
+    }
+
+    #[test]
+    fn tracked_changes_hold_only_changed_counters() {
+        let mut doc = Doc::new(1);
+        for name in ["a", "b", "c"] {
+            doc.increment(name, 1);
+        }
+
+        let tracker = ChangeTracker::new(&doc);
+        doc.increment("b", -1);
+        let update = tracker.changes(&doc);
+
+        assert_eq!(update.counters.len(), 1);
+        let mut replica = Doc::new(2);
+        replica.apply(doc.diff(&StateVector::new()));
+        replica.apply(update);
+        assert_eq!(replica.counter("b"), 0);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::ID;
+use std::collections::HashMap;
+
+/// Deleted characters, stored per client as sorted, non-overlapping
+/// `(clock, len)` ranges.
+///
+/// Unlike insertions, deletions aren't covered by the [`StateVector`](crate::StateVector),
+/// so the whole delete set travels with every update.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeleteSet {
+    clients: HashMap<u64, Vec<(u64, u64)>>,
+}
+
+impl DeleteSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `len` deleted characters starting at `id`.
+    pub fn insert(&mut self, id: ID, len: u64) {
+        if len == 0 {
+            return;
+        }
+
+        let ranges = self.clients.entry(id.client).or_default();
+        ranges.push((id.clock, len));
+        Self::normalize(ranges);
+    }
+
+    /// Returns `true` if the character with the given ID has been deleted.
+    pub fn contains(&self, id: &ID) -> bool {
+        self.clients.get(&id.client).is_some_and(|ranges| {
+            ranges
+                .iter()
+                .any(|&(clock, len)| id.clock >= clock && id.clock < clock.saturating_add(len))
+        })
+    }
+
+    /// Adds every range of `other` to this set.
+    pub fn merge(&mut self, other: &DeleteSet) {
+        for (client, ranges) in &other.clients {
+            let own = self.clients.entry(*client).or_default();
+            own.extend(ranges);
+            Self::normalize(own);
+        }
+    }
+
+    /// Returns the ranges of this set that aren't covered by `other`.
+    pub fn difference(&self, other: &DeleteSet) -> DeleteSet {
+        let mut clients = HashMap::new();
+        for (client, ranges) in &self.clients {
+            let theirs = other.clients.get(client).map_or(&[][..], Vec::as_slice);
+            let mut left = Vec::new();
+
+            for &(clock, len) in ranges {
+                let end = clock.saturating_add(len);
+                let mut from = clock;
+                let first = theirs.partition_point(|&(c, l)| c.saturating_add(l) <= clock);
+                for &(c, l) in theirs[first..].iter().take_while(|&&(c, _)| c < end) {
+                    if c > from {
+                        left.push((from, c - from));
+                    }
+                    from = from.max(c.saturating_add(l));
+                }
+                if from < end {
+                    left.push((from, end - from));
+                }
+            }
+
+            if !left.is_empty() {
+                clients.insert(*client, left);
+            }
+        }
+        DeleteSet { clients }
+    }
+
+    /// Iterates over all ranges as `(client, clock, len)`.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        self.clients.iter().flat_map(|(client, ranges)| {
+            ranges
+                .iter()
+                .map(move |&(clock, len)| (*client, clock, len))
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Sorts ranges and merges the ones that overlap or touch.
+    fn normalize(ranges: &mut Vec<(u64, u64)>) {
+        ranges.sort_unstable();
+
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for &(clock, len) in ranges.iter() {
+            match merged.last_mut() {
+                Some((last_clock, last_len)) if clock <= last_clock.saturating_add(*last_len) => {
+                    *last_len = (*last_len).max(clock.saturating_add(len) - *last_clock);
+                }
+                _ => merged.push((clock, len)),
+            }
+        }
+        *ranges = merged;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(client: u64, clock: u64) -> ID {
+        ID { client, clock }
+    }
+
+    #[test]
+    fn insert_merges_adjacent_ranges() {
+        let mut ds = DeleteSet::new();
+        ds.insert(id(1, 0), 2);
+        ds.insert(id(1, 2), 3);
+
+        assert_eq!(ds.iter().collect::<Vec<_>>(), vec![(1, 0, 5)]);
+    }
+
+    #[test]
+    fn insert_keeps_disjoint_ranges() {
+        let mut ds = DeleteSet::new();
+        ds.insert(id(1, 5), 1);
+        ds.insert(id(1, 0), 2);
+
+        assert_eq!(ds.iter().collect::<Vec<_>>(), vec![(1, 0, 2), (1, 5, 1)]);
+    }
+
+    #[test]
+    fn contains_checks_range_bounds() {
+        let mut ds = DeleteSet::new();
+        ds.insert(id(1, 3), 2);
+
+        assert!(!ds.contains(&id(1, 2)));
+        assert!(ds.contains(&id(1, 3)));
+        assert!(ds.contains(&id(1, 4)));
+        assert!(!ds.contains(&id(1, 5)));
+        assert!(!ds.contains(&id(2, 3)));
+    }
+
+    #[test]
+    fn merge_combines_overlapping_ranges() {
+        let mut a = DeleteSet::new();
+        a.insert(id(1, 0), 4);
+
+        let mut b = DeleteSet::new();
+        b.insert(id(1, 2), 4);
+        b.insert(id(2, 0), 1);
+
+        a.merge(&b);
+
+        assert!(a.contains(&id(1, 5)));
+        assert!(a.contains(&id(2, 0)));
+        assert_eq!(a.clients[&1], vec![(0, 6)]);
+    }
+
+    #[test]
+    fn huge_ranges_saturate() {
+        let mut ds = DeleteSet::new();
+        ds.insert(id(1, 5), u64::MAX);
+        ds.insert(id(1, 10), u64::MAX);
+
+        assert!(ds.contains(&id(1, u64::MAX - 1)));
+        assert!(!ds.contains(&id(1, 4)));
+        assert_eq!(ds.clients[&1], vec![(5, u64::MAX)]);
+    }
+
+    #[test]
+    fn difference_keeps_uncovered_parts() {
+        let mut a = DeleteSet::new();
+        a.insert(id(1, 0), 10);
+        a.insert(id(2, 0), 1);
+
+        let mut b = DeleteSet::new();
+        b.insert(id(1, 2), 3);
+        b.insert(id(1, 8), 5);
+        b.insert(id(2, 0), 1);
+
+        let diff = a.difference(&b);
+        assert_eq!(diff.clients[&1], vec![(0, 2), (5, 3)]);
+        assert!(!diff.clients.contains_key(&2));
+        assert!(b.difference(&b).is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+use crate::{Attributes, ConflictResolver, Doc, Run, SequenceCrdt};
+
+/// A Quill-style delta operation over the visible text.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeltaOp {
+    Insert {
+        text: String,
+        attributes: Attributes,
+    },
+    Retain {
+        len: usize,
+        attributes: Attributes,
+    },
+    Delete {
+        len: usize,
+    },
+}
+
+impl DeltaOp {
+    pub fn insert(text: &str) -> Self {
+        DeltaOp::Insert {
+            text: text.to_string(),
+            attributes: Attributes::new(),
+        }
+    }
+
+    pub fn retain(len: usize) -> Self {
+        DeltaOp::Retain {
+            len,
+            attributes: Attributes::new(),
+        }
+    }
+
+    pub fn delete(len: usize) -> Self {
+        DeltaOp::Delete { len }
+    }
+}
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Returns the document as a list of insert operations, one per run of
+    /// identically formatted text.
+    pub fn to_delta(&self) -> Vec<DeltaOp> {
+        self.runs()
+            .into_iter()
+            .map(|run| DeltaOp::Insert {
+                text: run.text,
+                attributes: run.attributes,
+            })
+            .collect()
+    }
+
+    /// Applies a delta to the visible content.
+    ///
+    /// Operations are interpreted left to right from position 0: `Retain` moves
+    /// past characters (formatting them if it carries attributes), `Insert` adds
+    /// text with exactly the given attributes, and `Delete` removes characters.
+    /// Anything after the last operation is left untouched.
+    pub fn apply_delta(&mut self, delta: &[DeltaOp]) {
+        let mut original = RunCursor::new(self.runs());
+        let mut pos = 0;
+        // Attributes of the character just before `pos`
+        let mut before = Attributes::new();
+
+        for op in delta {
+            match op {
+                DeltaOp::Retain { len, attributes } => {
+                    if let Some(last) = original.advance(*len) {
+                        before = last.clone();
+                        for (key, value) in attributes {
+                            match value {
+                                Some(value) => before.insert(key.clone(), Some(value.clone())),
+                                None => before.remove(key),
+                            };
+                        }
+                    }
+                    if !attributes.is_empty() {
+                        self.format(pos, *len, attributes);
+                    }
+                    pos += len;
+                }
+                DeltaOp::Insert { text, attributes } => {
+                    let len = text.chars().count();
+                    if len == 0 {
+                        continue;
+                    }
+                    self.insert(pos, text);
+
+                    // Only marks covering the character before can cover the
+                    // inserted text: marks growing at their end, and every mark
+                    // when inserting in the middle of its range. Clear anything
+                    // the delta didn't ask for
+                    let mut attributes = attributes.clone();
+                    for key in before.keys() {
+                        attributes.entry(key.clone()).or_insert(None);
+                    }
+                    if !attributes.is_empty() {
+                        self.format(pos, len, &attributes);
+                    }
+
+                    attributes.retain(|_, value| value.is_some());
+                    before = attributes;
+                    pos += len;
+                }
+                DeltaOp::Delete { len } => {
+                    original.advance(*len);
+                    self.delete(pos, *len);
+                }
+            }
+        }
+    }
+}
+
+/// Walks the runs of the text as it was before a delta, so the delta doesn't
+/// have to recompute them after every edit.
+struct RunCursor {
+    runs: Vec<Run>,
+    /// The run holding the last character passed, and where that run ends.
+    run: usize,
+    run_end: usize,
+    passed: usize,
+    len: usize,
+}
+
+impl RunCursor {
+    fn new(runs: Vec<Run>) -> Self {
+        let len = runs.iter().map(|run| run.text.chars().count()).sum();
+        let run_end = runs.first().map_or(0, |run| run.text.chars().count());
+        Self {
+            runs,
+            run: 0,
+            run_end,
+            passed: 0,
+            len,
+        }
+    }
+
+    /// Moves past `len` characters and returns the attributes of the last
+    /// one, or `None` if there weren't any left.
+    fn advance(&mut self, len: usize) -> Option<&Attributes> {
+        let passed = self.passed.saturating_add(len).min(self.len);
+        if passed == self.passed {
+            return None;
+        }
+        self.passed = passed;
+
+        while self.run_end < passed {
+            self.run += 1;
+            self.run_end += self.runs[self.run].text.chars().count();
+        }
+        Some(&self.runs[self.run].attributes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attrs(pairs: &[(&str, Option<&str>)]) -> Attributes {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.map(str::to_string)))
+            .collect()
+    }
+
+    fn insert_with(text: &str, pairs: &[(&str, Option<&str>)]) -> DeltaOp {
+        DeltaOp::Insert {
+            text: text.to_string(),
+            attributes: attrs(pairs),
+        }
+    }
+
+    #[test]
+    fn to_delta_of_empty_doc() {
+        let doc = Doc::new(1);
+
+        assert!(doc.to_delta().is_empty());
+    }
+
+    #[test]
+    fn to_delta_includes_attributes() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.format(6, 5, &attrs(&[("bold", Some("true"))]));
+
+        assert_eq!(
+            doc.to_delta(),
+            vec![
+                DeltaOp::insert("hello "),
+                insert_with("world", &[("bold", Some("true"))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_delta_inserts_into_empty_doc() {
+        let mut doc = Doc::new(1);
+        doc.apply_delta(&[
+            DeltaOp::insert("hello "),
+            insert_with("world", &[("italic", Some("true"))]),
+        ]);
+
+        assert_eq!(doc.value(), "hello world");
+        assert_eq!(
+            doc.to_delta(),
+            vec![
+                DeltaOp::insert("hello "),
+                insert_with("world", &[("italic", Some("true"))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_delta_retain_then_insert() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.apply_delta(&[DeltaOp::retain(5), DeltaOp::insert(",")]);
+
+        assert_eq!(doc.value(), "hello, world");
+    }
+
+    #[test]
+    fn apply_delta_retain_then_delete() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.apply_delta(&[DeltaOp::retain(5), DeltaOp::delete(6)]);
+
+        assert_eq!(doc.value(), "hello");
+    }
+
+    #[test]
+    fn apply_delta_multiple_ops_use_running_position() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "abcdef");
+        doc.apply_delta(&[
+            DeltaOp::delete(1),
+            DeltaOp::retain(2),
+            DeltaOp::insert("X"),
+            DeltaOp::delete(1),
+            DeltaOp::retain(1),
+            DeltaOp::insert("Y"),
+        ]);
+
+        assert_eq!(doc.value(), "bcXeYf");
+    }
+
+    #[test]
+    fn apply_delta_retain_formats() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        doc.apply_delta(&[DeltaOp::Retain {
+            len: 2,
+            attributes: attrs(&[("bold", Some("true"))]),
+        }]);
+
+        assert_eq!(
+            doc.to_delta(),
+            vec![
+                insert_with("he", &[("bold", Some("true"))]),
+                DeltaOp::insert("llo"),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_delta_retain_with_none_clears() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        doc.format(0, 5, &attrs(&[("bold", Some("true"))]));
+        doc.apply_delta(&[DeltaOp::Retain {
+            len: 5,
+            attributes: attrs(&[("bold", None)]),
+        }]);
+
+        assert_eq!(doc.to_delta(), vec![DeltaOp::insert("hello")]);
+    }
+
+    #[test]
+    fn apply_delta_insert_does_not_inherit_formatting() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "ab");
+        doc.format(0, 1, &attrs(&[("bold", Some("true"))]));
+        doc.apply_delta(&[DeltaOp::retain(1), DeltaOp::insert("X")]);
+
+        assert_eq!(
+            doc.to_delta(),
+            vec![
+                insert_with("a", &[("bold", Some("true"))]),
+                DeltaOp::insert("Xb"),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_delta_insert_inside_link_is_not_linked() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "ab");
+        doc.format(0, 2, &attrs(&[("link", Some("https://example.com"))]));
+        doc.apply_delta(&[DeltaOp::retain(1), DeltaOp::insert("X")]);
+
+        assert_eq!(
+            doc.to_delta(),
+            vec![
+                insert_with("a", &[("link", Some("https://example.com"))]),
+                DeltaOp::insert("X"),
+                insert_with("b", &[("link", Some("https://example.com"))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_delta_inserts_after_earlier_ops_of_the_same_delta() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "abcd");
+        doc.format(0, 2, &attrs(&[("italic", Some("true"))]));
+        doc.apply_delta(&[
+            DeltaOp::Retain {
+                len: 1,
+                attributes: attrs(&[("bold", Some("true"))]),
+            },
+            DeltaOp::insert("X"),
+            insert_with("Y", &[("italic", Some("true"))]),
+            DeltaOp::delete(2),
+            DeltaOp::insert("Z"),
+        ]);
+
+        assert_eq!(
+            doc.to_delta(),
+            vec![
+                insert_with("a", &[("bold", Some("true")), ("italic", Some("true"))]),
+                DeltaOp::insert("X"),
+                insert_with("Y", &[("italic", Some("true"))]),
+                DeltaOp::insert("Zd"),
+            ]
+        );
+    }
+
+    #[test]
+    fn delta_round_trips_through_new_doc() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello brave new world");
+        doc.format(0, 5, &attrs(&[("bold", Some("true"))]));
+        doc.format(
+            6,
+            5,
+            &attrs(&[
+                ("link", Some("https://example.com")),
+                ("italic", Some("true")),
+            ]),
+        );
+
+        let mut copy = Doc::new(2);
+        copy.apply_delta(&doc.to_delta());
+
+        assert_eq!(copy.to_delta(), doc.to_delta());
+    }
+}
@@ -1,15 +1,41 @@
-use crate::{ConflictResolver, Crdt, ID, Item, SequenceCrdt, StateVector, YataResolver};
-use std::collections::HashMap;
-
-#[derive(Debug)]
+use crate::conflict::item_timestamp;
+use crate::index::{ClockIndex, PositionIndex};
+use crate::moves::Layout;
+use crate::update::unseen;
+use crate::{
+    ConflictResolver, Counter, Crdt, DeleteSet, DocView, ID, Integration, Item, Mark, Move,
+    Register, SequenceCrdt, StateVector, Update, YataResolver,
+};
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone)]
 pub struct Doc<R: ConflictResolver = YataResolver> {
     pub client_id: u64,
     pub clock: u64,
     pub items: HashMap<ID, Item>,
     pub pending: Vec<Item>,
     pub state_vector: StateVector,
+    pub delete_set: DeleteSet,
+    pub marks: Vec<Mark>,
+    pub moves: Vec<Move>,
+    pub counters: BTreeMap<String, Counter>,
+    pub registers: BTreeMap<String, Register>,
+    /// GUIDs of the referenced sub-documents, by the ID of the character
+    /// holding the reference.
+    pub subdocs: BTreeMap<ID, String>,
     pub head: Option<ID>,
     pub resolver: R,
+    /// Every item, tombstones included, in document order once moves are in
+    /// play. Kept up to date along with the index.
+    pub(crate) order: Vec<ID>,
+    pub(crate) index: PositionIndex,
+    /// Which items the moves carry, worked out when first needed.
+    pub(crate) layout: Option<Layout>,
+    /// Where each item starts in its client's clock range.
+    pub(crate) starts: ClockIndex,
+    /// RGA timestamps of the first character of every item, kept only for a
+    /// resolver using [`Integration::Rga`].
+    pub(crate) timestamps: HashMap<ID, u64>,
 }
 
 impl Doc<YataResolver> {
@@ -20,8 +46,19 @@ impl Doc<YataResolver> {
             items: HashMap::new(),
             pending: Vec::new(),
             state_vector: HashMap::new(),
+            delete_set: DeleteSet::new(),
+            marks: Vec::new(),
+            moves: Vec::new(),
+            counters: BTreeMap::new(),
+            registers: BTreeMap::new(),
+            subdocs: BTreeMap::new(),
             head: None,
             resolver: YataResolver,
+            order: Vec::new(),
+            index: PositionIndex::default(),
+            layout: None,
+            starts: ClockIndex::default(),
+            timestamps: HashMap::new(),
         }
     }
 }
@@ -34,11 +71,27 @@ impl<R: ConflictResolver> Doc<R> {
             items: HashMap::new(),
             pending: Vec::new(),
             state_vector: HashMap::new(),
+            delete_set: DeleteSet::new(),
+            marks: Vec::new(),
+            moves: Vec::new(),
+            counters: BTreeMap::new(),
+            registers: BTreeMap::new(),
+            subdocs: BTreeMap::new(),
             head: None,
             resolver,
+            order: Vec::new(),
+            index: PositionIndex::default(),
+            layout: None,
+            starts: ClockIndex::default(),
+            timestamps: HashMap::new(),
         }
     }
 
+    /// A read-only view of the items, as passed to the resolver.
+    pub fn view(&self) -> DocView<'_> {
+        DocView::new(&self.items, &self.starts, &self.timestamps, self.head)
+    }
+
     /// Generates a new unique identifier for a local operation.
     ///
     /// Returns an [`ID`] with the current clock value, then advances the clock
@@ -55,6 +108,29 @@ impl<R: ConflictResolver> Doc<R> {
         id
     }
 
+    /// Returns the first clock value of `client` that hasn't been integrated yet.
+    fn next_clock(&self, client: u64) -> u64 {
+        self.state_vector.get(&client).map_or(0, |clock| clock + 1)
+    }
+
+    /// Returns `true` if the character with the given ID has been integrated.
+    pub(crate) fn contains_id(&self, id: &ID) -> bool {
+        self.state_vector
+            .get(&id.client)
+            .is_some_and(|clock| *clock >= id.clock)
+    }
+
+    /// Finds the item containing the character with the given ID.
+    ///
+    /// Items of a client partition its clock range, so the containing item is the
+    /// closest item starting at or before `id.clock`.
+    pub(crate) fn find_item(&self, id: ID) -> Option<ID> {
+        if !self.contains_id(&id) {
+            return None;
+        }
+        self.view().item_containing(id).map(|item| item.id)
+    }
+
     /// Finds the insertion position in the linked list for a given character position.
     ///
     /// Returns the neighboring items and offset for where to insert. If `offset > 0`,
@@ -68,36 +144,20 @@ impl<R: ConflictResolver> Doc<R> {
     ///
     /// `(left, right, offset)`:
     /// * `left` - Item before insertion point, or `None` if at start
-    /// * `right` - Item at/after insertion point, or `None` if at end  
+    /// * `right` - Item at/after insertion point, or `None` if at end
     /// * `offset` - Characters into `right` item (0 = before, >0 = split here)
-    fn find_pos(&self, pos: usize) -> (Option<ID>, Option<ID>, usize) {
-        let mut index = 0;
-        let mut left = None;
-        let mut current = self.head;
-
-        while let Some(id) = current {
-            let item = &self.items[&id];
-            if item.is_deleted {
-                current = item.right;
-                continue;
-            }
-
-            let len = item.content.chars().count();
-            if index + len > pos {
-                let offset = pos - index;
-                return (left, Some(id), offset);
-            }
-
-            index += len;
-            left = Some(id);
-            current = item.right;
+    pub(crate) fn find_pos(&self, pos: usize) -> (Option<ID>, Option<ID>, usize) {
+        let entries = &self.index.entries;
+        let idx = self.index.find(pos);
+        let left = idx.checked_sub(1).map(|i| entries[i].id);
+
+        match entries.get(idx) {
+            Some(entry) => (left, Some(entry.id), pos - entry.start),
+            None => (left, None, 0),
         }
-
-        (left, None, 0)
     }
 
-    /// Splits an item at the given offset, creating a new item for the left part.
-    /// Returns the ID of the newly created left split item.
+    /// Splits an item at the given offset, creating a new item for the right part.
     ///
     /// # Arguments
     ///
@@ -106,53 +166,278 @@ impl<R: ConflictResolver> Doc<R> {
     ///
     /// # Returns
     ///
-    /// The ID of the newly created left split item. The original item retains
-    /// its ID but its content is updated to contain only the right part.
+    /// The ID of the newly created right split item. The original item retains
+    /// its ID but its content is updated to contain only the left part. The right
+    /// part is identified by the clock of its first character, so every replica
+    /// that splits the same item at the same offset ends up with the same IDs.
     fn split_item(&mut self, item_id: ID, offset: usize) -> ID {
-        let item = self.items.get(&item_id).unwrap();
-        let item_left = item.left;
-
-        // Split the content
-        let mut chars = item.content.chars();
-        let left_content: String = chars.by_ref().take(offset).collect();
-        let right_content: String = chars.collect();
-
-        // Create new left split item
-        let left_split_id = self.next_id(&left_content);
-        let left_split = Item {
-            id: left_split_id,
-            left: item_left,
-            right: Some(item_id),
-            content: left_content,
+        let item = self.items.get_mut(&item_id).unwrap();
+        let right_split = item.split_off(offset);
+        let right_id = right_split.id;
+
+        item.right = Some(right_id);
+
+        // Update the next item's left pointer
+        if let Some(next_id) = right_split.right {
+            self.items.get_mut(&next_id).unwrap().left = Some(right_id);
+        }
+
+        self.items.insert(right_id, right_split);
+        self.starts.insert(right_id);
+        if let Some(time) = self.timestamps.get(&item_id) {
+            self.timestamps.insert(right_id, time + offset as u64);
+        }
+        let owner = self.owner(item_id);
+        self.lay_out(right_id, owner);
+
+        right_id
+    }
+
+    /// Makes sure an item starts exactly at `id`, splitting the containing item if
+    /// needed, and returns `id`.
+    pub(crate) fn clean_start(&mut self, id: ID) -> ID {
+        let start = self.find_item(id).expect("item should exist");
+        if start != id {
+            self.split_item(start, (id.clock - start.clock) as usize);
+        }
+        id
+    }
+
+    /// Makes sure an item ends exactly at `id`, splitting the containing item if
+    /// needed, and returns the ID of that item.
+    pub(crate) fn clean_end(&mut self, id: ID) -> ID {
+        let start = self.find_item(id).expect("item should exist");
+        if self.items[&start].last_id() != id {
+            self.split_item(start, (id.clock - start.clock + 1) as usize);
+        }
+        start
+    }
+
+    /// Inserts `text` at the visible position `pos` without updating the
+    /// index.
+    ///
+    /// Character IDs don't change when items are split, so an index that is out
+    /// of date still maps positions before every edit made since it was built
+    /// to the right characters. That allows several edits to share one
+    /// [`reindex`](Self::reindex), as long as they go from right to left.
+    pub(crate) fn insert_unindexed(&mut self, pos: usize, text: &str) -> ID {
+        let (left_id, right_id, offset) = self.find_pos(pos);
+
+        // Handle splitting the right item if insertion is inside it. A later
+        // edit of the same batch may already have split it there.
+        let left_id = match right_id {
+            Some(rid) if offset > 0 => {
+                if offset < self.items[&rid].len() {
+                    self.split_item(rid, offset);
+                }
+                Some(rid)
+            }
+            _ => self.placement(left_id, right_id),
+        };
+
+        let new_id = self.next_id(text);
+        let new_item = Item {
+            id: new_id,
+            origin_left: left_id.map(|lid| self.items[&lid].last_id()),
+            origin_right: match left_id {
+                Some(lid) => self.items[&lid].right,
+                None => self.head,
+            },
+            left: None,
+            right: None,
+            content: text.to_string(),
             is_deleted: false,
         };
 
-        // Update the original item (now the right part)
-        let item_mut = self.items.get_mut(&item_id).unwrap();
-        item_mut.content = right_content;
-        item_mut.left = Some(left_split_id);
+        self.attach(new_item, left_id);
+        new_id
+    }
 
-        // Insert the left split
-        self.items.insert(left_split_id, left_split);
+    /// Deletes `len` visible characters from `pos` without updating the index,
+    /// see [`insert_unindexed`](Self::insert_unindexed). Returns `false` if
+    /// there was nothing to delete.
+    pub(crate) fn delete_unindexed(&mut self, pos: usize, len: usize) -> bool {
+        let end = pos.saturating_add(len).min(self.len());
+        if pos >= end {
+            return false;
+        }
 
-        // Update the previous item's right pointer or head
-        if let Some(prev_id) = item_left {
-            self.items.get_mut(&prev_id).unwrap().right = Some(left_split_id);
+        let mut deleted = DeleteSet::new();
+        for entry in &self.index.entries[self.index.find(pos)..] {
+            if entry.start >= end {
+                break;
+            }
+            let from = pos.saturating_sub(entry.start);
+            let to = (end - entry.start).min(entry.len);
+            let id = ID {
+                client: entry.id.client,
+                clock: entry.id.clock + from as u64,
+            };
+            deleted.insert(id, (to - from) as u64);
+        }
+
+        self.apply_deletes(&deleted);
+        self.delete_set.merge(&deleted);
+        true
+    }
+
+    /// Returns the part of every item past what `remote` has seen, in clock
+    /// order.
+    pub(crate) fn unseen_items(&self, remote: &StateVector) -> Vec<Item> {
+        let mut items: Vec<Item> = self
+            .starts
+            .clients()
+            .flat_map(|client| {
+                let seen = remote.get(&client).map_or(0, |clock| clock + 1);
+                self.starts.from(client, seen)
+            })
+            .filter_map(|id| unseen(&self.items[&id], remote))
+            .collect();
+        items.sort_by_key(|item| item.id);
+        items
+    }
+
+    /// Places `item` directly after `left` (or at the head) and fixes up the
+    /// neighbouring pointers.
+    fn attach(&mut self, mut item: Item, left: Option<ID>) {
+        let right = match left {
+            Some(lid) => self.items[&lid].right,
+            None => self.head,
+        };
+        item.left = left;
+        item.right = right;
+        let owner = self.owner_after(left);
+        if self.resolver.integration() == Integration::Rga {
+            let time = item_timestamp(&item, &self.view(), &mut HashMap::new());
+            self.timestamps.insert(item.id, time);
+        }
+
+        let id = item.id;
+        self.items.insert(id, item);
+        self.starts.insert(id);
+
+        if let Some(lid) = left {
+            self.items.get_mut(&lid).unwrap().right = Some(id);
         } else {
-            self.head = Some(left_split_id);
+            self.head = Some(id);
         }
 
-        left_split_id
+        if let Some(rid) = right {
+            self.items.get_mut(&rid).unwrap().left = Some(id);
+        }
+        self.lay_out(id, owner);
     }
 
-    fn try_link(&mut self, item: Item) {}
-    fn link(&mut self, item: Item) {}
-    fn resolve_pending(&mut self) {}
+    /// Integrates a remote item once everything it depends on has arrived.
+    ///
+    /// Items are integrated in clock order per client, and only after both of
+    /// their origins are known. Anything else is parked in `pending` and retried
+    /// by [`resolve_pending`](Self::resolve_pending). Items whose clock range
+    /// overflows are dropped.
+    fn try_link(&mut self, mut item: Item) {
+        let next = self.next_clock(item.id.client);
+        let Some(end) = item.end() else {
+            return;
+        };
+
+        // Already integrated
+        if end <= next {
+            return;
+        }
+
+        if item.id.clock > next {
+            self.pending.push(item);
+            return;
+        }
+
+        // Keep only the part we haven't seen yet
+        if item.id.clock < next {
+            item = item.split_off((next - item.id.clock) as usize);
+        }
+
+        let has_origins = item.origin_left.is_none_or(|id| self.contains_id(&id))
+            && item.origin_right.is_none_or(|id| self.contains_id(&id));
+        if !has_origins {
+            self.pending.push(item);
+            return;
+        }
+
+        self.link(item, end);
+    }
+
+    /// Inserts a remote item between its origins, using the resolver to order it
+    /// against concurrent insertions at the same place. `end` is the clock
+    /// value right after it.
+    fn link(&mut self, item: Item, end: u64) {
+        let left = item.origin_left.map(|id| self.clean_end(id));
+        let right = item.origin_right.map(|id| self.clean_start(id));
+        let left = self.resolver.place(&item, left, right, &self.view());
+
+        let id = item.id;
+        self.attach(
+            Item {
+                is_deleted: false,
+                ..item
+            },
+            left,
+        );
+
+        self.state_vector.insert(id.client, end - 1);
+        if id.client == self.client_id {
+            self.clock = self.clock.max(end);
+        }
+    }
+
+    /// Retries pending items until no more of them can be integrated.
+    fn resolve_pending(&mut self) {
+        loop {
+            let pending = std::mem::take(&mut self.pending);
+            let before = pending.len();
+
+            for item in pending {
+                self.try_link(item);
+            }
+
+            if self.pending.len() >= before {
+                break;
+            }
+        }
+    }
+
+    /// Marks every integrated character covered by `delete_set` as deleted,
+    /// splitting items at the range boundaries.
+    fn apply_deletes(&mut self, delete_set: &DeleteSet) {
+        for (client, start, len) in delete_set.iter() {
+            let end = start.saturating_add(len).min(self.next_clock(client));
+            let mut clock = start;
+
+            while clock < end {
+                let id = ID { client, clock };
+                let start_id = self.find_item(id).expect("item should exist");
+                let item = &self.items[&start_id];
+                if item.is_deleted {
+                    clock = start_id.clock.saturating_add(item.len() as u64);
+                    continue;
+                }
+
+                let id = self.clean_start(id);
+                if id.clock.saturating_add(self.items[&id].len() as u64) > end {
+                    self.split_item(id, (end - id.clock) as usize);
+                }
+
+                let item = self.items.get_mut(&id).unwrap();
+                item.is_deleted = true;
+                clock = id.clock.saturating_add(item.len() as u64);
+            }
+        }
+    }
 }
 
 pub struct DocIterator<'a, R: ConflictResolver> {
     doc: &'a Doc<R>,
-    current: Option<ID>,
+    order: std::slice::Iter<'a, ID>,
+    /// Next item of the list, followed when there is no separate order.
+    next: Option<ID>,
 }
 
 impl<'a, R: ConflictResolver> IntoIterator for &'a Doc<R> {
@@ -162,7 +447,12 @@ impl<'a, R: ConflictResolver> IntoIterator for &'a Doc<R> {
     fn into_iter(self) -> Self::IntoIter {
         DocIterator {
             doc: self,
-            current: self.head,
+            order: self.order.iter(),
+            next: if self.moves.is_empty() {
+                self.head
+            } else {
+                None
+            },
         }
     }
 }
@@ -171,9 +461,16 @@ impl<'a, R: ConflictResolver> Iterator for DocIterator<'a, R> {
     type Item = &'a Item;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(id) = self.current {
+        for id in self.order.by_ref() {
+            let item = self.doc.items.get(id).unwrap();
+
+            if !item.is_deleted {
+                return Some(item);
+            }
+        }
+        while let Some(id) = self.next {
             let item = self.doc.items.get(&id).unwrap();
-            self.current = item.right;
+            self.next = item.right;
 
             if !item.is_deleted {
                 return Some(item);
@@ -184,12 +481,80 @@ impl<'a, R: ConflictResolver> Iterator for DocIterator<'a, R> {
 }
 
 impl<R: ConflictResolver> Crdt for Doc<R> {
-    type Update = Vec<Item>;
+    type Update = Update;
+
+    /// Integrates a remote update.
+    ///
+    /// Items whose dependencies are missing wait in `pending` until a later
+    /// update delivers them. Deletions are remembered in the delete set, so
+    /// they also take effect on items that arrive afterwards.
+    fn apply(&mut self, update: Self::Update) {
+        let known_moves = self.known_moves();
+        let mut items = update.items;
+        items.sort_by_key(|item| item.id);
+
+        for item in items {
+            self.try_link(item);
+        }
+        self.resolve_pending();
+
+        self.delete_set.merge(&update.delete_set);
+        let delete_set = std::mem::take(&mut self.delete_set);
+        self.apply_deletes(&delete_set);
+        self.delete_set = delete_set;
+
+        for mark in update.marks {
+            if !self.marks.iter().any(|m| m.id == mark.id) {
+                self.marks.push(mark);
+            }
+        }
+        let moves = self.moves.len();
+        for mv in update.moves {
+            if !self.moves.iter().any(|m| m.id == mv.id) {
+                self.moves.push(mv);
+            }
+        }
+        if self.moves.len() != moves || self.known_moves() != known_moves {
+            self.moves_changed();
+        }
+        for subdoc in update.subdocs {
+            self.subdocs.entry(subdoc.id).or_insert(subdoc.guid);
+        }
+        for (name, counter) in &update.counters {
+            self.counters
+                .entry(name.clone())
+                .or_default()
+                .merge(counter);
+        }
+        for (name, register) in &update.registers {
+            self.registers
+                .entry(name.clone())
+                .or_default()
+                .merge(register);
+        }
 
-    fn apply(&mut self, update: Self::Update) {}
+        self.reindex();
+    }
+
+    /// Returns everything `remote` hasn't seen: the unseen part of each item, the
+    /// whole delete set, all formatting marks, all moves, every counter and
+    /// register, and all sub-document references.
+    ///
+    /// A state vector only covers items, so everything else is sent in full.
+    /// That's what a peer catching up needs; to pass on the changes of a single
+    /// edit, use a [`ChangeTracker`](crate::ChangeTracker) instead.
     fn diff(&self, remote: &StateVector) -> Self::Update {
-        Vec::<Item>::new()
+        Update {
+            items: self.unseen_items(remote),
+            delete_set: self.delete_set.clone(),
+            marks: self.marks.clone(),
+            moves: self.moves.clone(),
+            counters: self.counters.clone(),
+            registers: self.registers.clone(),
+            subdocs: self.subdoc_references().collect(),
+        }
     }
+
     fn state_vector(&self) -> StateVector {
         self.state_vector.clone()
     }
@@ -200,100 +565,24 @@ impl<R: ConflictResolver> SequenceCrdt for Doc<R> {
         if text.is_empty() {
             return;
         }
-        let (mut left_id, right_id, offset) = self.find_pos(pos);
-
-        // Handle splitting the right item if insertion is inside it
-        if let Some(rid) = right_id {
-            if offset > 0 {
-                left_id = Some(self.split_item(rid, offset));
-            }
-        }
-
-        let new_id = self.next_id(text);
-        let new_item = Item {
-            id: new_id,
-            left: left_id,
-            right: right_id,
-            content: text.to_string(),
-            is_deleted: false,
-        };
-
-        self.items.insert(new_id, new_item);
-
-        // Update links
-        if let Some(lid) = left_id {
-            self.items.get_mut(&lid).unwrap().right = Some(new_id);
-        } else {
-            self.head = Some(new_id);
-        }
-
-        if let Some(rid) = right_id {
-            self.items.get_mut(&rid).unwrap().left = Some(new_id);
-        }
+        let id = self.insert_unindexed(pos, text);
+        self.reindex_insert(pos, id);
     }
 
     /// Deletes a range of characters starting at `pos` with length `len`.
     ///
-    /// Items within the deletion range are marked as deleted. If deletion starts or
-    /// ends mid-item, the item is split first. Deleted items remain in the structure
-    /// but are skipped during iteration.
+    /// The visible characters in the range are recorded in the delete set and
+    /// marked as deleted, splitting items where the range starts or ends
+    /// mid-item. Deleted items remain in the structure but are skipped during
+    /// iteration.
     ///
     /// # Arguments
     ///
     /// * `pos` - Starting character position (0-indexed)
     /// * `len` - Number of characters to delete
     fn delete(&mut self, pos: usize, len: usize) {
-        if len == 0 {
-            return;
-        }
-
-        let (_, start_item_id, start_offset) = self.find_pos(pos);
-        let Some(mut current_id) = start_item_id else {
-            return;
-        };
-
-        let mut remaining = len;
-
-        // If deletion starts in the middle of an item, split it first
-        if start_offset > 0 {
-            self.split_item(current_id, start_offset);
-        }
-
-        // Delete items moving rightward until length is covered
-        while remaining > 0 {
-            let Some(item) = self.items.get(&current_id) else {
-                break;
-            };
-
-            if item.is_deleted {
-                let Some(next) = item.right else { break };
-                current_id = next;
-                continue;
-            }
-
-            let item_len = item.content.chars().count();
-            let next = item.right;
-
-            if remaining < item_len {
-                // Partial deletion: split and mark left part deleted
-                let left_id = self.split_item(current_id, remaining);
-                self.items
-                    .get_mut(&left_id)
-                    .expect("split item should exist")
-                    .is_deleted = true;
-                break;
-            }
-
-            // Full deletion
-            self.items
-                .get_mut(&current_id)
-                .expect("item should exist")
-                .is_deleted = true;
-
-            remaining -= item_len;
-
-            let Some(next_id) = next else { break };
-            current_id = next_id;
+        if self.delete_unindexed(pos, len) {
+            self.reindex_delete(pos, pos.saturating_add(len).min(self.index.len()));
         }
     }
 
@@ -305,6 +594,8 @@ impl<R: ConflictResolver> SequenceCrdt for Doc<R> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::BinaryEncode;
+    use crate::test_util::sync;
 
     // Helper function to create test IDs
     fn id(client: u64, clock: u64) -> ID {
@@ -601,21 +892,26 @@ mod tests {
     fn insert_in_middle_splits_item() {
         let mut doc = Doc::new(1);
         doc.insert(0, "hllo"); // Creates ID (1, 0) with length 4, clock advances to 4
-        doc.insert(1, "e"); // Split creates ID (1, 4) for "h", then (1, 5) for "e"
+        doc.insert(1, "e"); // Split creates (1, 1) for "llo", then (1, 4) for "e"
+
+        // Splits used to take fresh clock values, giving "h" the ID (1, 4).
+        // Replicas split items at different times, so they ended up with
+        // different IDs for the same characters and couldn't sync; see
+        // `replicas_agree_on_split_ids`.
 
         assert_eq!(doc.value(), "hello");
         assert_eq!(doc.items.len(), 3);
 
-        // Left split "h" has NEW ID starting at clock 4
-        let left_split = doc.items.get(&id(1, 4)).unwrap();
+        // Left split "h" keeps the ORIGINAL ID starting at clock 0
+        let left_split = doc.items.get(&id(1, 0)).unwrap();
         assert_eq!(left_split.content, "h");
 
-        // Inserted "e" has ID starting at clock 5
-        let inserted = doc.items.get(&id(1, 5)).unwrap();
+        // Inserted "e" has ID starting at clock 4
+        let inserted = doc.items.get(&id(1, 4)).unwrap();
         assert_eq!(inserted.content, "e");
 
-        // Right split "llo" keeps ORIGINAL ID starting at clock 0
-        let right_split = doc.items.get(&id(1, 0)).unwrap();
+        // Right split "llo" is identified by the clock of its first character
+        let right_split = doc.items.get(&id(1, 1)).unwrap();
         assert_eq!(right_split.content, "llo");
     }
 
@@ -716,15 +1012,35 @@ mod tests {
         doc.insert(0, "hello"); // clock: 0 -> 5
         assert_eq!(doc.clock, 5);
 
-        doc.insert(2, "X"); // Splits "hello", clock: 5 -> 6 (for "X"), but also 6 -> 8 (for "he" split)
-        // Actually, let me recalculate...
-        // Split creates "he" (2 chars) at clock 5, clock becomes 7
-        // Then insert "X" (1 char) at clock 7, clock becomes 8
-        // Original "llo" keeps its ID
+        doc.insert(2, "X"); // Splits "hello" into (1, 0) "he" and (1, 2) "llo"
+        // Splitting doesn't consume clock values, only the inserted "X" does
+        assert_eq!(doc.clock, 6);
 
         assert_eq!(doc.value(), "heXllo");
     }
 
+    #[test]
+    fn replicas_agree_on_split_ids() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let mut b = Doc::new(2);
+        b.apply(a.diff(&b.state_vector()));
+
+        // Each replica splits "hello" for a different reason
+        a.insert(2, "X");
+        b.delete(2, 1);
+
+        // Both name the right part after its first character, so an origin or
+        // delete pointing at it means the same thing on both sides
+        assert!(a.items.contains_key(&id(1, 2)));
+        assert!(b.items.contains_key(&id(1, 2)));
+        assert_eq!(a.clock, 6);
+
+        sync(&mut a, &mut b);
+        assert_eq!(a.value(), "heXlo");
+        assert_eq!(a.value(), b.value());
+    }
+
     #[test]
     fn insert_between_two_items() {
         let mut doc = Doc::new(1);
@@ -1018,4 +1334,246 @@ mod tests {
 
         assert_eq!(doc.value(), "");
     }
+
+    #[test]
+    fn apply_diff_to_empty_doc() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        a.insert(5, " world");
+
+        let mut b = Doc::new(2);
+        b.apply(a.diff(&b.state_vector()));
+
+        assert_eq!(b.value(), "hello world");
+        assert_eq!(b.state_vector(), a.state_vector());
+    }
+
+    #[test]
+    fn diff_only_contains_unseen_items() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        a.insert(5, "!");
+        let update = a.diff(&b.state_vector());
+
+        assert_eq!(update.items.len(), 1);
+        assert_eq!(update.items[0].content, "!");
+    }
+
+    #[test]
+    fn diff_slices_partially_seen_items() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+
+        let remote = StateVector::from([(1, 1)]);
+        let update = a.diff(&remote);
+
+        assert_eq!(update.items.len(), 1);
+        assert_eq!(update.items[0].id, id(1, 2));
+        assert_eq!(update.items[0].content, "llo");
+        assert_eq!(update.items[0].origin_left, Some(id(1, 1)));
+    }
+
+    #[test]
+    fn apply_is_idempotent() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let update = a.diff(&StateVector::new());
+
+        let mut b = Doc::new(2);
+        b.apply(update.clone());
+        b.apply(update);
+
+        assert_eq!(b.value(), "hello");
+    }
+
+    #[test]
+    fn remote_insert_inside_item_splits_it() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        b.insert(2, "XY");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), "heXYllo");
+        assert_eq!(b.value(), "heXYllo");
+    }
+
+    #[test]
+    fn concurrent_inserts_at_same_position_converge() {
+        let mut a = Doc::new(1);
+        a.insert(0, "ac");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        a.insert(1, "X");
+        b.insert(1, "Y");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), "aXYc");
+    }
+
+    #[test]
+    fn concurrent_runs_are_not_interleaved() {
+        let mut a = Doc::new(1);
+        let mut b = Doc::new(2);
+
+        for (i, ch) in ["h", "e", "y"].iter().enumerate() {
+            a.insert(i, ch);
+        }
+        for (i, ch) in ["y", "o"].iter().enumerate() {
+            b.insert(i, ch);
+        }
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), "heyyo");
+    }
+
+    #[test]
+    fn concurrent_deletes_and_inserts_converge() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello world");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        a.delete(0, 6);
+        b.insert(5, ",");
+        b.delete(7, 3);
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), ",ld");
+    }
+
+    #[test]
+    fn out_of_order_updates_wait_in_pending() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let first = a.diff(&StateVector::new());
+        let sv = a.state_vector();
+        a.insert(5, " world");
+        let second = a.diff(&sv);
+
+        let mut b = Doc::new(2);
+        b.apply(second);
+        assert_eq!(b.value(), "");
+        assert_eq!(b.pending.len(), 1);
+
+        b.apply(first);
+        assert_eq!(b.value(), "hello world");
+        assert!(b.pending.is_empty());
+    }
+
+    #[test]
+    fn deletes_apply_to_items_that_arrive_later() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        let mut c = Doc::new(3);
+        b.delete(0, 2);
+
+        // c learns about the delete before the deleted item
+        let mut deletes_only = b.diff(&b.state_vector());
+        deletes_only.items.clear();
+        c.apply(deletes_only);
+        c.apply(a.diff(&c.state_vector()));
+
+        assert_eq!(c.value(), "llo");
+    }
+
+    #[test]
+    fn huge_delete_range_does_not_overflow() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        // A peer can claim any range length; the end must saturate, not wrap
+        let mut update = Update::default();
+        update.delete_set.insert(id(1, 2), u64::MAX);
+        let update = Update::decode(&update.encode()).unwrap();
+        b.apply(update);
+
+        assert_eq!(b.value(), "he");
+    }
+
+    #[test]
+    fn random_concurrent_edits_converge() {
+        let mut seed: u64 = 42;
+        let mut rand = move |max: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((seed >> 33) as usize) % max.max(1)
+        };
+
+        let mut docs = [Doc::new(1), Doc::new(2), Doc::new(3)];
+        for _ in 0..50 {
+            for doc in docs.iter_mut() {
+                for _ in 0..3 {
+                    let len = doc.value().chars().count();
+                    if len > 0 && rand(3) == 0 {
+                        let pos = rand(len);
+                        doc.delete(pos, 1 + rand(3));
+                    } else {
+                        let text = ["a", "bc", "def", "🦀"][rand(4)];
+                        doc.insert(rand(len + 1), text);
+                    }
+                }
+            }
+
+            let (i, j) = match rand(3) {
+                0 => (0, 1),
+                1 => (1, 2),
+                _ => (0, 2),
+            };
+            let [a, b, c] = &mut docs;
+            match (i, j) {
+                (0, 1) => sync(a, b),
+                (1, 2) => sync(b, c),
+                _ => sync(a, c),
+            }
+        }
+
+        let [a, b, c] = &mut docs;
+        sync(a, b);
+        sync(b, c);
+        sync(a, b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(b.value(), c.value());
+    }
+
+    #[test]
+    fn items_overflowing_the_clock_are_dropped() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        let crafted = Item {
+            id: id(2, u64::MAX),
+            origin_left: None,
+            origin_right: None,
+            left: None,
+            right: None,
+            content: "XY".into(),
+            is_deleted: false,
+        };
+        let update = Update {
+            items: vec![crafted],
+            ..Default::default()
+        };
+
+        assert_eq!(Update::decode(&update.encode()), None);
+        doc.apply(update);
+        assert_eq!(doc.value(), "hello");
+        assert!(doc.pending.is_empty());
+        assert_eq!(doc.state_vector(), StateVector::from([(1, 4)]));
+    }
 }
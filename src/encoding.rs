@@ -0,0 +1,481 @@
+use crate::traits::BinaryEncode;
+use crate::{
+    Anchor, Counter, DeleteSet, ID, Item, Mark, Move, Register, StateVector, Subdoc, Update,
+};
+use std::collections::BTreeMap;
+
+/// Appends variable-length encoded values to a buffer.
+#[derive(Debug, Default)]
+pub(crate) struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Writes an unsigned LEB128 varint.
+    pub fn u64(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.buf.push(byte);
+                return;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+
+    pub fn id(&mut self, id: &ID) {
+        self.u64(id.client);
+        self.u64(id.clock);
+    }
+
+    pub fn option<T>(&mut self, value: Option<&T>, write: impl FnOnce(&mut Self, &T)) {
+        match value {
+            Some(value) => {
+                self.u8(1);
+                write(self, value);
+            }
+            None => self.u8(0),
+        }
+    }
+}
+
+/// Reads values written by [`Encoder`]. Every read returns `None` once the
+/// input is exhausted or malformed.
+#[derive(Debug)]
+pub(crate) struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.buf.split_first()?;
+        self.buf = rest;
+        Some(byte)
+    }
+
+    pub fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = usize::try_from(self.u64()?).ok()?;
+        if len > self.buf.len() {
+            return None;
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(bytes)
+    }
+
+    pub fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_vec()).ok()
+    }
+
+    pub fn id(&mut self) -> Option<ID> {
+        Some(ID {
+            client: self.u64()?,
+            clock: self.u64()?,
+        })
+    }
+
+    pub fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> Option<T>) -> Option<Option<T>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => read(self).map(Some),
+            _ => None,
+        }
+    }
+
+    /// Reads a length-prefixed sequence.
+    pub fn list<T>(&mut self, mut read: impl FnMut(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        let len = self.u64()?;
+        // Don't trust the length for preallocation, the input may be corrupt
+        let mut values = Vec::new();
+        for _ in 0..len {
+            values.push(read(self)?);
+        }
+        Some(values)
+    }
+}
+
+pub(crate) fn write_item(encoder: &mut Encoder, item: &Item) {
+    encoder.id(&item.id);
+    encoder.option(item.origin_left.as_ref(), Encoder::id);
+    encoder.option(item.origin_right.as_ref(), Encoder::id);
+    encoder.string(&item.content);
+}
+
+pub(crate) fn read_item(decoder: &mut Decoder) -> Option<Item> {
+    let item = Item {
+        id: decoder.id()?,
+        origin_left: decoder.option(Decoder::id)?,
+        origin_right: decoder.option(Decoder::id)?,
+        left: None,
+        right: None,
+        content: decoder.string()?,
+        is_deleted: false,
+    };
+    // Items always cover at least one clock value, and no more than there are
+    (!item.content.is_empty() && item.end().is_some()).then_some(item)
+}
+
+pub(crate) fn write_delete_set(encoder: &mut Encoder, delete_set: &DeleteSet) {
+    let mut ranges: Vec<_> = delete_set.iter().collect();
+    ranges.sort_unstable();
+    encoder.u64(ranges.len() as u64);
+    for (client, clock, len) in ranges {
+        encoder.u64(client);
+        encoder.u64(clock);
+        encoder.u64(len);
+    }
+}
+
+pub(crate) fn read_delete_set(decoder: &mut Decoder) -> Option<DeleteSet> {
+    let mut delete_set = DeleteSet::new();
+    for (id, len) in decoder.list(|d| Some((d.id()?, d.u64()?)))? {
+        delete_set.insert(id, len);
+    }
+    Some(delete_set)
+}
+
+fn write_anchor(encoder: &mut Encoder, anchor: &Anchor) {
+    match anchor {
+        Anchor::Before(id) => {
+            encoder.u8(0);
+            encoder.id(id);
+        }
+        Anchor::After(id) => {
+            encoder.u8(1);
+            encoder.id(id);
+        }
+        Anchor::End => encoder.u8(2),
+    }
+}
+
+fn read_anchor(decoder: &mut Decoder) -> Option<Anchor> {
+    match decoder.u8()? {
+        0 => Some(Anchor::Before(decoder.id()?)),
+        1 => Some(Anchor::After(decoder.id()?)),
+        2 => Some(Anchor::End),
+        _ => None,
+    }
+}
+
+pub(crate) fn write_mark(encoder: &mut Encoder, mark: &Mark) {
+    encoder.id(&mark.id);
+    write_anchor(encoder, &mark.start);
+    write_anchor(encoder, &mark.end);
+    encoder.string(&mark.key);
+    encoder.option(mark.value.as_ref(), |e, value| e.string(value));
+}
+
+pub(crate) fn read_mark(decoder: &mut Decoder) -> Option<Mark> {
+    Some(Mark {
+        id: decoder.id()?,
+        start: read_anchor(decoder)?,
+        end: read_anchor(decoder)?,
+        key: decoder.string()?,
+        value: decoder.option(Decoder::string)?,
+    })
+}
+
+pub(crate) fn write_move(encoder: &mut Encoder, mv: &Move) {
+    encoder.id(&mv.id);
+    encoder.option(mv.after.as_ref(), Encoder::id);
+    encoder.id(&mv.last);
+    encoder.id(&mv.target);
+}
+
+pub(crate) fn read_move(decoder: &mut Decoder) -> Option<Move> {
+    Some(Move {
+        id: decoder.id()?,
+        after: decoder.option(Decoder::id)?,
+        last: decoder.id()?,
+        target: decoder.id()?,
+    })
+}
+
+pub(crate) fn write_subdoc(encoder: &mut Encoder, subdoc: &Subdoc) {
+    encoder.id(&subdoc.id);
+    encoder.string(&subdoc.guid);
+}
+
+pub(crate) fn read_subdoc(decoder: &mut Decoder) -> Option<Subdoc> {
+    Some(Subdoc {
+        id: decoder.id()?,
+        guid: decoder.string()?,
+    })
+}
+
+pub(crate) fn write_counters(encoder: &mut Encoder, counters: &BTreeMap<String, Counter>) {
+    encoder.u64(counters.len() as u64);
+    for (name, counter) in counters {
+        encoder.string(name);
+        write_state_vector(encoder, &counter.increments);
+        write_state_vector(encoder, &counter.decrements);
+    }
+}
+
+pub(crate) fn read_counters(decoder: &mut Decoder) -> Option<BTreeMap<String, Counter>> {
+    let counters = decoder.list(|d| {
+        let name = d.string()?;
+        let counter = Counter {
+            increments: read_state_vector(d)?,
+            decrements: read_state_vector(d)?,
+        };
+        Some((name, counter))
+    })?;
+    Some(counters.into_iter().collect())
+}
+
+pub(crate) fn write_registers(encoder: &mut Encoder, registers: &BTreeMap<String, Register>) {
+    encoder.u64(registers.len() as u64);
+    for (name, register) in registers {
+        encoder.string(name);
+        encoder.u64(register.values.len() as u64);
+        for (id, value) in &register.values {
+            encoder.id(id);
+            encoder.string(value);
+        }
+        write_state_vector(encoder, &register.seen);
+    }
+}
+
+pub(crate) fn read_registers(decoder: &mut Decoder) -> Option<BTreeMap<String, Register>> {
+    let registers = decoder.list(|d| {
+        let name = d.string()?;
+        let register = Register {
+            values: d.list(|d| Some((d.id()?, d.string()?)))?,
+            seen: read_state_vector(d)?,
+        };
+        Some((name, register))
+    })?;
+    Some(registers.into_iter().collect())
+}
+
+pub(crate) fn write_update(encoder: &mut Encoder, update: &Update) {
+    encoder.u64(update.items.len() as u64);
+    for item in &update.items {
+        write_item(encoder, item);
+    }
+    write_delete_set(encoder, &update.delete_set);
+    encoder.u64(update.marks.len() as u64);
+    for mark in &update.marks {
+        write_mark(encoder, mark);
+    }
+    encoder.u64(update.moves.len() as u64);
+    for mv in &update.moves {
+        write_move(encoder, mv);
+    }
+    write_counters(encoder, &update.counters);
+    write_registers(encoder, &update.registers);
+    encoder.u64(update.subdocs.len() as u64);
+    for subdoc in &update.subdocs {
+        write_subdoc(encoder, subdoc);
+    }
+}
+
+pub(crate) fn read_update(decoder: &mut Decoder) -> Option<Update> {
+    Some(Update {
+        items: decoder.list(read_item)?,
+        delete_set: read_delete_set(decoder)?,
+        marks: decoder.list(read_mark)?,
+        moves: decoder.list(read_move)?,
+        counters: read_counters(decoder)?,
+        registers: read_registers(decoder)?,
+        subdocs: decoder.list(read_subdoc)?,
+    })
+}
+
+pub(crate) fn write_state_vector(encoder: &mut Encoder, state_vector: &StateVector) {
+    let mut clients: Vec<_> = state_vector.iter().collect();
+    clients.sort_unstable();
+    encoder.u64(clients.len() as u64);
+    for (client, clock) in clients {
+        encoder.u64(*client);
+        encoder.u64(*clock);
+    }
+}
+
+pub(crate) fn read_state_vector(decoder: &mut Decoder) -> Option<StateVector> {
+    let clients = decoder.list(|d| Some((d.u64()?, d.u64()?)))?;
+    Some(clients.into_iter().collect())
+}
+
+impl BinaryEncode for Update {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        write_update(&mut encoder, self);
+        encoder.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(bytes);
+        let update = read_update(&mut decoder)?;
+        decoder.is_empty().then_some(update)
+    }
+}
+
+impl BinaryEncode for StateVector {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        write_state_vector(&mut encoder, self);
+        encoder.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(bytes);
+        let state_vector = read_state_vector(&mut decoder)?;
+        decoder.is_empty().then_some(state_vector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Attributes, Crdt, Doc, SequenceCrdt};
+
+    fn sample_update() -> Update {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello 🦀 world");
+        doc.delete(2, 3);
+        doc.insert(1, "X");
+        let mut attributes = Attributes::new();
+        attributes.insert("bold".into(), Some("true".into()));
+        attributes.insert("link".into(), None);
+        doc.format(0, 4, &attributes);
+        doc.diff(&StateVector::new())
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        let values = [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX];
+
+        let mut encoder = Encoder::new();
+        for value in values {
+            encoder.u64(value);
+        }
+        let bytes = encoder.into_bytes();
+
+        let mut decoder = Decoder::new(&bytes);
+        for value in values {
+            assert_eq!(decoder.u64(), Some(value));
+        }
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn update_round_trips() {
+        let update = sample_update();
+        let decoded = Update::decode(&update.encode()).unwrap();
+
+        assert_eq!(decoded.delete_set, update.delete_set);
+        assert_eq!(decoded.marks, update.marks);
+        assert_eq!(decoded.items.len(), update.items.len());
+        for (decoded, item) in decoded.items.iter().zip(&update.items) {
+            assert_eq!(decoded.id, item.id);
+            assert_eq!(decoded.origin_left, item.origin_left);
+            assert_eq!(decoded.origin_right, item.origin_right);
+            assert_eq!(decoded.content, item.content);
+        }
+    }
+
+    #[test]
+    fn decoded_update_applies() {
+        let update = sample_update();
+
+        let mut doc = Doc::new(2);
+        doc.apply(Update::decode(&update.encode()).unwrap());
+
+        assert_eq!(doc.value(), "hXe 🦀 world");
+    }
+
+    #[test]
+    fn moves_round_trip() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.move_range(6, 5, 0);
+        let update = doc.diff(&StateVector::new());
+        let decoded = Update::decode(&update.encode()).unwrap();
+        assert_eq!(decoded.moves, update.moves);
+
+        let mut other = Doc::new(2);
+        other.apply(decoded);
+        assert_eq!(other.value(), "worldhello ");
+    }
+
+    #[test]
+    fn counters_and_registers_round_trip() {
+        let mut doc = Doc::new(1);
+        doc.increment("votes", 3);
+        doc.increment("votes", -1);
+        doc.set_register("title", "Draft");
+        let update = doc.diff(&StateVector::new());
+        let decoded = Update::decode(&update.encode()).unwrap();
+
+        assert_eq!(decoded.counters, update.counters);
+        assert_eq!(decoded.registers, update.registers);
+    }
+
+    #[test]
+    fn truncated_update_fails_to_decode() {
+        let bytes = sample_update().encode();
+
+        for len in 0..bytes.len() {
+            assert!(Update::decode(&bytes[..len]).is_none(), "len {len}");
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_fail_to_decode() {
+        let mut bytes = sample_update().encode();
+        bytes.push(0);
+
+        assert!(Update::decode(&bytes).is_none());
+    }
+
+    #[test]
+    fn state_vector_round_trips() {
+        let sv = StateVector::from([(1, 10), (42, 0), (7, 300)]);
+
+        assert_eq!(StateVector::decode(&sv.encode()), Some(sv));
+    }
+}
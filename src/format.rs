@@ -0,0 +1,362 @@
+use crate::{ConflictResolver, Doc, ID};
+use std::collections::{BTreeMap, HashMap};
+
+/// Formatting attributes keyed by name, e.g. `bold` or `link`. A `None` value
+/// clears the attribute.
+pub type Attributes = BTreeMap<String, Option<String>>;
+
+/// A gap between two characters, attached to the character on one side of it so
+/// it keeps its place while text is inserted around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Anchor {
+    Before(ID),
+    After(ID),
+    End,
+}
+
+/// A formatting operation over the characters between `start` and `end`.
+///
+/// `id.clock` is a Lamport timestamp rather than a position in the client's
+/// clock range: when marks for the same key overlap, the one with the highest
+/// `(clock, client)` wins.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mark {
+    pub id: ID,
+    pub start: Anchor,
+    pub end: Anchor,
+    pub key: String,
+    pub value: Option<String>,
+}
+
+/// A stretch of visible text sharing the same attributes.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Run {
+    pub text: String,
+    pub attributes: Attributes,
+}
+
+/// Returns `true` if text typed at the end of a range formatted with `key`
+/// should pick up the formatting. Links don't grow; everything else does.
+pub(crate) fn expands(key: &str) -> bool {
+    key != "link"
+}
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Applies `attributes` to `len` characters starting at `pos`.
+    ///
+    /// Each attribute becomes a [`Mark`] anchored to characters rather than
+    /// positions, Peritext style. Marks never grow at their start. At their end
+    /// they grow unless the attribute is `link`, so text typed after a bold word
+    /// is bold while text typed after a link is not. Concurrent edits at the
+    /// boundaries follow the same rules once merged.
+    pub fn format(&mut self, pos: usize, len: usize, attributes: &Attributes) {
+        let len = len.min(self.len().saturating_sub(pos));
+        if len == 0 {
+            return;
+        }
+
+        let first = self.char_id(pos).expect("char should exist");
+        let last = self.char_id(pos + len - 1).expect("char should exist");
+        let next = self.char_id(pos + len);
+
+        let clock = self
+            .marks
+            .iter()
+            .map(|mark| mark.id.clock + 1)
+            .max()
+            .unwrap_or(0);
+
+        for (clock, (key, value)) in (clock..).zip(attributes) {
+            let end = if expands(key) {
+                next.map_or(Anchor::End, Anchor::Before)
+            } else {
+                Anchor::After(last)
+            };
+
+            self.marks.push(Mark {
+                id: ID {
+                    client: self.client_id,
+                    clock,
+                },
+                start: Anchor::Before(first),
+                end,
+                key: key.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+
+    /// Returns the visible text split into runs of identical formatting.
+    pub fn runs(&self) -> Vec<Run> {
+        let is_known = |anchor: &Anchor| match anchor {
+            Anchor::Before(id) | Anchor::After(id) => self.contains_id(id),
+            Anchor::End => true,
+        };
+
+        // Marks pointing at characters we haven't received yet are ignored
+        let mut opens: HashMap<Anchor, Vec<&Mark>> = HashMap::new();
+        let mut closes: HashMap<Anchor, Vec<ID>> = HashMap::new();
+        for mark in &self.marks {
+            if is_known(&mark.start) && is_known(&mark.end) {
+                opens.entry(mark.start).or_default().push(mark);
+                closes.entry(mark.end).or_default().push(mark.id);
+            }
+        }
+
+        let mut active: Vec<&Mark> = Vec::new();
+        let mut runs: Vec<Run> = Vec::new();
+        for id in self.in_order() {
+            let item = &self.items[&id];
+
+            for (offset, ch) in item.content.chars().enumerate() {
+                let char_id = ID {
+                    client: id.client,
+                    clock: id.clock + offset as u64,
+                };
+
+                toggle(Anchor::Before(char_id), &opens, &closes, &mut active);
+
+                if !item.is_deleted {
+                    let attributes = resolve(&active);
+                    match runs.last_mut() {
+                        Some(run) if run.attributes == attributes => run.text.push(ch),
+                        _ => runs.push(Run {
+                            text: ch.to_string(),
+                            attributes,
+                        }),
+                    }
+                }
+
+                toggle(Anchor::After(char_id), &opens, &closes, &mut active);
+            }
+        }
+
+        runs
+    }
+
+    /// Returns the ID of the visible character at `pos`.
+    pub(crate) fn char_id(&self, pos: usize) -> Option<ID> {
+        let (_, right, offset) = self.find_pos(pos);
+        right.map(|id| ID {
+            client: id.client,
+            clock: id.clock + offset as u64,
+        })
+    }
+}
+
+/// Opens and closes the marks attached to `anchor`.
+fn toggle<'a>(
+    anchor: Anchor,
+    opens: &HashMap<Anchor, Vec<&'a Mark>>,
+    closes: &HashMap<Anchor, Vec<ID>>,
+    active: &mut Vec<&'a Mark>,
+) {
+    if let Some(ids) = closes.get(&anchor) {
+        active.retain(|mark| !ids.contains(&mark.id));
+    }
+    if let Some(marks) = opens.get(&anchor) {
+        active.extend(marks);
+    }
+}
+
+/// Picks the winning value for each key among the active marks, dropping
+/// attributes that were cleared.
+fn resolve(active: &[&Mark]) -> Attributes {
+    let mut winners: HashMap<&str, &Mark> = HashMap::new();
+    for mark in active {
+        let winner = winners.entry(&mark.key).or_insert(mark);
+        if (mark.id.clock, mark.id.client) > (winner.id.clock, winner.id.client) {
+            *winner = mark;
+        }
+    }
+
+    winners
+        .into_values()
+        .filter(|mark| mark.value.is_some())
+        .map(|mark| (mark.key.clone(), mark.value.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SequenceCrdt;
+    use crate::test_util::sync;
+
+    fn attrs(pairs: &[(&str, Option<&str>)]) -> Attributes {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.map(str::to_string)))
+            .collect()
+    }
+
+    fn run(text: &str, pairs: &[(&str, Option<&str>)]) -> Run {
+        Run {
+            text: text.to_string(),
+            attributes: attrs(pairs),
+        }
+    }
+
+    #[test]
+    fn runs_of_unformatted_doc() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+
+        assert_eq!(doc.runs(), vec![run("hello", &[])]);
+    }
+
+    #[test]
+    fn format_splits_runs() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.format(0, 5, &attrs(&[("bold", Some("true"))]));
+
+        assert_eq!(
+            doc.runs(),
+            vec![run("hello", &[("bold", Some("true"))]), run(" world", &[])]
+        );
+    }
+
+    #[test]
+    fn format_expands_at_end_only() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "abc");
+        doc.format(1, 1, &attrs(&[("bold", Some("true"))]));
+
+        doc.insert(2, "X"); // Typed after the bold "b"
+        doc.insert(1, "Y"); // Typed before the bold "b"
+
+        assert_eq!(
+            doc.runs(),
+            vec![
+                run("aY", &[]),
+                run("bX", &[("bold", Some("true"))]),
+                run("c", &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn format_expands_at_end_of_doc() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "ab");
+        doc.format(0, 2, &attrs(&[("italic", Some("true"))]));
+        doc.insert(2, "c");
+
+        assert_eq!(doc.runs(), vec![run("abc", &[("italic", Some("true"))])]);
+    }
+
+    #[test]
+    fn link_does_not_expand() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "ab");
+        doc.format(0, 1, &attrs(&[("link", Some("https://example.com"))]));
+        doc.insert(1, "X");
+
+        assert_eq!(
+            doc.runs(),
+            vec![
+                run("a", &[("link", Some("https://example.com"))]),
+                run("Xb", &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn none_clears_attribute() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        doc.format(0, 5, &attrs(&[("bold", Some("true"))]));
+        doc.format(1, 3, &attrs(&[("bold", None)]));
+
+        assert_eq!(
+            doc.runs(),
+            vec![
+                run("h", &[("bold", Some("true"))]),
+                run("ell", &[]),
+                run("o", &[("bold", Some("true"))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn format_is_clamped_to_content() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hi");
+        doc.format(1, 10, &attrs(&[("bold", Some("true"))]));
+        doc.format(5, 1, &attrs(&[("italic", Some("true"))]));
+
+        assert_eq!(
+            doc.runs(),
+            vec![run("h", &[]), run("i", &[("bold", Some("true"))])]
+        );
+    }
+
+    #[test]
+    fn formatting_survives_deletes_inside_range() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        doc.format(0, 5, &attrs(&[("bold", Some("true"))]));
+        doc.delete(1, 3);
+
+        assert_eq!(doc.runs(), vec![run("ho", &[("bold", Some("true"))])]);
+    }
+
+    #[test]
+    fn concurrent_formats_converge() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello world");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        a.format(0, 7, &attrs(&[("bold", Some("true"))]));
+        b.format(4, 7, &attrs(&[("italic", Some("true"))]));
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.runs(), b.runs());
+        assert_eq!(
+            a.runs(),
+            vec![
+                run("hell", &[("bold", Some("true"))]),
+                run("o w", &[("bold", Some("true")), ("italic", Some("true"))]),
+                run("orld", &[("italic", Some("true"))]),
+            ]
+        );
+    }
+
+    #[test]
+    fn concurrent_values_for_same_key_converge() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        a.format(0, 5, &attrs(&[("color", Some("red"))]));
+        b.format(0, 5, &attrs(&[("color", Some("blue"))]));
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.runs(), b.runs());
+        assert_eq!(a.runs(), vec![run("hello", &[("color", Some("blue"))])]);
+    }
+
+    #[test]
+    fn concurrent_insert_at_end_of_bold_is_bold() {
+        let mut a = Doc::new(1);
+        a.insert(0, "ab");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        a.format(0, 1, &attrs(&[("bold", Some("true"))]));
+        b.insert(1, "X");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.runs(), b.runs());
+        assert_eq!(
+            a.runs(),
+            vec![run("aX", &[("bold", Some("true"))]), run("b", &[])]
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use crate::{ConflictResolver, Doc, ID};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+
+/// A visible item together with the number of characters and newlines that
+/// come before it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct IndexEntry {
+    pub id: ID,
+    pub start: usize,
+    pub len: usize,
+    pub newlines_before: usize,
+    pub newlines: usize,
+}
+
+/// Visible items in document order with running character and newline counts,
+/// so positions and lines can be found by binary search instead of walking the
+/// linked list.
+///
+/// Local edits update the index where they happen, anything else rebuilds it.
+/// It must not be relied upon while a change is in progress.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct PositionIndex {
+    pub entries: Vec<IndexEntry>,
+}
+
+impl PositionIndex {
+    pub fn build<R: ConflictResolver>(doc: &Doc<R>) -> Self {
+        let mut entries = Vec::new();
+        let mut start = 0;
+        let mut newlines_before = 0;
+
+        for item in doc {
+            let len = item.len();
+            let newlines = item.content.matches('\n').count();
+            entries.push(IndexEntry {
+                id: item.id,
+                start,
+                len,
+                newlines_before,
+                newlines,
+            });
+            start += len;
+            newlines_before += newlines;
+        }
+
+        Self { entries }
+    }
+
+    /// Replaces the entries in `range` with entries for `items`, given as
+    /// `(id, len, newlines)`, and shifts the ones after them.
+    pub fn splice(
+        &mut self,
+        range: Range<usize>,
+        items: impl IntoIterator<Item = (ID, usize, usize)>,
+    ) {
+        let (mut start, mut newlines_before) = self.offsets(range.start);
+        let (old_start, old_newlines) = self.offsets(range.end);
+
+        let entries: Vec<IndexEntry> = items
+            .into_iter()
+            .map(|(id, len, newlines)| {
+                let entry = IndexEntry {
+                    id,
+                    start,
+                    len,
+                    newlines_before,
+                    newlines,
+                };
+                start += len;
+                newlines_before += newlines;
+                entry
+            })
+            .collect();
+
+        let shifted = range.start + entries.len();
+        self.entries.splice(range, entries);
+        for entry in &mut self.entries[shifted..] {
+            entry.start = entry.start - old_start + start;
+            entry.newlines_before = entry.newlines_before - old_newlines + newlines_before;
+        }
+    }
+
+    /// Characters and newlines before the entry at `idx`.
+    fn offsets(&self, idx: usize) -> (usize, usize) {
+        match self.entries.get(idx) {
+            Some(entry) => (entry.start, entry.newlines_before),
+            None => (self.len(), self.newlines()),
+        }
+    }
+
+    /// Number of visible characters.
+    pub fn len(&self) -> usize {
+        self.entries.last().map_or(0, |e| e.start + e.len)
+    }
+
+    /// Number of visible newlines.
+    pub fn newlines(&self) -> usize {
+        self.entries
+            .last()
+            .map_or(0, |e| e.newlines_before + e.newlines)
+    }
+
+    /// Returns the index of the entry containing the visible character at
+    /// `pos`, or `entries.len()` if `pos` is past the end.
+    pub fn find(&self, pos: usize) -> usize {
+        self.entries.partition_point(|e| e.start + e.len <= pos)
+    }
+
+    /// Returns the index of the entry containing the `n`th (0-indexed) newline.
+    pub fn find_newline(&self, n: usize) -> Option<usize> {
+        let idx = self
+            .entries
+            .partition_point(|e| e.newlines_before + e.newlines <= n);
+        (idx < self.entries.len()).then_some(idx)
+    }
+}
+
+/// Start clocks of every item, per client.
+///
+/// Items of a client partition its clock range, so the item containing a
+/// character is the one starting closest at or before it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ClockIndex {
+    clients: HashMap<u64, BTreeSet<u64>>,
+}
+
+impl ClockIndex {
+    /// Records an item starting at `id`.
+    pub fn insert(&mut self, id: ID) {
+        self.clients.entry(id.client).or_default().insert(id.clock);
+    }
+
+    /// Returns the start of the item that would contain `id`: the closest one
+    /// starting at or before it.
+    pub fn find(&self, id: ID) -> Option<ID> {
+        let clock = *self
+            .clients
+            .get(&id.client)?
+            .range(..=id.clock)
+            .next_back()?;
+        Some(ID {
+            client: id.client,
+            clock,
+        })
+    }
+
+    /// Starts of the items of `client` holding clocks from `clock` onwards, in
+    /// clock order.
+    pub fn from(&self, client: u64, clock: u64) -> impl Iterator<Item = ID> + '_ {
+        let starts = self.clients.get(&client);
+        let first = starts
+            .and_then(|starts| starts.range(..=clock).next_back())
+            .copied()
+            .unwrap_or(clock);
+        starts
+            .into_iter()
+            .flat_map(move |starts| starts.range(first..))
+            .map(move |&clock| ID { client, clock })
+    }
+
+    /// Clients with at least one item.
+    pub fn clients(&self) -> impl Iterator<Item = u64> + '_ {
+        self.clients.keys().copied()
+    }
+}
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Rebuilds the document order and the position index after the visible
+    /// text changed.
+    pub(crate) fn reindex(&mut self) {
+        self.arrange();
+        self.index = PositionIndex::build(self);
+    }
+
+    /// Updates the document order and the position index after the item `id`
+    /// was inserted at the visible position `pos`.
+    pub(crate) fn reindex_insert(&mut self, pos: usize, id: ID) {
+        let idx = self.index.find(pos);
+        match self.index.entries.get(idx) {
+            // The insertion split the item it landed in
+            Some(entry) if entry.start < pos => {
+                let left = entry.id;
+                let right = ID {
+                    client: left.client,
+                    clock: left.clock + (pos - entry.start) as u64,
+                };
+                let entries = [left, id, right].map(|id| self.entry(id));
+                self.index.splice(idx..idx + 1, entries);
+                self.order_after(left, &[id, right]);
+            }
+            _ => {
+                self.index.splice(idx..idx, [self.entry(id)]);
+                self.order_inserted(id);
+            }
+        }
+    }
+
+    /// Updates the document order and the position index after the visible
+    /// characters from `pos` to `end` were deleted.
+    pub(crate) fn reindex_delete(&mut self, pos: usize, end: usize) {
+        let first = self.index.find(pos);
+        let last = self.index.find(end - 1);
+        let (head, tail) = (&self.index.entries[first], &self.index.entries[last]);
+
+        // What is left of the first and last item, both split off the deleted
+        // characters
+        let mut splits = Vec::new();
+        let mut remnants = Vec::new();
+        if head.start < pos {
+            remnants.push(head.id);
+            splits.push((head.id, head.id.clock + (pos - head.start) as u64));
+        }
+        if end < tail.start + tail.len {
+            let right = ID {
+                client: tail.id.client,
+                clock: tail.id.clock + (end - tail.start) as u64,
+            };
+            remnants.push(right);
+            let left = self.items[&right].left.expect("split item has a left part");
+            splits.push((left, right.clock));
+        }
+
+        let entries: Vec<_> = remnants.into_iter().map(|id| self.entry(id)).collect();
+        self.index.splice(first..last + 1, entries);
+        for (left, clock) in splits {
+            let right = ID {
+                client: left.client,
+                clock,
+            };
+            self.order_after(left, &[right]);
+        }
+    }
+
+    /// The index entry of the item `id`, without its position.
+    fn entry(&self, id: ID) -> (ID, usize, usize) {
+        let item = &self.items[&id];
+        (id, item.len(), item.content.matches('\n').count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SequenceCrdt;
+
+    #[test]
+    fn index_of_empty_doc() {
+        let doc = Doc::new(1);
+
+        assert_eq!(doc.index.len(), 0);
+        assert_eq!(doc.index.newlines(), 0);
+        assert_eq!(doc.index.find(0), 0);
+        assert_eq!(doc.index.find_newline(0), None);
+    }
+
+    #[test]
+    fn index_tracks_positions_and_newlines() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "ab\n");
+        doc.insert(3, "c\nd\n");
+
+        assert_eq!(doc.index.len(), 7);
+        assert_eq!(doc.index.newlines(), 3);
+        assert_eq!(doc.index.find(2), 0);
+        assert_eq!(doc.index.find(3), 1);
+        assert_eq!(doc.index.find(7), 2);
+        assert_eq!(doc.index.find_newline(0), Some(0));
+        assert_eq!(doc.index.find_newline(2), Some(1));
+        assert_eq!(doc.index.find_newline(3), None);
+    }
+
+    /// Builds the index the slow way, by walking the list.
+    fn naive_index(doc: &Doc) -> Vec<IndexEntry> {
+        let mut entries = Vec::new();
+        let (mut start, mut newlines_before) = (0, 0);
+        let mut current = doc.head;
+        while let Some(id) = current {
+            let item = &doc.items[&id];
+            if !item.is_deleted {
+                let newlines = item.content.matches('\n').count();
+                entries.push(IndexEntry {
+                    id,
+                    start,
+                    len: item.len(),
+                    newlines_before,
+                    newlines,
+                });
+                start += item.len();
+                newlines_before += newlines;
+            }
+            current = item.right;
+        }
+        entries
+    }
+
+    #[test]
+    fn index_is_updated_in_place_by_local_edits() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello\nworld");
+        doc.delete(3, 4);
+        doc.insert(2, "\n\n");
+        assert_eq!(doc.index.entries, naive_index(&doc));
+
+        let mut seed = 7u64;
+        let mut rand = |max: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (seed >> 33) as usize % max
+        };
+        for _ in 0..300 {
+            let len = doc.len();
+            if len > 0 && rand(3) == 0 {
+                doc.delete(rand(len), 1 + rand(4));
+            } else {
+                doc.insert(rand(len + 1), ["a", "b\n", "\n", "xyz"][rand(4)]);
+            }
+            assert_eq!(doc.index.entries, naive_index(&doc));
+        }
+        assert_eq!(doc.index.len(), doc.value().chars().count());
+    }
+
+    #[test]
+    fn clock_index_finds_containing_item_start() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.insert(5, ",");
+        doc.delete(1, 2);
+
+        // "h" (1, 0), "el" (1, 1), "lo" (1, 3), "," (1, 11), " world" (1, 5)
+        let id = |clock| ID { client: 1, clock };
+        assert_eq!(doc.starts.find(id(4)), Some(id(3)));
+        assert_eq!(doc.starts.find(id(11)), Some(id(11)));
+        assert_eq!(
+            doc.starts.find(ID {
+                client: 2,
+                clock: 0
+            }),
+            None
+        );
+        assert_eq!(
+            doc.starts.from(1, 4).collect::<Vec<_>>(),
+            vec![id(3), id(5), id(11)]
+        );
+        assert_eq!(doc.find_item(id(8)), Some(id(5)));
+    }
+}
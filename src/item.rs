@@ -1,10 +1,74 @@
 use crate::id::ID;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Item {
     pub id: ID,
+    /// The character this item was inserted after. Unlike `left`, never changes.
+    pub origin_left: Option<ID>,
+    /// The character this item was inserted before. Unlike `right`, never changes.
+    pub origin_right: Option<ID>,
     pub left: Option<ID>,
     pub right: Option<ID>,
     pub content: String,
     pub is_deleted: bool,
 }
+
+impl Item {
+    /// Number of characters (and clock values) covered by this item.
+    pub(crate) fn len(&self) -> usize {
+        self.content.chars().count()
+    }
+
+    /// The clock value right after this item, or `None` if its clock range
+    /// runs past `u64::MAX`, which only a crafted update can do.
+    pub(crate) fn end(&self) -> Option<u64> {
+        self.id.clock.checked_add(self.len() as u64)
+    }
+
+    /// The ID of the last character in this item.
+    pub(crate) fn last_id(&self) -> ID {
+        ID {
+            client: self.id.client,
+            clock: self.id.clock + self.len() as u64 - 1,
+        }
+    }
+
+    /// Returns `true` if the character with the given ID belongs to this item.
+    pub(crate) fn contains(&self, id: &ID) -> bool {
+        id.client == self.id.client
+            && id.clock >= self.id.clock
+            && id.clock < self.id.clock + self.len() as u64
+    }
+
+    /// Truncates this item to its first `offset` characters and returns the rest
+    /// as a new item placed directly to its right.
+    pub(crate) fn split_off(&mut self, offset: usize) -> Item {
+        debug_assert!(offset > 0 && offset < self.len(), "split outside item");
+
+        let byte_offset = self
+            .content
+            .char_indices()
+            .nth(offset)
+            .map_or(self.content.len(), |(i, _)| i);
+        let right_content = self.content.split_off(byte_offset);
+
+        let right_id = ID {
+            client: self.id.client,
+            clock: self.id.clock + offset as u64,
+        };
+
+        Item {
+            id: right_id,
+            origin_left: Some(ID {
+                client: right_id.client,
+                clock: right_id.clock - 1,
+            }),
+            origin_right: self.origin_right,
+            left: Some(self.id),
+            right: self.right,
+            content: right_content,
+            is_deleted: self.is_deleted,
+        }
+    }
+}
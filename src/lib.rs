@@ -1,21 +1,70 @@
+mod changes;
+mod client;
 mod conflict;
+mod counter;
+mod delete_set;
+mod delta;
 mod doc;
+mod encoding;
+mod format;
 mod id;
+mod index;
 mod item;
+mod lines;
+mod moves;
+mod persistence;
+mod protocol;
+mod register;
+mod server;
+mod shared;
+mod slice;
+mod snapshot;
 mod state;
+#[cfg(feature = "futures")]
+mod stream;
+mod subdoc;
+#[cfg(test)]
+mod test_util;
+mod text_diff;
 mod traits;
+mod update;
+#[cfg(feature = "websocket")]
+mod websocket;
+mod workspace;
+mod xml;
+#[cfg(feature = "websocket")]
+mod yjs;
 
-pub use conflict::{ConflictResolver, YataResolver};
+pub use changes::ChangeTracker;
+pub use client::Client;
+pub use conflict::{
+    ConflictResolver, DocView, FugueResolver, Integration, RgaResolver, YataResolver,
+};
+pub use counter::Counter;
+pub use delete_set::DeleteSet;
+pub use delta::DeltaOp;
 pub use doc::Doc;
+pub use format::{Anchor, Attributes, Mark, Run};
 pub use id::ID;
 pub use item::Item;
+pub use moves::Move;
+pub use persistence::UpdateLog;
+pub use protocol::{Message, read_message, write_message};
+pub use register::Register;
+pub use server::Server;
+pub use shared::{SharedDoc, Transaction};
 pub use state::StateVector;
-pub use traits::{Crdt, SequenceCrdt};
+#[cfg(feature = "futures")]
+pub use stream::{UpdateSink, UpdateStream};
+pub use subdoc::{Subdoc, Subdocs};
+pub use traits::{BinaryEncode, Crdt, SequenceCrdt};
+pub use update::{Update, diff_update, merge_updates, state_vector_from_update};
+#[cfg(feature = "websocket")]
+pub use websocket::{WebSocketClient, WebSocketMessage};
+pub use workspace::{Workspace, WorkspaceMessage};
+pub use xml::{XmlAttribute, XmlFragment, XmlKind, XmlNode, XmlStateVector, XmlUpdate};
 
 // Future supporting structs/traits:
-// 1. struct Transaction/Txn (batches multiple local operations before emitting single update)
-// 2. impl Iterator on Doc
-// 3. GC?
-// 4. IntegrationQueue (hold items whose deps, i.e. left + right, haven't arrived yet)
-// 5. trait DeltaSerializable (serialize/deserialize updates)
-// 6. struct Update (encapsulates deltas between state vectors)
+// 1. impl Iterator on Doc
+// 2. GC?
+// 3. IntegrationQueue (hold items whose deps, i.e. left + right, haven't arrived yet)
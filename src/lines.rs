@@ -0,0 +1,231 @@
+use crate::{ConflictResolver, Doc, SequenceCrdt};
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Number of lines, i.e. visible newlines plus one.
+    pub fn line_count(&self) -> usize {
+        self.index.newlines() + 1
+    }
+
+    /// Converts a visible character position into a 0-indexed `(line, column)`.
+    ///
+    /// Columns count characters, like positions do. Positions past the end are
+    /// clamped to the end of the document.
+    pub fn pos_to_line_col(&self, pos: usize) -> (usize, usize) {
+        let pos = pos.min(self.index.len());
+        let idx = self.index.find(pos);
+
+        let line = match self.index.entries.get(idx) {
+            Some(entry) => {
+                let item = &self.items[&entry.id];
+                let before = item
+                    .content
+                    .chars()
+                    .take(pos - entry.start)
+                    .filter(|ch| *ch == '\n')
+                    .count();
+                entry.newlines_before + before
+            }
+            None => self.index.newlines(),
+        };
+
+        (line, pos - self.line_start(line))
+    }
+
+    /// Converts a 0-indexed `(line, column)` into a visible character position.
+    ///
+    /// Columns past the end of the line are clamped to the line end (before its
+    /// newline), and lines past the end of the document map to its end.
+    pub fn line_col_to_pos(&self, line: usize, col: usize) -> usize {
+        if line >= self.line_count() {
+            return self.index.len();
+        }
+
+        let start = self.line_start(line);
+        let end = self.newline_pos(line).unwrap_or_else(|| self.index.len());
+        start + col.min(end - start)
+    }
+
+    /// Inserts `text` at the given line and column.
+    pub fn insert_line_col(&mut self, line: usize, col: usize, text: &str) {
+        let pos = self.line_col_to_pos(line, col);
+        self.insert(pos, text);
+    }
+
+    /// Deletes the text between two `(line, column)` positions, `end` exclusive.
+    pub fn delete_line_col(&mut self, start: (usize, usize), end: (usize, usize)) {
+        let start = self.line_col_to_pos(start.0, start.1);
+        let end = self.line_col_to_pos(end.0, end.1);
+        if end > start {
+            self.delete(start, end - start);
+        }
+    }
+
+    /// Iterates over the lines of the document, without their trailing newline.
+    ///
+    /// A document ending in a newline yields an empty last line, matching
+    /// [`line_count`](Self::line_count).
+    pub fn lines(&self) -> impl Iterator<Item = String> + '_ {
+        let mut chars = self.into_iter().flat_map(|item| item.content.chars());
+        let mut done = false;
+
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+
+            let mut line = String::new();
+            for ch in chars.by_ref() {
+                if ch == '\n' {
+                    return Some(line);
+                }
+                line.push(ch);
+            }
+
+            done = true;
+            Some(line)
+        })
+    }
+
+    /// Returns the position of the `n`th (0-indexed) newline.
+    fn newline_pos(&self, n: usize) -> Option<usize> {
+        let entry = &self.index.entries[self.index.find_newline(n)?];
+        let item = &self.items[&entry.id];
+        let offset = item
+            .content
+            .chars()
+            .enumerate()
+            .filter(|(_, ch)| *ch == '\n')
+            .nth(n - entry.newlines_before)
+            .map(|(offset, _)| offset)?;
+        Some(entry.start + offset)
+    }
+
+    /// Returns the position of the first character of `line`.
+    fn line_start(&self, line: usize) -> usize {
+        match line.checked_sub(1) {
+            Some(n) => self.newline_pos(n).map_or(self.index.len(), |pos| pos + 1),
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Crdt;
+
+    fn doc_with(text: &str) -> Doc {
+        let mut doc = Doc::new(1);
+        doc.insert(0, text);
+        doc
+    }
+
+    #[test]
+    fn line_count_counts_newlines() {
+        assert_eq!(Doc::new(1).line_count(), 1);
+        assert_eq!(doc_with("one").line_count(), 1);
+        assert_eq!(doc_with("one\ntwo").line_count(), 2);
+        assert_eq!(doc_with("one\ntwo\n").line_count(), 3);
+    }
+
+    #[test]
+    fn pos_to_line_col_in_single_item() {
+        let doc = doc_with("ab\ncd\n\nef");
+
+        assert_eq!(doc.pos_to_line_col(0), (0, 0));
+        assert_eq!(doc.pos_to_line_col(2), (0, 2));
+        assert_eq!(doc.pos_to_line_col(3), (1, 0));
+        assert_eq!(doc.pos_to_line_col(6), (2, 0));
+        assert_eq!(doc.pos_to_line_col(7), (3, 0));
+        assert_eq!(doc.pos_to_line_col(9), (3, 2));
+        assert_eq!(doc.pos_to_line_col(100), (3, 2));
+    }
+
+    #[test]
+    fn pos_to_line_col_across_items() {
+        let mut doc = doc_with("ab\n");
+        doc.insert(3, "cd");
+        doc.insert(5, "\ne");
+
+        assert_eq!(doc.value(), "ab\ncd\ne");
+        assert_eq!(doc.pos_to_line_col(4), (1, 1));
+        assert_eq!(doc.pos_to_line_col(5), (1, 2));
+        assert_eq!(doc.pos_to_line_col(6), (2, 0));
+    }
+
+    #[test]
+    fn line_col_to_pos_round_trips() {
+        let doc = doc_with("fn main() {\n    🦀\n}\n");
+
+        for pos in 0..=doc.value().chars().count() {
+            let (line, col) = doc.pos_to_line_col(pos);
+            assert_eq!(doc.line_col_to_pos(line, col), pos);
+        }
+    }
+
+    #[test]
+    fn line_col_to_pos_clamps() {
+        let doc = doc_with("ab\ncd");
+
+        assert_eq!(doc.line_col_to_pos(0, 10), 2);
+        assert_eq!(doc.line_col_to_pos(1, 10), 5);
+        assert_eq!(doc.line_col_to_pos(5, 0), 5);
+    }
+
+    #[test]
+    fn insert_line_col_inserts_at_position() {
+        let mut doc = doc_with("fn main() {\n}\n");
+        doc.insert_line_col(1, 0, "    println!();\n");
+
+        assert_eq!(doc.value(), "fn main() {\n    println!();\n}\n");
+    }
+
+    #[test]
+    fn delete_line_col_across_lines() {
+        let mut doc = doc_with("one\ntwo\nthree");
+        doc.delete_line_col((0, 1), (2, 2));
+
+        assert_eq!(doc.value(), "oree");
+    }
+
+    #[test]
+    fn delete_line_col_with_reversed_range_is_noop() {
+        let mut doc = doc_with("one\ntwo");
+        doc.delete_line_col((1, 0), (0, 0));
+
+        assert_eq!(doc.value(), "one\ntwo");
+    }
+
+    #[test]
+    fn lines_iterates_lines() {
+        let doc = doc_with("one\n\nthree\n");
+
+        assert_eq!(
+            doc.lines().collect::<Vec<_>>(),
+            vec!["one", "", "three", ""]
+        );
+        assert_eq!(Doc::new(1).lines().collect::<Vec<_>>(), vec![""]);
+    }
+
+    #[test]
+    fn lines_skip_deleted_text() {
+        let mut doc = doc_with("one\ntwo\nthree");
+        doc.delete(3, 4);
+
+        assert_eq!(doc.lines().collect::<Vec<_>>(), vec!["one", "three"]);
+        assert_eq!(doc.line_count(), 2);
+    }
+
+    #[test]
+    fn line_index_follows_remote_updates() {
+        let mut a = doc_with("one\nthree");
+        let mut b = Doc::new(2);
+        b.apply(a.diff(&b.state_vector()));
+        b.insert_line_col(1, 0, "two\n");
+        a.apply(b.diff(&a.state_vector()));
+
+        assert_eq!(a.line_count(), 3);
+        assert_eq!(a.pos_to_line_col(8), (2, 0));
+        assert_eq!(a.line_col_to_pos(1, 3), 7);
+    }
+}
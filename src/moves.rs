@@ -0,0 +1,636 @@
+use crate::{ConflictResolver, Doc, ID, SequenceCrdt};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+/// Moves the characters after `after` (or from the start of the document) up
+/// to and including `last` so they show up right after `target`.
+///
+/// The range is held by characters rather than positions, so text typed into
+/// it concurrently moves along with it. `target` is a placeholder character the
+/// move inserts, and deletes, where the text goes, so the destination doesn't
+/// depend on where other text ends up. As for [`Mark`](crate::Mark)s,
+/// `id.clock` is a Lamport timestamp: where moves overlap, each character goes
+/// where the one with the highest `(clock, client)` puts it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Move {
+    pub id: ID,
+    pub after: Option<ID>,
+    pub last: ID,
+    pub target: ID,
+}
+
+/// Content of the placeholder characters moves put their text after.
+const PLACEHOLDER: char = '\u{fffc}';
+
+/// Gap between the keys of neighbouring items when a layout is worked out,
+/// leaving room for the items inserted between them later.
+const KEY_GAP: u64 = 1 << 20;
+
+/// Which moves are in effect and the items each of them carries.
+///
+/// Working this out walks the whole document, so the layout is kept on the
+/// [`Doc`] and only worked out again once the moves in effect may have changed.
+/// Items inserted or split in the meantime are added as they appear.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Layout {
+    /// Keys of all items, tombstones included, increasing in list order.
+    keys: HashMap<ID, u64>,
+    /// Indices into `Doc::moves` of the moves in effect, highest priority first.
+    applied: Vec<usize>,
+    /// The move carrying each moved item.
+    owners: HashMap<ID, usize>,
+}
+
+impl Layout {
+    /// Adds `id` between the items with the keys `prev` and `next`. Returns
+    /// `false` if there's no key left between them.
+    fn insert(
+        &mut self,
+        id: ID,
+        prev: Option<u64>,
+        next: Option<u64>,
+        owner: Option<usize>,
+    ) -> bool {
+        let low = prev.unwrap_or(0);
+        let high = next.unwrap_or(u64::MAX);
+        if high - low < 2 {
+            return false;
+        }
+
+        self.keys.insert(id, low + (high - low) / 2);
+        if let Some(owner) = owner {
+            self.owners.insert(id, owner);
+        }
+        true
+    }
+}
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Moves `len` characters starting at `pos` so they start at `to` instead,
+    /// `to` being a position in the text before the move. Does nothing if `to`
+    /// falls inside the moved range.
+    ///
+    /// Unlike deleting and reinserting the text, the characters keep their
+    /// identity: concurrent deletions and formatting still apply to them, and
+    /// text typed into the range concurrently moves along with it. When the
+    /// same text is moved concurrently, the move with the highest Lamport
+    /// timestamp wins, and a move that would put text inside itself is ignored.
+    pub fn move_range(&mut self, pos: usize, len: usize, to: usize) {
+        let len = len.min(self.len().saturating_sub(pos));
+        let to = to.min(self.len());
+        if len == 0 || (pos..=pos + len).contains(&to) {
+            return;
+        }
+
+        let first = self.char_id(pos).expect("char should exist");
+        let last = self.char_id(pos + len - 1).expect("char should exist");
+        self.clean_start(first);
+        self.clean_end(last);
+        self.reindex();
+
+        let order: Vec<ID> = self.in_order().collect();
+        let start = order.iter().position(|id| *id == first);
+        let end = order.iter().position(|id| self.items[id].last_id() == last);
+        let (Some(start), Some(end)) = (start, end) else {
+            unreachable!("moved characters should be in the document");
+        };
+
+        // Text moved before may have come from several places. Every stretch
+        // that is also contiguous in list order gets its own move. Placeholders
+        // stay where they are, or the text placed after them would come along.
+        let targets: HashSet<ID> = self.moves.iter().map(|mv| mv.target).collect();
+        let is_target = |id: &ID| targets.contains(&self.items[id].last_id());
+        let stretches: Vec<(ID, ID)> = order[start..=end]
+            .chunk_by(|a, b| self.items[a].right == Some(*b) && !is_target(a) && !is_target(b))
+            .filter(|stretch| stretch.iter().any(|id| !self.items[id].is_deleted))
+            .map(|stretch| {
+                (
+                    stretch[0],
+                    self.items[&stretch[stretch.len() - 1]].last_id(),
+                )
+            })
+            .collect();
+
+        // One placeholder per stretch, so they show up one after the other
+        let placeholders = self.clock;
+        self.insert(to, &PLACEHOLDER.to_string().repeat(stretches.len()));
+        self.delete(to, stretches.len());
+
+        let clock = self
+            .moves
+            .iter()
+            .map(|mv| mv.id.clock + 1)
+            .max()
+            .unwrap_or(0);
+
+        for (offset, (first, last)) in (0..).zip(stretches) {
+            let after = self.items[&first].left.map(|id| self.items[&id].last_id());
+            self.moves.push(Move {
+                id: ID {
+                    client: self.client_id,
+                    clock: clock + offset,
+                },
+                after,
+                last,
+                target: ID {
+                    client: self.client_id,
+                    clock: placeholders + offset,
+                },
+            });
+        }
+        self.moves_changed();
+        self.reindex();
+    }
+
+    /// Lays out every item, tombstones included, in document order, applying the
+    /// moves on top of the list order.
+    ///
+    /// Without moves the list order is the document order, and `order` is left
+    /// empty.
+    pub(crate) fn arrange(&mut self) {
+        if self.moves.is_empty() {
+            self.order.clear();
+            return;
+        }
+        let list = self.list();
+
+        self.ensure_layout();
+        let layout = self.layout.as_ref().expect("layout was just worked out");
+
+        // The items each move carries, and the ones no move does, in list order
+        let mut pieces: HashMap<usize, Vec<ID>> = HashMap::new();
+        let mut unmoved = Vec::new();
+        for id in list {
+            match layout.owners.get(&id) {
+                Some(i) => pieces.entry(*i).or_default().push(id),
+                None => unmoved.push(id),
+            }
+        }
+
+        // The text moved after each character, most recent move first
+        let mut targets: HashMap<ID, Vec<&[ID]>> = HashMap::new();
+        for i in &layout.applied {
+            if let Some(piece) = pieces.get(i) {
+                targets
+                    .entry(self.moves[*i].target)
+                    .or_default()
+                    .push(piece);
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.items.len());
+        let mut stack: Vec<&[ID]> = vec![&unmoved];
+        while let Some(items) = stack.last_mut() {
+            let Some((&id, rest)) = items.split_first() else {
+                stack.pop();
+                continue;
+            };
+            *items = rest;
+            order.push(id);
+
+            let last = self.items[&id].last_id();
+            stack.extend(targets.get(&last).into_iter().flatten().rev());
+        }
+
+        self.order = order;
+    }
+
+    /// Every item, tombstones included, in document order.
+    pub(crate) fn in_order(&self) -> impl Iterator<Item = ID> + '_ {
+        let mut next = if self.moves.is_empty() {
+            self.head
+        } else {
+            None
+        };
+        let list = std::iter::from_fn(move || {
+            let id = next?;
+            next = self.items[&id].right;
+            Some(id)
+        });
+        self.order.iter().copied().chain(list)
+    }
+
+    /// Adds `ids`, which were just split off `left` or inserted right after it
+    /// in the same moved piece, to the document order after `left`.
+    pub(crate) fn order_after(&mut self, left: ID, ids: &[ID]) {
+        if self.moves.is_empty() {
+            return;
+        }
+        match self.order.iter().position(|id| *id == left) {
+            Some(i) => {
+                self.order.splice(i + 1..i + 1, ids.iter().copied());
+            }
+            None => self.arrange(),
+        }
+    }
+
+    /// Adds the item `id`, which was just inserted, to the document order.
+    ///
+    /// The item goes right before its right neighbour in the list if both are
+    /// carried by the same move, or neither is, and likewise right after its
+    /// left neighbour unless text was moved there. Anything else arranges the
+    /// whole document again.
+    pub(crate) fn order_inserted(&mut self, id: ID) {
+        if self.moves.is_empty() {
+            return;
+        }
+
+        let at = self.layout.as_ref().and_then(|layout| {
+            let owner = layout.owners.get(&id);
+            let item = &self.items[&id];
+            let position = |of: ID| self.order.iter().position(|id| *id == of);
+
+            if let Some(right) = item.right.filter(|right| layout.owners.get(right) == owner) {
+                return position(right);
+            }
+            let left = item.left.filter(|left| layout.owners.get(left) == owner)?;
+            let last = self.items[&left].last_id();
+            if layout.applied.iter().any(|&i| self.moves[i].target == last) {
+                return None;
+            }
+            position(left).map(|i| i + 1)
+        });
+
+        match at {
+            Some(i) => self.order.insert(i, id),
+            None => self.arrange(),
+        }
+    }
+
+    /// Picks the item that text typed between the visible items `left` and
+    /// `right` is attached after.
+    ///
+    /// Without moves that is `left`. Text attached after the end of a moved
+    /// range shows up where the range came from though, in which case the text
+    /// is attached right before `right` instead.
+    pub(crate) fn placement(&mut self, left: Option<ID>, right: Option<ID>) -> Option<ID> {
+        if self.moves.is_empty() {
+            return left;
+        }
+
+        self.ensure_layout();
+        let layout = self.layout.as_ref().expect("layout was just worked out");
+        let owner = |id: Option<ID>| id.and_then(|id| layout.owners.get(&id).copied());
+
+        if self.carrier(layout, left) == owner(left) {
+            return left;
+        }
+
+        let before_right = match right {
+            Some(id) => self.items[&id].left,
+            None => {
+                // The last item of the list
+                let mut last = left.or(self.head);
+                while let Some(next) = last.and_then(|id| self.items[&id].right) {
+                    last = Some(next);
+                }
+                last
+            }
+        };
+        if self.carrier(layout, before_right) == owner(right) {
+            return before_right;
+        }
+
+        left
+    }
+
+    /// The move that would carry text attached after `item`, or at the start
+    /// of the list if `item` is `None`.
+    fn carrier(&self, layout: &Layout, item: Option<ID>) -> Option<usize> {
+        let key = item.map(|id| layout.keys[&id]);
+        let key_of = |id: ID| layout.keys[&self.find_item(id).expect("item should exist")];
+
+        layout.applied.iter().copied().find(|&i| {
+            let mv = &self.moves[i];
+            let after = mv.after.map(key_of);
+            after <= key && key < Some(key_of(mv.last))
+        })
+    }
+
+    /// Adds an item that was just attached after `left`, or split off the item
+    /// before it, to the layout.
+    pub(crate) fn lay_out(&mut self, id: ID, owner: Option<usize>) {
+        let Some(layout) = &mut self.layout else {
+            return;
+        };
+        let item = &self.items[&id];
+        let prev = item.left.map(|id| layout.keys[&id]);
+        let next = item.right.map(|id| layout.keys[&id]);
+        if !layout.insert(id, prev, next, owner) {
+            self.layout = None;
+        }
+    }
+
+    /// The move that will carry an item attached after `left`, if the layout
+    /// is known.
+    pub(crate) fn owner_after(&self, left: Option<ID>) -> Option<usize> {
+        self.layout
+            .as_ref()
+            .and_then(|layout| self.carrier(layout, left))
+    }
+
+    /// The move carrying the item `id`, if the layout is known.
+    pub(crate) fn owner(&self, id: ID) -> Option<usize> {
+        self.layout
+            .as_ref()
+            .and_then(|layout| layout.owners.get(&id).copied())
+    }
+
+    /// Lines the ends of every known move up with item boundaries and forgets
+    /// the layout, after moves were added or became known.
+    pub(crate) fn moves_changed(&mut self) {
+        let boundaries: Vec<ID> = self
+            .moves
+            .iter()
+            .filter(|mv| self.is_known(mv))
+            .flat_map(|mv| mv.after.into_iter().chain([mv.last, mv.target]))
+            .collect();
+        for id in boundaries {
+            self.clean_end(id);
+        }
+        self.layout = None;
+    }
+
+    /// Number of moves whose characters have all been integrated.
+    pub(crate) fn known_moves(&self) -> usize {
+        self.moves.iter().filter(|mv| self.is_known(mv)).count()
+    }
+
+    /// Returns `true` if every character `mv` refers to has been integrated.
+    fn is_known(&self, mv: &Move) -> bool {
+        [mv.after, Some(mv.last), Some(mv.target)]
+            .iter()
+            .flatten()
+            .all(|id| self.contains_id(id))
+    }
+
+    /// Items, tombstones included, in list order, i.e. where they were
+    /// inserted.
+    fn list(&self) -> Vec<ID> {
+        std::iter::successors(self.head, |id| self.items[id].right).collect()
+    }
+
+    fn ensure_layout(&mut self) {
+        if self.layout.is_none() {
+            self.layout = Some(self.compute_layout());
+        }
+    }
+
+    /// Works out which items each move carries, once moved ranges line up with
+    /// item boundaries.
+    fn compute_layout(&self) -> Layout {
+        let list = self.list();
+        let positions: HashMap<ID, usize> =
+            list.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let position = |id: ID| positions[&self.find_item(id).expect("item should exist")];
+
+        let mut layout = Layout {
+            keys: list
+                .iter()
+                .zip((1..).map(|i| i * KEY_GAP))
+                .map(|(id, key)| (*id, key))
+                .collect(),
+            applied: Vec::new(),
+            owners: HashMap::new(),
+        };
+
+        let mut moves: Vec<usize> = (0..self.moves.len())
+            .filter(|&i| self.is_known(&self.moves[i]))
+            .collect();
+        moves.sort_by_key(|&i| Reverse((self.moves[i].id.clock, self.moves[i].id.client)));
+
+        for i in moves {
+            let mv = &self.moves[i];
+            let start = mv.after.map_or(0, |id| position(id) + 1);
+            let end = position(mv.last) + 1;
+
+            // Characters already placed by a later move stay where it put them
+            let claimed: Vec<ID> = list[start.min(end)..end]
+                .iter()
+                .filter(|id| !layout.owners.contains_key(*id))
+                .copied()
+                .collect();
+            for id in &claimed {
+                layout.owners.insert(*id, i);
+            }
+
+            // Moving text into itself, directly or by way of other moves, would
+            // leave it nowhere
+            let mut target = mv.target;
+            let cyclic = loop {
+                let owner = self
+                    .find_item(target)
+                    .and_then(|item| layout.owners.get(&item).copied());
+                match owner {
+                    None => break false,
+                    Some(owner) if owner == i => break true,
+                    Some(owner) => target = self.moves[owner].target,
+                }
+            };
+
+            if cyclic {
+                for id in &claimed {
+                    layout.owners.remove(id);
+                }
+            } else {
+                layout.applied.push(i);
+            }
+        }
+
+        layout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sync;
+
+    fn rand(seed: &mut u64, max: usize) -> usize {
+        *seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((*seed >> 33) as usize) % max.max(1)
+    }
+
+    #[test]
+    fn move_range_relocates_text() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+
+        doc.move_range(6, 5, 0);
+        assert_eq!(doc.value(), "worldhello ");
+
+        doc.move_range(0, 5, 11);
+        assert_eq!(doc.value(), "hello world");
+    }
+
+    #[test]
+    fn move_into_itself_does_nothing() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "abcdef");
+
+        doc.move_range(1, 3, 2);
+        doc.move_range(1, 3, 4);
+        doc.move_range(1, 0, 5);
+
+        assert_eq!(doc.value(), "abcdef");
+        assert!(doc.moves.is_empty());
+    }
+
+    #[test]
+    fn local_edits_land_where_they_are_made() {
+        // Checks every edit against the same edit on a plain list of characters
+        let mut seed = 3;
+        let mut doc = Doc::new(1);
+        let mut expected: Vec<char> = Vec::new();
+
+        for _ in 0..200 {
+            let len = expected.len();
+            match rand(&mut seed, 4) {
+                0 if len > 0 => {
+                    let pos = rand(&mut seed, len);
+                    let n = 1 + rand(&mut seed, 3);
+                    doc.delete(pos, n);
+                    expected.drain(pos..(pos + n).min(len));
+                }
+                1 if len > 0 => {
+                    let pos = rand(&mut seed, len);
+                    let n = 1 + rand(&mut seed, (len - pos).min(8));
+                    let to = rand(&mut seed, len + 1);
+                    doc.move_range(pos, n, to);
+
+                    if !(pos..=pos + n).contains(&to) {
+                        let moved: Vec<char> = expected.drain(pos..pos + n).collect();
+                        let to = if to > pos { to - n } else { to };
+                        expected.splice(to..to, moved);
+                    }
+                }
+                _ => {
+                    let pos = rand(&mut seed, len + 1);
+                    let text = ["a", "bc", "🦀"][rand(&mut seed, 3)];
+                    doc.insert(pos, text);
+                    expected.splice(pos..pos, text.chars());
+                }
+            }
+
+            assert_eq!(doc.value(), expected.iter().collect::<String>());
+
+            // The index and order kept up to date along the way are right too
+            for entry in &doc.index.entries {
+                let text: String = expected[entry.start..entry.start + entry.len]
+                    .iter()
+                    .collect();
+                assert_eq!(doc.items[&entry.id].content, text);
+            }
+            let mut arranged = doc.clone();
+            arranged.arrange();
+            assert_eq!(doc.order, arranged.order);
+        }
+    }
+
+    #[test]
+    fn concurrent_edits_move_with_the_text() {
+        let mut a = Doc::new(1);
+        a.insert(0, "one two three ");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        a.move_range(4, 4, 14);
+        b.insert(5, "X"); // Inside "two"
+        b.delete(7, 1); // The "o" of "two"
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), "one three tXw ");
+        assert_eq!(b.value(), a.value());
+    }
+
+    #[test]
+    fn concurrent_moves_of_the_same_text_pick_one() {
+        let mut a = Doc::new(1);
+        a.insert(0, "abc xyz");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        a.move_range(4, 3, 0);
+        b.move_range(4, 3, 3);
+        sync(&mut a, &mut b);
+
+        // Same Lamport timestamp, the higher client wins
+        assert_eq!(a.value(), "abcxyz ");
+        assert_eq!(b.value(), a.value());
+
+        // A later move takes over
+        a.move_range(3, 3, 0);
+        sync(&mut a, &mut b);
+        assert_eq!(a.value(), "xyzabc ");
+        assert_eq!(b.value(), a.value());
+    }
+
+    #[test]
+    fn concurrent_moves_into_each_other_keep_all_text() {
+        let mut a = Doc::new(1);
+        a.insert(0, "abcd");
+        let mut b = Doc::new(2);
+        sync(&mut a, &mut b);
+
+        a.move_range(0, 2, 3); // "ab" between "c" and "d"
+        b.move_range(2, 2, 1); // "cd" between "a" and "b"
+        sync(&mut a, &mut b);
+
+        // "cd" into "ab" wins, "ab" into "cd" would now put "ab" inside itself
+        assert_eq!(a.value(), "acdb");
+        assert_eq!(b.value(), a.value());
+    }
+
+    #[test]
+    fn random_concurrent_moves_converge() {
+        let mut seed = 11;
+        let mut docs = [1, 2, 3].map(Doc::new);
+
+        for _ in 0..50 {
+            for doc in docs.iter_mut() {
+                for _ in 0..3 {
+                    let len = doc.len();
+                    match rand(&mut seed, 4) {
+                        0 if len > 0 => doc.delete(rand(&mut seed, len), 1 + rand(&mut seed, 3)),
+                        1 if len > 0 => {
+                            let pos = rand(&mut seed, len);
+                            let n = 1 + rand(&mut seed, 5);
+                            doc.move_range(pos, n, rand(&mut seed, len + 1));
+                        }
+                        _ => doc.insert(
+                            rand(&mut seed, len + 1),
+                            ["x", "yz", "🦀"][rand(&mut seed, 3)],
+                        ),
+                    }
+                }
+            }
+
+            // The layout kept up to date along the way matches a fresh one
+            for doc in docs.iter_mut() {
+                doc.ensure_layout();
+                let layout = doc.layout.as_ref().unwrap();
+                let fresh = doc.compute_layout();
+                assert_eq!(layout.applied, fresh.applied);
+                assert_eq!(layout.owners, fresh.owners);
+            }
+
+            let [a, b, c] = &mut docs;
+            match rand(&mut seed, 3) {
+                0 => sync(a, b),
+                1 => sync(b, c),
+                _ => sync(a, c),
+            }
+        }
+
+        let [a, b, c] = &mut docs;
+        sync(a, b);
+        sync(b, c);
+        sync(a, b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(b.value(), c.value());
+    }
+}
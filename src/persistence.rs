@@ -0,0 +1,344 @@
+use crate::{BinaryEncode, ChangeTracker, ConflictResolver, Crdt, Doc, StateVector, Update};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Size of the `(length, checksum)` header in front of every record.
+const HEADER_LEN: usize = 8;
+
+/// An append-only file of encoded updates that a [`Doc`] is rebuilt from.
+///
+/// Each record is a little-endian `u32` payload length, a `u32` checksum of the
+/// payload and the encoded [`Update`]. A record cut short by a crash, or one
+/// whose checksum doesn't match, ends the log: it's dropped on open along with
+/// anything after it.
+#[derive(Debug)]
+pub struct UpdateLog {
+    path: PathBuf,
+    file: File,
+    records: usize,
+    /// What has been persisted so far.
+    tracker: ChangeTracker,
+    /// Number of records after which [`save`](Self::save) compacts the log.
+    pub compact_after: usize,
+}
+
+impl UpdateLog {
+    pub const DEFAULT_COMPACT_AFTER: usize = 64;
+
+    /// Opens the log at `path`, creating it if it doesn't exist, and replays
+    /// every stored update into `doc`.
+    pub fn open<R: ConflictResolver>(path: impl AsRef<Path>, doc: &mut Doc<R>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let (updates, valid_len) = read_records(&bytes);
+        if valid_len < bytes.len() {
+            // Drop the torn tail so new records don't end up behind it
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+
+        let records = updates.len();
+        for update in updates {
+            doc.apply(update);
+        }
+
+        Ok(Self {
+            path,
+            file,
+            records,
+            tracker: ChangeTracker::new(doc),
+            compact_after: Self::DEFAULT_COMPACT_AFTER,
+        })
+    }
+
+    /// Number of records currently in the log.
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Appends an update to the log and flushes it to disk.
+    pub fn append(&mut self, update: &Update) -> io::Result<()> {
+        self.file.write_all(&encode_record(update)?)?;
+        self.file.sync_data()?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Appends whatever changed in `doc` since it was opened or last saved,
+    /// compacting the log once it holds more than `compact_after` records.
+    ///
+    /// Only the changes are written, so a record's size doesn't grow with the
+    /// history of the document.
+    pub fn save<R: ConflictResolver>(&mut self, doc: &Doc<R>) -> io::Result<()> {
+        let update = self.tracker.changes(doc);
+        if update.is_empty() {
+            return Ok(());
+        }
+
+        if self.records >= self.compact_after {
+            return self.compact(doc);
+        }
+
+        self.append(&update)?;
+        self.tracker.reset(doc);
+        Ok(())
+    }
+
+    /// Replaces the log with a single update holding the whole state of `doc`.
+    ///
+    /// The snapshot is written to a temporary file which then replaces the log,
+    /// so a crash midway leaves either the old or the new log intact.
+    pub fn compact<R: ConflictResolver>(&mut self, doc: &Doc<R>) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&encode_record(&doc.diff(&StateVector::new()))?)?;
+            tmp.sync_all()?;
+        }
+
+        fs::rename(&tmp_path, &self.path)?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.records = 1;
+        self.tracker.reset(doc);
+        Ok(())
+    }
+}
+
+/// Frames an encoded update as a log record.
+fn encode_record(update: &Update) -> io::Result<Vec<u8>> {
+    let payload = update.encode();
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "update too large"))?;
+
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&checksum(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// Parses records until the end of the input or the first damaged record.
+/// Returns the updates and the number of bytes they span.
+fn read_records(bytes: &[u8]) -> (Vec<Update>, usize) {
+    let mut updates = Vec::new();
+    let mut offset = 0;
+
+    while let Some(header) = bytes.get(offset..offset + HEADER_LEN) {
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let sum = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+        let start = offset + HEADER_LEN;
+        let Some(payload) = bytes.get(start..start + len) else {
+            break;
+        };
+        if checksum(payload) != sum {
+            break;
+        }
+        let Some(update) = Update::decode(payload) else {
+            break;
+        };
+
+        updates.push(update);
+        offset = start + len;
+    }
+
+    (updates, offset)
+}
+
+/// 32-bit FNV-1a, enough to catch torn or partially flushed writes.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SequenceCrdt;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn open_creates_empty_log() {
+        let path = temp_path("empty");
+        let mut doc = Doc::new(1);
+        let log = UpdateLog::open(&path, &mut doc).unwrap();
+
+        assert_eq!(log.records(), 0);
+        assert!(path.exists());
+        assert_eq!(doc.value(), "");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn saved_changes_are_replayed_on_open() {
+        let path = temp_path("replay");
+        {
+            let mut doc = Doc::new(1);
+            let mut log = UpdateLog::open(&path, &mut doc).unwrap();
+            doc.insert(0, "hello");
+            log.save(&doc).unwrap();
+            doc.insert(5, " world");
+            doc.delete(0, 1);
+            log.save(&doc).unwrap();
+            assert_eq!(log.records(), 2);
+        }
+
+        let mut doc = Doc::new(1);
+        let log = UpdateLog::open(&path, &mut doc).unwrap();
+
+        assert_eq!(log.records(), 2);
+        assert_eq!(doc.value(), "ello world");
+        // The clock resumes after the replayed items of this client
+        assert_eq!(doc.clock, 11);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_without_changes_appends_nothing() {
+        let path = temp_path("noop");
+        let mut doc = Doc::new(1);
+        let mut log = UpdateLog::open(&path, &mut doc).unwrap();
+        doc.insert(0, "hello");
+        log.save(&doc).unwrap();
+        log.save(&doc).unwrap();
+
+        assert_eq!(log.records(), 1);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_records_deletes_only_changes() {
+        let path = temp_path("deletes");
+        let mut doc = Doc::new(1);
+        let mut log = UpdateLog::open(&path, &mut doc).unwrap();
+        doc.insert(0, "hello");
+        log.save(&doc).unwrap();
+        doc.delete(0, 2);
+        log.save(&doc).unwrap();
+
+        assert_eq!(log.records(), 2);
+        let (records, _) = read_records(&fs::read(&path).unwrap());
+        assert!(records[1].items.is_empty());
+        assert_eq!(
+            records[1].delete_set.iter().collect::<Vec<_>>(),
+            vec![(1, 0, 2)]
+        );
+        let mut reopened = Doc::new(1);
+        UpdateLog::open(&path, &mut reopened).unwrap();
+        assert_eq!(reopened.value(), "llo");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compact_rewrites_log_as_single_record() {
+        let path = temp_path("compact");
+        let mut doc = Doc::new(1);
+        let mut log = UpdateLog::open(&path, &mut doc).unwrap();
+        for (i, word) in ["one ", "two ", "three"].iter().enumerate() {
+            doc.insert(i * 4, word);
+            log.save(&doc).unwrap();
+        }
+        let before = fs::metadata(&path).unwrap().len();
+
+        log.compact(&doc).unwrap();
+        doc.insert(0, ">");
+        log.save(&doc).unwrap();
+
+        assert_eq!(log.records(), 2);
+        assert!(fs::metadata(&path).unwrap().len() < before + 20);
+        let mut reopened = Doc::new(1);
+        UpdateLog::open(&path, &mut reopened).unwrap();
+        assert_eq!(reopened.value(), ">one two three");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_compacts_after_threshold() {
+        let path = temp_path("threshold");
+        let mut doc = Doc::new(1);
+        let mut log = UpdateLog::open(&path, &mut doc).unwrap();
+        log.compact_after = 3;
+
+        for i in 0..5 {
+            doc.insert(i, "x");
+            log.save(&doc).unwrap();
+        }
+
+        assert!(log.records() <= 3);
+        let mut reopened = Doc::new(1);
+        UpdateLog::open(&path, &mut reopened).unwrap();
+        assert_eq!(reopened.value(), "xxxxx");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn truncated_tail_is_dropped() {
+        let path = temp_path("truncated");
+        {
+            let mut doc = Doc::new(1);
+            let mut log = UpdateLog::open(&path, &mut doc).unwrap();
+            doc.insert(0, "hello");
+            log.save(&doc).unwrap();
+            doc.insert(5, " world");
+            log.save(&doc).unwrap();
+        }
+
+        // Simulate a crash halfway through writing the second record
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let mut doc = Doc::new(1);
+        let mut log = UpdateLog::open(&path, &mut doc).unwrap();
+        assert_eq!(log.records(), 1);
+        assert_eq!(doc.value(), "hello");
+
+        // New records land after the last good one
+        doc.insert(5, "!");
+        log.save(&doc).unwrap();
+        let mut reopened = Doc::new(1);
+        UpdateLog::open(&path, &mut reopened).unwrap();
+        assert_eq!(reopened.value(), "hello!");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_record_ends_log() {
+        let path = temp_path("corrupt");
+        {
+            let mut doc = Doc::new(1);
+            let mut log = UpdateLog::open(&path, &mut doc).unwrap();
+            doc.insert(0, "hello");
+            log.save(&doc).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[3, 0, 0, 0, 1, 2, 3, 4, 9, 9, 9]).unwrap();
+
+        let mut doc = Doc::new(1);
+        let log = UpdateLog::open(&path, &mut doc).unwrap();
+
+        assert_eq!(log.records(), 1);
+        assert_eq!(doc.value(), "hello");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+use crate::encoding::{Decoder, Encoder};
+use crate::{BinaryEncode, ConflictResolver, Crdt, SharedDoc, StateVector, Update};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+/// Largest frame accepted from a peer.
+const MAX_FRAME_LEN: usize = 64 << 20;
+
+/// A message of the sync protocol.
+///
+/// A client opens with [`Hello`](Message::Hello) naming the document, then both
+/// sides send [`SyncStep1`](Message::SyncStep1) with their state vector and
+/// answer the other's with [`SyncStep2`](Message::SyncStep2) holding what it is
+/// missing. After that, every change is sent as an [`Update`](Message::Update).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Hello(String),
+    SyncStep1(StateVector),
+    SyncStep2(Update),
+    Update(Update),
+}
+
+impl BinaryEncode for Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        match self {
+            Message::Hello(name) => {
+                encoder.u8(0);
+                encoder.string(name);
+            }
+            Message::SyncStep1(state_vector) => {
+                encoder.u8(1);
+                encoder.bytes(&state_vector.encode());
+            }
+            Message::SyncStep2(update) => {
+                encoder.u8(2);
+                encoder.bytes(&update.encode());
+            }
+            Message::Update(update) => {
+                encoder.u8(3);
+                encoder.bytes(&update.encode());
+            }
+        }
+        encoder.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(bytes);
+        let message = match decoder.u8()? {
+            0 => Message::Hello(decoder.string()?),
+            1 => Message::SyncStep1(StateVector::decode(decoder.bytes()?)?),
+            2 => Message::SyncStep2(Update::decode(decoder.bytes()?)?),
+            3 => Message::Update(Update::decode(decoder.bytes()?)?),
+            _ => return None,
+        };
+        decoder.is_empty().then_some(message)
+    }
+}
+
+/// Writes a message as a little-endian `u32` length followed by its encoding.
+pub fn write_message(writer: &mut impl Write, message: &Message) -> io::Result<()> {
+    let payload = message.encode();
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|&len| len as usize <= MAX_FRAME_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&payload)
+}
+
+/// Reads a message written by [`write_message`]. Returns `None` if the stream
+/// ended cleanly before the next message.
+pub fn read_message(reader: &mut impl Read) -> io::Result<Option<Message>> {
+    let mut header = [0; 4];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let len = u32::from_le_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(invalid_data("frame too large"));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Message::decode(&payload)
+        .map(Some)
+        .ok_or_else(|| invalid_data("malformed message"))
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A byte stream the sync protocol runs over.
+pub(crate) trait Connection: Read + Write + Send + 'static {
+    fn try_clone(&self) -> io::Result<Self>
+    where
+        Self: Sized;
+
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        std::os::unix::net::UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        std::os::unix::net::UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+/// One end of a sync connection for a [`SharedDoc`]: forwards the document's
+/// changes to the peer and applies what the peer sends.
+///
+/// Messages are written by a dedicated thread, so a slow peer never holds up
+/// the transactions of others.
+pub(crate) struct Link<R: ConflictResolver> {
+    doc: SharedDoc<R>,
+    origin: u64,
+    observer: u64,
+    sender: Mutex<Option<Outbox>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Queues a message for the peer.
+type Outbox = Box<dyn Fn(Message) + Send>;
+
+impl<R: ConflictResolver> Link<R> {
+    /// Starts writing to `writer`, beginning with `greeting` and followed by
+    /// every change to `doc` that didn't come from this link.
+    pub fn new(
+        doc: SharedDoc<R>,
+        writer: impl Write + Send + 'static,
+        greeting: Vec<Message>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let link = Self::detached(doc, greeting, sender);
+        let writer = thread::spawn(move || {
+            let mut writer = BufWriter::new(writer);
+            for message in receiver {
+                if write_message(&mut writer, &message)
+                    .and_then(|_| writer.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        *link.writer.lock().unwrap_or_else(PoisonError::into_inner) = Some(writer);
+        link
+    }
+
+    /// Like [`new`](Self::new), but leaves writing to the caller: messages for
+    /// the peer are sent to `outbox`, converted into whatever the caller waits
+    /// for.
+    pub fn detached<T: From<Message> + Send + 'static>(
+        doc: SharedDoc<R>,
+        greeting: Vec<Message>,
+        outbox: Sender<T>,
+    ) -> Self {
+        for message in greeting {
+            let _ = outbox.send(message.into());
+        }
+
+        let origin = doc.next_origin();
+        let forward = outbox.clone();
+        let observer = doc.observe_from(move |update, from| {
+            if from != Some(origin) {
+                let _ = forward.send(Message::Update(update.clone()).into());
+            }
+        });
+
+        let sender: Outbox = Box::new(move |message| {
+            let _ = outbox.send(message.into());
+        });
+        Self {
+            doc,
+            origin,
+            observer,
+            sender: Mutex::new(Some(sender)),
+            writer: Mutex::new(None),
+        }
+    }
+
+    /// Queues a message for the peer. Messages after [`finish`](Self::finish)
+    /// are dropped.
+    pub fn send(&self, message: Message) {
+        let sender = self.sender.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(send) = sender.as_ref() {
+            send(message);
+        }
+    }
+
+    /// Answers a state vector or applies an update from the peer.
+    pub fn handle(&self, message: Message) -> io::Result<()> {
+        match message {
+            Message::SyncStep1(state_vector) => {
+                self.send(Message::SyncStep2(self.doc.diff(&state_vector)));
+            }
+            Message::SyncStep2(update) | Message::Update(update) => {
+                self.doc
+                    .transact_from(Some(self.origin), |txn| txn.apply(update));
+            }
+            Message::Hello(_) => return Err(invalid_data("unexpected hello")),
+        }
+        Ok(())
+    }
+
+    /// Stops forwarding changes and, unless detached, waits until everything
+    /// queued so far has been written.
+    pub fn finish(&self) {
+        self.doc.unobserve(self.observer);
+        self.sender
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl<R: ConflictResolver> Drop for Link<R> {
+    fn drop(&mut self) {
+        self.doc.unobserve(self.observer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Crdt, Doc, SequenceCrdt};
+
+    fn sample_update() -> Update {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        doc.delete(0, 1);
+        doc.diff(&StateVector::new())
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            Message::Hello("notes".into()),
+            Message::SyncStep1(StateVector::from([(1, 4), (2, 0)])),
+            Message::SyncStep2(sample_update()),
+            Message::Update(sample_update()),
+        ];
+
+        let mut bytes = Vec::new();
+        for message in &messages {
+            write_message(&mut bytes, message).unwrap();
+        }
+
+        let mut reader = bytes.as_slice();
+        for message in messages {
+            // Items come back without `is_deleted`, that's carried by the delete set
+            let read = read_message(&mut reader).unwrap().unwrap();
+            assert_eq!(read.encode(), message.encode());
+        }
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_frame_is_an_error() {
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &Message::Hello("notes".into())).unwrap();
+        bytes.pop();
+
+        let err = read_message(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected() {
+        let bytes = u32::MAX.to_le_bytes();
+
+        let err = read_message(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unknown_message_is_rejected() {
+        let bytes = [1, 0, 0, 0, 9];
+
+        let err = read_message(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{ConflictResolver, Doc, ID, StateVector};
+
+/// A single-valued field that keeps every value written concurrently, so the
+/// conflict can be shown instead of silently dropping a write.
+///
+/// Each write is tagged with the writer's client ID and a clock counting that
+/// client's writes to the register. `seen` is the state vector of the writes
+/// the register has seen. A write replaces every value seen so far, so when
+/// merging, a value one side has seen but no longer holds was overwritten and
+/// is dropped.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Register {
+    /// The current values, ordered by ID.
+    pub values: Vec<(ID, String)>,
+    pub seen: StateVector,
+}
+
+impl Register {
+    /// Folds `other` into this register, keeping the values neither side has
+    /// overwritten.
+    pub fn merge(&mut self, other: &Register) {
+        let survives = |(id, _): &&(ID, String), values: &[(ID, String)], seen: &StateVector| {
+            values.iter().any(|(other, _)| other == id)
+                || seen.get(&id.client).is_none_or(|clock| *clock < id.clock)
+        };
+
+        let mut values: Vec<(ID, String)> = self
+            .values
+            .iter()
+            .filter(|value| survives(value, &other.values, &other.seen))
+            .chain(
+                other
+                    .values
+                    .iter()
+                    .filter(|value| survives(value, &self.values, &self.seen)),
+            )
+            .cloned()
+            .collect();
+        values.sort_by_key(|(id, _)| *id);
+        values.dedup_by_key(|(id, _)| *id);
+
+        for (client, clock) in &other.seen {
+            let own = self.seen.entry(*client).or_insert(*clock);
+            *own = (*own).max(*clock);
+        }
+        self.values = values;
+    }
+}
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Sets the register `name` to `value`, replacing every value it had.
+    pub fn set_register(&mut self, name: &str, value: &str) {
+        let register = self.registers.entry(name.to_string()).or_default();
+        let id = ID {
+            client: self.client_id,
+            clock: register
+                .seen
+                .get(&self.client_id)
+                .map_or(0, |clock| clock + 1),
+        };
+        register.seen.insert(id.client, id.clock);
+        register.values = vec![(id, value.to_string())];
+    }
+
+    /// Returns the values of the register `name`: a single one, or one per
+    /// concurrent write, ordered by writer. Empty if it was never set.
+    pub fn register(&self, name: &str) -> Vec<&str> {
+        self.registers.get(name).map_or(Vec::new(), |register| {
+            register
+                .values
+                .iter()
+                .map(|(_, value)| value.as_str())
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Doc;
+    use crate::test_util::sync;
+
+    #[test]
+    fn later_writes_replace_earlier_ones() {
+        let mut a = Doc::new(1);
+        let mut b = Doc::new(2);
+        a.set_register("title", "Draft");
+        sync(&mut a, &mut b);
+        b.set_register("title", "Final");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.register("title"), ["Final"]);
+        assert_eq!(b.register("title"), ["Final"]);
+        assert!(a.register("owner").is_empty());
+    }
+
+    #[test]
+    fn concurrent_writes_are_all_kept_until_overwritten() {
+        let mut a = Doc::new(1);
+        let mut b = Doc::new(2);
+        a.set_register("title", "Mine");
+        b.set_register("title", "Yours");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.register("title"), ["Mine", "Yours"]);
+        assert_eq!(b.register("title"), ["Mine", "Yours"]);
+
+        a.set_register("title", "Ours");
+        sync(&mut a, &mut b);
+        assert_eq!(a.register("title"), ["Ours"]);
+        assert_eq!(b.register("title"), ["Ours"]);
+    }
+}
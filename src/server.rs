@@ -0,0 +1,342 @@
+use crate::protocol::{Connection, Link, Message, invalid_data, read_message};
+use crate::{Doc, SharedDoc, Update, UpdateLog, merge_updates};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+/// Client ID the server uses for the documents it hosts. The server never edits
+/// them itself, so it never takes up any clock values.
+const SERVER_CLIENT_ID: u64 = 0;
+
+/// Hosts named documents for clients speaking the sync protocol (see
+/// [`Message`]) and persists each of them as an [`UpdateLog`] in a directory.
+///
+/// Every update a client sends is applied, saved and passed on to the other
+/// clients of the same document. Cloning a server gives another handle to it.
+#[derive(Clone)]
+pub struct Server {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    dir: PathBuf,
+    docs: Mutex<HashMap<String, Arc<Hosted>>>,
+}
+
+pub(crate) struct Hosted {
+    pub doc: SharedDoc,
+    log: Mutex<UpdateLog>,
+    /// Updates of the document not written to the log yet, in commit order.
+    unsaved: Arc<Mutex<Vec<Update>>>,
+    #[cfg(feature = "websocket")]
+    pub awareness: Mutex<crate::websocket::Awareness>,
+}
+
+impl Hosted {
+    /// Appends the updates made since the last save to the document's log, as
+    /// one record, compacting the log once it has grown past its threshold.
+    ///
+    /// The updates are the ones observed as the document changed, so saving
+    /// never compares against the whole state.
+    pub fn save(&self) -> io::Result<()> {
+        let mut log = lock(&self.log);
+        let updates = std::mem::take(&mut *lock(&self.unsaved));
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let result = if log.records() >= log.compact_after {
+            self.doc.read(|doc| log.compact(doc))
+        } else {
+            log.append(&merge_updates(&updates))
+        };
+        if result.is_err() {
+            // Keep them for the next attempt
+            lock(&self.unsaved).splice(..0, updates);
+        }
+        result
+    }
+}
+
+impl Server {
+    /// Creates a server storing its documents in `dir`, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                dir,
+                docs: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    /// Returns the document called `name`, loading it from disk or creating it
+    /// on first use.
+    ///
+    /// Names may only contain ASCII letters, digits, `-`, `_` and `.`, and must
+    /// not start with a `.`.
+    pub fn doc(&self, name: &str) -> io::Result<SharedDoc> {
+        Ok(self.hosted(name)?.doc.clone())
+    }
+
+    pub(crate) fn hosted(&self, name: &str) -> io::Result<Arc<Hosted>> {
+        check_name(name)?;
+
+        let mut docs = lock(&self.inner.docs);
+        if let Some(hosted) = docs.get(name) {
+            return Ok(Arc::clone(hosted));
+        }
+
+        let mut doc = Doc::new(SERVER_CLIENT_ID);
+        let log = UpdateLog::open(self.inner.dir.join(format!("{name}.log")), &mut doc)?;
+        let doc = SharedDoc::from_doc(doc);
+        let unsaved = Arc::new(Mutex::new(Vec::new()));
+        let queue = Arc::clone(&unsaved);
+        doc.observe(move |update| lock(&queue).push(update.clone()));
+
+        let hosted = Arc::new(Hosted {
+            doc,
+            log: Mutex::new(log),
+            unsaved,
+            #[cfg(feature = "websocket")]
+            awareness: Mutex::default(),
+        });
+        docs.insert(name.to_owned(), Arc::clone(&hosted));
+        Ok(hosted)
+    }
+
+    /// Accepts TCP connections, serving each on its own thread. A connection
+    /// that fails to be accepted is skipped.
+    pub fn serve_tcp(&self, listener: TcpListener) -> io::Result<()> {
+        // Accept errors such as an aborted connection only concern that one
+        for stream in listener.incoming().flatten() {
+            self.spawn(stream);
+        }
+        Ok(())
+    }
+
+    /// Accepts Unix socket connections, serving each on its own thread. A
+    /// connection that fails to be accepted is skipped.
+    #[cfg(unix)]
+    pub fn serve_unix(&self, listener: std::os::unix::net::UnixListener) -> io::Result<()> {
+        for stream in listener.incoming().flatten() {
+            self.spawn(stream);
+        }
+        Ok(())
+    }
+
+    fn spawn(&self, connection: impl Connection) {
+        let server = self.clone();
+        thread::spawn(move || {
+            // A broken connection only affects its own client
+            let _ = server.handle(connection);
+        });
+    }
+
+    fn handle(&self, connection: impl Connection) -> io::Result<()> {
+        let mut reader = BufReader::new(connection.try_clone()?);
+        let Some(Message::Hello(name)) = read_message(&mut reader)? else {
+            return Err(invalid_data("expected hello"));
+        };
+        let hosted = self.hosted(&name)?;
+
+        let greeting = vec![Message::SyncStep1(hosted.doc.state_vector())];
+        let link = Link::new(hosted.doc.clone(), connection.try_clone()?, greeting);
+
+        let result = (|| {
+            while let Some(message) = read_message(&mut reader)? {
+                let changes = !matches!(message, Message::SyncStep1(_));
+                link.handle(message)?;
+                if changes {
+                    hosted.save()?;
+                }
+            }
+            Ok(())
+        })();
+
+        link.finish();
+        let _ = connection.shutdown();
+        result
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Fails unless `name` is usable as a document name, which also makes it safe
+/// to use in a file name.
+pub(crate) fn check_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid document name",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use crate::{Client, Crdt, SequenceCrdt, StateVector};
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    /// Starts a server on a free localhost port.
+    fn start(dir: &Path) -> (Server, String) {
+        let server = Server::new(dir).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let serving = server.clone();
+        thread::spawn(move || serving.serve_tcp(listener));
+        (server, addr)
+    }
+
+    /// Waits up to five seconds for `doc` to read `expected`.
+    fn wait_for(doc: &SharedDoc, expected: &str) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while doc.value() != expected {
+            assert!(Instant::now() < deadline, "got {:?}", doc.value());
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn clients_sync_through_server() {
+        let dir = temp_path("sync");
+        let (_server, addr) = start(&dir);
+
+        let a = Client::connect_tcp(&addr, "notes", SharedDoc::new(1)).unwrap();
+        let b = Client::connect_tcp(&addr, "notes", SharedDoc::new(2)).unwrap();
+
+        a.doc().transact(|txn| txn.insert(0, "hello"));
+        wait_for(b.doc(), "hello");
+        b.doc().transact(|txn| txn.insert(5, " world"));
+        wait_for(a.doc(), "hello world");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn handshake_syncs_both_ways() {
+        let dir = temp_path("handshake");
+        let (server, addr) = start(&dir);
+        server
+            .doc("notes")
+            .unwrap()
+            .apply(remote_update(7, "server"));
+
+        let doc = SharedDoc::new(1);
+        doc.transact(|txn| txn.insert(0, "client "));
+        let client = Client::connect_tcp(&addr, "notes", doc).unwrap();
+
+        // Connecting returns once the server's state has arrived
+        assert!(client.doc().value().contains("server"));
+        let hosted = server.doc("notes").unwrap();
+        wait_for(&hosted, &client.doc().value());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn documents_are_independent() {
+        let dir = temp_path("names");
+        let (server, addr) = start(&dir);
+
+        let a = Client::connect_tcp(&addr, "a", SharedDoc::new(1)).unwrap();
+        let b = Client::connect_tcp(&addr, "b", SharedDoc::new(2)).unwrap();
+        a.doc().transact(|txn| txn.insert(0, "only a"));
+        wait_for(&server.doc("a").unwrap(), "only a");
+
+        assert_eq!(b.doc().value(), "");
+        assert_eq!(server.doc("b").unwrap().value(), "");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn documents_survive_restart() {
+        let dir = temp_path("restart");
+        {
+            let (server, addr) = start(&dir);
+            let client = Client::connect_tcp(&addr, "notes", SharedDoc::new(1)).unwrap();
+            client.doc().transact(|txn| txn.insert(0, "persisted"));
+            wait_for(&server.doc("notes").unwrap(), "persisted");
+            client.close();
+        }
+
+        let (_server, addr) = start(&dir);
+        let client = Client::connect_tcp(&addr, "notes", SharedDoc::new(2)).unwrap();
+        assert_eq!(client.doc().value(), "persisted");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serves_unix_sockets() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = temp_path("unix");
+        let server = Server::new(&dir).unwrap();
+        let socket = dir.join("sync.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let serving = server.clone();
+        thread::spawn(move || serving.serve_unix(listener));
+
+        let a = Client::connect_unix(&socket, "notes", SharedDoc::new(1)).unwrap();
+        let b = Client::connect_unix(&socket, "notes", SharedDoc::new(2)).unwrap();
+        a.doc().transact(|txn| txn.insert(0, "over unix"));
+        wait_for(b.doc(), "over unix");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        let dir = temp_path("invalid");
+        let (server, addr) = start(&dir);
+
+        for name in ["", ".hidden", "../escape", "a/b"] {
+            assert!(server.doc(name).is_err(), "{name:?}");
+        }
+        assert!(Client::connect_tcp(&addr, "../escape", SharedDoc::new(1)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn save_appends_only_the_observed_changes() {
+        let dir = temp_path("save");
+        let server = Server::new(&dir).unwrap();
+        let hosted = server.hosted("notes").unwrap();
+
+        hosted.doc.apply(remote_update(7, "hello"));
+        hosted.doc.apply(remote_update(8, "!"));
+        hosted.save().unwrap();
+        hosted.save().unwrap();
+        hosted.doc.transact(|txn| txn.delete(0, 1));
+        hosted.save().unwrap();
+
+        let mut doc = Doc::new(1);
+        let log = UpdateLog::open(dir.join("notes.log"), &mut doc).unwrap();
+        assert_eq!(log.records(), 2);
+        assert_eq!(doc.value(), hosted.doc.value());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn remote_update(client: u64, text: &str) -> crate::Update {
+        let mut doc = Doc::new(client);
+        doc.insert(0, text);
+        doc.diff(&StateVector::new())
+    }
+}
@@ -0,0 +1,431 @@
+use crate::{
+    ChangeTracker, ConflictResolver, Crdt, Doc, SequenceCrdt, StateVector, Update, YataResolver,
+};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{
+    Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+const POISONED: &str = "a transaction panicked and left the document poisoned";
+
+/// Called with a transaction's update and the origin it was started with.
+type Observer = Arc<dyn Fn(&Update, Option<u64>) + Send + Sync>;
+
+/// A cloneable, thread-safe handle to a [`Doc`].
+///
+/// Changes go through [`transact`](Self::transact), which holds write access
+/// for the whole closure, so several edits land atomically and are announced to
+/// observers as a single [`Update`]. Readers never see a transaction halfway.
+///
+/// A panic inside a transaction can leave the document half changed, with its
+/// indexes out of step with its items. Nothing of it is announced; instead the
+/// handle is poisoned, and every later transaction or read panics too, so a
+/// broken document is never served to anyone. See [`is_poisoned`](Self::is_poisoned).
+pub struct SharedDoc<R: ConflictResolver = YataResolver> {
+    inner: Arc<Inner<R>>,
+}
+
+struct Inner<R: ConflictResolver> {
+    doc: RwLock<Doc<R>>,
+    observers: Mutex<Vec<(u64, Observer)>>,
+    /// Every update gets a ticket while the document is still locked, and
+    /// observers see the updates in ticket order, so in commit order.
+    tickets: AtomicU64,
+    /// The ticket whose update is dispatched next.
+    dispatch: Mutex<u64>,
+    /// Signalled when `dispatch` moves on.
+    turn: Condvar,
+    next_observer: AtomicU64,
+}
+
+impl SharedDoc<YataResolver> {
+    pub fn new(client_id: u64) -> Self {
+        Self::from_doc(Doc::new(client_id))
+    }
+}
+
+impl<R: ConflictResolver> SharedDoc<R> {
+    pub fn from_doc(doc: Doc<R>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                doc: RwLock::new(doc),
+                observers: Mutex::new(Vec::new()),
+                tickets: AtomicU64::new(0),
+                dispatch: Mutex::new(0),
+                turn: Condvar::new(),
+                next_observer: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the document. If it changed anything,
+    /// observers are called with an update holding the changes once the
+    /// document is unlocked again.
+    ///
+    /// Observers run on the calling thread. They may read the document but must
+    /// not start a transaction on it, which would deadlock.
+    pub fn transact<T>(&self, f: impl FnOnce(&mut Transaction<'_, R>) -> T) -> T {
+        self.transact_from(None, f)
+    }
+
+    /// Like [`transact`](Self::transact), passing `origin` on to observers so
+    /// they can tell where a change came from.
+    pub(crate) fn transact_from<T>(
+        &self,
+        origin: Option<u64>,
+        f: impl FnOnce(&mut Transaction<'_, R>) -> T,
+    ) -> T {
+        // A panic drops the lock while unwinding, which poisons it before any
+        // ticket is taken, so nothing is announced
+        let (result, update) = {
+            let mut doc = self.write_lock();
+            let mut txn = Transaction::new(&mut doc);
+            let result = f(&mut txn);
+            let update = txn
+                .changes()
+                .map(|update| (self.inner.tickets.fetch_add(1, Ordering::Relaxed), update));
+            (result, update)
+        };
+
+        // The document is unlocked again, so observers can read it while
+        // another transaction waits for its turn to dispatch
+        if let Some((ticket, update)) = update {
+            self.dispatch(ticket, &update, origin);
+        }
+        result
+    }
+
+    /// Returns `true` if a transaction panicked. The document may be half
+    /// changed, so transactions and reads panic from then on.
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.doc.is_poisoned()
+    }
+
+    /// Waits until every update with an earlier ticket was dispatched, then
+    /// calls the observers with `update`.
+    fn dispatch(&self, ticket: u64, update: &Update, origin: Option<u64>) {
+        let mut next = lock(&self.inner.dispatch);
+        while *next != ticket {
+            next = self
+                .inner
+                .turn
+                .wait(next)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        drop(next);
+
+        // Hands the turn on even if an observer panics
+        let _turn = Turn(&self.inner);
+        let observers: Vec<Observer> = lock(&self.inner.observers)
+            .iter()
+            .map(|(_, observer)| Arc::clone(observer))
+            .collect();
+        for observer in observers {
+            observer(update, origin);
+        }
+    }
+
+    /// Runs `f` with shared access to the document.
+    pub fn read<T>(&self, f: impl FnOnce(&Doc<R>) -> T) -> T {
+        f(&self.read_lock())
+    }
+
+    /// Applies a remote update in its own transaction.
+    pub fn apply(&self, update: Update) {
+        self.transact(|txn| txn.apply(update));
+    }
+
+    /// Returns the changes a peer at `remote` is missing.
+    pub fn diff(&self, remote: &StateVector) -> Update {
+        self.read_lock().diff(remote)
+    }
+
+    pub fn state_vector(&self) -> StateVector {
+        self.read_lock().state_vector()
+    }
+
+    pub fn value(&self) -> String {
+        self.read_lock().value()
+    }
+
+    /// Registers a callback for the updates of every following transaction
+    /// that changes the document. Returns an id for [`unobserve`](Self::unobserve).
+    pub fn observe(&self, observer: impl Fn(&Update) + Send + Sync + 'static) -> u64 {
+        self.observe_from(move |update, _| observer(update))
+    }
+
+    /// Like [`observe`](Self::observe), also passing the transaction origin.
+    pub(crate) fn observe_from(
+        &self,
+        observer: impl Fn(&Update, Option<u64>) + Send + Sync + 'static,
+    ) -> u64 {
+        let id = self.inner.next_observer.fetch_add(1, Ordering::Relaxed);
+        lock(&self.inner.observers).push((id, Arc::new(observer)));
+        id
+    }
+
+    /// Returns a fresh id, distinct from every observer id, to use as a
+    /// transaction origin.
+    pub(crate) fn next_origin(&self) -> u64 {
+        self.inner.next_observer.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Removes an observer. Returns `false` if it wasn't registered.
+    pub fn unobserve(&self, id: u64) -> bool {
+        let mut observers = lock(&self.inner.observers);
+        let len = observers.len();
+        observers.retain(|(observer, _)| *observer != id);
+        observers.len() != len
+    }
+
+    fn read_lock(&self) -> RwLockReadGuard<'_, Doc<R>> {
+        self.inner.doc.read().expect(POISONED)
+    }
+
+    fn write_lock(&self) -> RwLockWriteGuard<'_, Doc<R>> {
+        self.inner.doc.write().expect(POISONED)
+    }
+}
+
+impl<R: ConflictResolver + Clone> SharedDoc<R> {
+    /// Returns a copy of the document as of the last finished transaction.
+    pub fn snapshot(&self) -> Doc<R> {
+        self.read_lock().clone()
+    }
+}
+
+impl<R: ConflictResolver> Clone for SharedDoc<R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+/// Lets the next update be dispatched once dropped.
+struct Turn<'a, R: ConflictResolver>(&'a Inner<R>);
+
+impl<R: ConflictResolver> Drop for Turn<'_, R> {
+    fn drop(&mut self) {
+        *lock(&self.0.dispatch) += 1;
+        self.0.turn.notify_all();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Write access to a [`SharedDoc`] for the duration of
+/// [`transact`](SharedDoc::transact). Dereferences to the [`Doc`].
+pub struct Transaction<'a, R: ConflictResolver> {
+    doc: &'a mut Doc<R>,
+    tracker: ChangeTracker,
+}
+
+impl<'a, R: ConflictResolver> Transaction<'a, R> {
+    pub(crate) fn new(doc: &'a mut Doc<R>) -> Self {
+        Self {
+            tracker: ChangeTracker::new(doc),
+            doc,
+        }
+    }
+
+    /// Returns everything that changed since the transaction started, or
+    /// `None` if nothing did.
+    pub(crate) fn changes(&self) -> Option<Update> {
+        let update = self.tracker.changes(self.doc);
+        (!update.is_empty()).then_some(update)
+    }
+}
+
+impl<R: ConflictResolver> Deref for Transaction<'_, R> {
+    type Target = Doc<R>;
+
+    fn deref(&self) -> &Doc<R> {
+        self.doc
+    }
+}
+
+impl<R: ConflictResolver> DerefMut for Transaction<'_, R> {
+    fn deref_mut(&mut self) -> &mut Doc<R> {
+        self.doc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn transaction_changes_are_visible_to_readers() {
+        let doc = SharedDoc::new(1);
+        doc.transact(|txn| {
+            txn.insert(0, "hello");
+            txn.insert(5, " world");
+        });
+
+        assert_eq!(doc.value(), "hello world");
+        assert_eq!(doc.read(|doc| doc.len()), 11);
+    }
+
+    #[test]
+    fn observers_get_one_update_per_transaction() {
+        let doc = SharedDoc::new(1);
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&updates);
+        doc.observe(move |update| received.lock().unwrap().push(update.clone()));
+
+        doc.transact(|txn| {
+            txn.insert(0, "hello");
+            txn.insert(5, "!");
+            txn.delete(0, 1);
+        });
+        doc.transact(|txn| txn.value());
+
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates.len(), 1);
+        let mut replica = Doc::new(2);
+        replica.apply(updates[0].clone());
+        assert_eq!(replica.value(), "ello!");
+    }
+
+    #[test]
+    fn observers_may_read_the_document() {
+        let doc = SharedDoc::new(1);
+        let seen = Arc::new(Mutex::new(String::new()));
+        let (reader, received) = (doc.clone(), Arc::clone(&seen));
+        doc.observe(move |_| *received.lock().unwrap() = reader.value());
+
+        doc.transact(|txn| txn.insert(0, "hi"));
+
+        assert_eq!(*seen.lock().unwrap(), "hi");
+    }
+
+    #[test]
+    fn unobserve_stops_notifications() {
+        let doc = SharedDoc::new(1);
+        let count = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&count);
+        let id = doc.observe(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+
+        doc.transact(|txn| txn.insert(0, "a"));
+        assert!(doc.unobserve(id));
+        assert!(!doc.unobserve(id));
+        doc.transact(|txn| txn.insert(0, "b"));
+
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn duplicate_remote_update_is_not_announced() {
+        let mut remote = Doc::new(2);
+        remote.insert(0, "hello");
+        let update = remote.diff(&StateVector::new());
+
+        let doc = SharedDoc::new(1);
+        let count = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&count);
+        doc.observe(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        doc.apply(update.clone());
+        doc.apply(update);
+
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        assert_eq!(doc.value(), "hello");
+    }
+
+    #[test]
+    fn snapshot_is_independent_of_later_changes() {
+        let doc = SharedDoc::new(1);
+        doc.transact(|txn| txn.insert(0, "hello"));
+        let snapshot = doc.snapshot();
+        doc.transact(|txn| txn.insert(0, ">"));
+
+        assert_eq!(snapshot.value(), "hello");
+        assert_eq!(doc.value(), ">hello");
+    }
+
+    #[test]
+    fn concurrent_updates_from_many_threads() {
+        let doc = SharedDoc::new(0);
+        let threads: Vec<_> = (1..=8)
+            .map(|client| {
+                let doc = doc.clone();
+                thread::spawn(move || {
+                    let mut remote = Doc::new(client);
+                    for _ in 0..20 {
+                        let sv = remote.state_vector();
+                        remote.insert(0, "x");
+                        doc.apply(remote.diff(&sv));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(doc.read(|doc| doc.len()), 160);
+        assert_eq!(doc.state_vector().len(), 8);
+    }
+
+    #[test]
+    fn panicking_transaction_poisons_the_document() {
+        let doc = SharedDoc::new(1);
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&updates);
+        doc.observe(move |update| received.lock().unwrap().push(update.clone()));
+
+        let other = doc.clone();
+        let result = thread::spawn(move || {
+            other.transact(|txn| {
+                txn.insert(0, "a");
+                panic!("boom");
+            })
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(doc.is_poisoned());
+        assert!(updates.lock().unwrap().is_empty());
+        let reader = doc.clone();
+        assert!(thread::spawn(move || reader.value()).join().is_err());
+        let writer = doc.clone();
+        assert!(
+            thread::spawn(move || writer.transact(|txn| txn.insert(0, "b")))
+                .join()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn observers_reading_the_document_while_another_thread_commits() {
+        let doc = SharedDoc::new(0);
+        let reader = doc.clone();
+        doc.observe(move |_| {
+            reader.value();
+        });
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let doc = doc.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        doc.transact(|txn| txn.insert(0, "x"));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(doc.read(|doc| doc.len()), 400);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{ConflictResolver, Doc};
+use std::ops::{Bound, RangeBounds};
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Number of visible characters.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns `true` if there's no visible text, even if deleted items remain.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the visible character at `pos`.
+    pub fn char_at(&self, pos: usize) -> Option<char> {
+        let entry = self.index.entries.get(self.index.find(pos))?;
+        self.items[&entry.id].content.chars().nth(pos - entry.start)
+    }
+
+    /// Returns the visible text in `range`, clamped to the document length.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> String {
+        self.chunks(range).collect()
+    }
+
+    /// Iterates over the visible text in `range` as borrowed pieces, one per
+    /// item, without building the whole string.
+    pub fn chunks(&self, range: impl RangeBounds<usize>) -> impl Iterator<Item = &str> + '_ {
+        let start = match range.start_bound() {
+            Bound::Included(&pos) => pos,
+            Bound::Excluded(&pos) => pos.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&pos) => pos.saturating_add(1),
+            Bound::Excluded(&pos) => pos,
+            Bound::Unbounded => usize::MAX,
+        }
+        .min(self.len());
+
+        let entries = if start < end {
+            &self.index.entries[self.index.find(start)..]
+        } else {
+            &[]
+        };
+
+        entries
+            .iter()
+            .take_while(move |entry| entry.start < end)
+            .map(move |entry| {
+                let content = self.items[&entry.id].content.as_str();
+                let from = start.saturating_sub(entry.start);
+                let to = (end - entry.start).min(entry.len);
+                &content[byte_offset(content, from)..byte_offset(content, to)]
+            })
+    }
+}
+
+/// Converts a character offset into a byte offset.
+fn byte_offset(content: &str, chars: usize) -> usize {
+    content
+        .char_indices()
+        .nth(chars)
+        .map_or(content.len(), |(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SequenceCrdt;
+
+    fn doc_with_items(parts: &[&str]) -> Doc {
+        let mut doc = Doc::new(1);
+        let mut pos = 0;
+        for part in parts {
+            doc.insert(pos, part);
+            pos += part.chars().count();
+        }
+        doc
+    }
+
+    #[test]
+    fn len_counts_visible_chars() {
+        let mut doc = doc_with_items(&["hello", " 🦀"]);
+        assert_eq!(doc.len(), 7);
+
+        doc.delete(0, 2);
+        assert_eq!(doc.len(), 5);
+        assert!(!doc.is_empty());
+        assert!(Doc::new(1).is_empty());
+    }
+
+    #[test]
+    fn char_at_returns_visible_chars() {
+        let mut doc = doc_with_items(&["ab", "🦀c"]);
+        doc.delete(1, 1);
+
+        assert_eq!(doc.char_at(0), Some('a'));
+        assert_eq!(doc.char_at(1), Some('🦀'));
+        assert_eq!(doc.char_at(2), Some('c'));
+        assert_eq!(doc.char_at(3), None);
+    }
+
+    #[test]
+    fn slice_within_one_item() {
+        let doc = doc_with_items(&["hello world"]);
+
+        assert_eq!(doc.slice(6..11), "world");
+        assert_eq!(doc.slice(..5), "hello");
+        assert_eq!(doc.slice(2..=3), "ll");
+    }
+
+    #[test]
+    fn slice_across_items() {
+        let doc = doc_with_items(&["hel", "lo ", "wor", "ld"]);
+
+        assert_eq!(doc.slice(2..9), "llo wor");
+        assert_eq!(doc.slice(..), "hello world");
+    }
+
+    #[test]
+    fn slice_is_clamped() {
+        let doc = doc_with_items(&["hello"]);
+
+        assert_eq!(doc.slice(3..100), "lo");
+        assert_eq!(doc.slice(10..20), "");
+        assert_eq!(doc.slice(2..=usize::MAX), "llo");
+        assert_eq!(
+            doc.slice((Bound::Excluded(usize::MAX), Bound::Unbounded)),
+            ""
+        );
+    }
+
+    #[test]
+    fn slice_with_unicode() {
+        let doc = doc_with_items(&["a🦀b", "🦀c"]);
+
+        assert_eq!(doc.slice(1..4), "🦀b🦀");
+    }
+
+    #[test]
+    fn chunks_borrow_item_content() {
+        let mut doc = doc_with_items(&["hel", "lo ", "world"]);
+        doc.delete(3, 2);
+
+        assert_eq!(
+            doc.chunks(..).collect::<Vec<_>>(),
+            vec!["hel", " ", "world"]
+        );
+        assert_eq!(doc.chunks(1..5).collect::<Vec<_>>(), vec!["el", " ", "w"]);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::encoding::{
+    Decoder, Encoder, read_counters, read_delete_set, read_item, read_mark, read_move,
+    read_registers, read_subdoc, write_counters, write_delete_set, write_item, write_mark,
+    write_move, write_registers, write_subdoc,
+};
+use crate::{ConflictResolver, Doc, ID, Item, YataResolver};
+use std::collections::HashMap;
+
+impl Doc<YataResolver> {
+    /// Loads a document from [`encode_state_as_update`](Doc::encode_state_as_update)
+    /// output. Returns `None` if the bytes aren't a valid snapshot.
+    pub fn from_state(client_id: u64, bytes: &[u8]) -> Option<Self> {
+        Self::from_state_with_resolver(client_id, YataResolver, bytes)
+    }
+}
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Serialises the complete state of the document: every item including
+    /// tombstones in list order, the delete set, formatting marks, moves,
+    /// counters, registers, sub-document references and pending items.
+    /// Loaded sub-documents aren't included: they have snapshots of their own.
+    ///
+    /// It holds the same state as `diff(&StateVector::new())`, but also records
+    /// the item order, so [`from_state`](Doc::from_state) links items back up
+    /// directly instead of integrating every one of them again.
+    pub fn encode_state_as_update(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+
+        encoder.u64(self.items.len() as u64);
+        let mut current = self.head;
+        while let Some(id) = current {
+            let item = &self.items[&id];
+            write_item(&mut encoder, item);
+            encoder.u8(item.is_deleted as u8);
+            current = item.right;
+        }
+
+        write_delete_set(&mut encoder, &self.delete_set);
+
+        encoder.u64(self.marks.len() as u64);
+        for mark in &self.marks {
+            write_mark(&mut encoder, mark);
+        }
+
+        encoder.u64(self.moves.len() as u64);
+        for mv in &self.moves {
+            write_move(&mut encoder, mv);
+        }
+
+        write_counters(&mut encoder, &self.counters);
+        write_registers(&mut encoder, &self.registers);
+
+        encoder.u64(self.subdocs.len() as u64);
+        for subdoc in self.subdoc_references() {
+            write_subdoc(&mut encoder, &subdoc);
+        }
+
+        encoder.u64(self.pending.len() as u64);
+        for item in &self.pending {
+            write_item(&mut encoder, item);
+        }
+
+        encoder.into_bytes()
+    }
+
+    /// Like [`from_state`](Doc::from_state), with a custom resolver.
+    pub fn from_state_with_resolver(client_id: u64, resolver: R, bytes: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(bytes);
+        let mut doc = Self::with_resolver(client_id, resolver);
+
+        let items = decoder.list(|d| {
+            let mut item = read_item(d)?;
+            item.is_deleted = match d.u8()? {
+                0 => false,
+                1 => true,
+                _ => return None,
+            };
+            Some(item)
+        })?;
+        doc.delete_set = read_delete_set(&mut decoder)?;
+        doc.marks = decoder.list(read_mark)?;
+        doc.moves = decoder.list(read_move)?;
+        doc.counters = read_counters(&mut decoder)?;
+        doc.registers = read_registers(&mut decoder)?;
+        doc.subdocs = decoder
+            .list(read_subdoc)?
+            .into_iter()
+            .map(|subdoc| (subdoc.id, subdoc.guid))
+            .collect();
+        doc.pending = decoder.list(read_item)?;
+        if !decoder.is_empty() {
+            return None;
+        }
+
+        // Every client's items must cover its clock range without gaps, or the
+        // state vector would claim items we don't have
+        let mut ranges: HashMap<u64, Vec<(u64, u64)>> = HashMap::new();
+        for item in &items {
+            ranges
+                .entry(item.id.client)
+                .or_default()
+                .push((item.id.clock, item.len() as u64));
+        }
+        for (client, mut ranges) in ranges {
+            ranges.sort_unstable();
+            let mut next = 0;
+            for (clock, len) in ranges {
+                if clock != next {
+                    return None;
+                }
+                next += len;
+            }
+            doc.state_vector.insert(client, next - 1);
+        }
+        doc.clock = doc
+            .state_vector
+            .get(&client_id)
+            .map_or(0, |clock| clock + 1);
+
+        // Items were written in list order, link them back up as they are
+        let mut left: Option<ID> = None;
+        for item in items {
+            let id = item.id;
+            doc.items.insert(
+                id,
+                Item {
+                    left,
+                    right: None,
+                    ..item
+                },
+            );
+            doc.starts.insert(id);
+            match left {
+                Some(lid) => doc.items.get_mut(&lid)?.right = Some(id),
+                None => doc.head = Some(id),
+            }
+            left = Some(id);
+        }
+
+        doc.reindex();
+        Some(doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Attributes, Crdt, SequenceCrdt, StateVector};
+
+    /// IDs of all items, tombstones included, in list order.
+    fn order<R: ConflictResolver>(doc: &Doc<R>) -> Vec<ID> {
+        let mut ids = Vec::new();
+        let mut current = doc.head;
+        while let Some(id) = current {
+            ids.push(id);
+            current = doc.items[&id].right;
+        }
+        ids
+    }
+
+    fn edited_doc() -> Doc {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello world");
+        let mut b = Doc::new(2);
+        b.apply(a.diff(&b.state_vector()));
+
+        a.insert(5, ",");
+        b.insert(5, "!");
+        b.delete(0, 1);
+        a.apply(b.diff(&a.state_vector()));
+
+        let mut attributes = Attributes::new();
+        attributes.insert("bold".into(), Some("true".into()));
+        a.format(0, 3, &attributes);
+        a.move_range(0, 4, 10);
+        a.increment("views", 2);
+        a.set_register("title", "Hello");
+        a.insert_subdoc(2, "page");
+        a
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        let doc = edited_doc();
+        let loaded = Doc::from_state(1, &doc.encode_state_as_update()).unwrap();
+
+        assert_eq!(loaded.value(), doc.value());
+        assert_eq!(loaded.runs(), doc.runs());
+        assert_eq!(loaded.state_vector(), doc.state_vector());
+        assert_eq!(loaded.delete_set, doc.delete_set);
+        assert_eq!(loaded.counters, doc.counters);
+        assert_eq!(loaded.registers, doc.registers);
+        assert_eq!(loaded.subdocs, doc.subdocs);
+        assert_eq!(order(&loaded), order(&doc));
+        assert_eq!(loaded.items.len(), doc.items.len());
+    }
+
+    #[test]
+    fn snapshot_of_empty_doc() {
+        let doc = Doc::new(1);
+        let loaded = Doc::from_state(1, &doc.encode_state_as_update()).unwrap();
+
+        assert_eq!(loaded.value(), "");
+        assert!(loaded.head.is_none());
+    }
+
+    #[test]
+    fn loaded_doc_resumes_own_clock() {
+        let doc = edited_doc();
+        let mut loaded = Doc::from_state(1, &doc.encode_state_as_update()).unwrap();
+
+        assert_eq!(loaded.clock, doc.clock);
+        loaded.insert(0, ">");
+        let id = ID {
+            client: 1,
+            clock: doc.clock,
+        };
+        assert_eq!(loaded.items[&id].content, ">");
+    }
+
+    #[test]
+    fn loaded_doc_keeps_syncing() {
+        let doc = edited_doc();
+        let mut loaded = Doc::from_state(3, &doc.encode_state_as_update()).unwrap();
+        let mut other = Doc::new(4);
+        other.apply(doc.diff(&StateVector::new()));
+
+        loaded.insert(0, "A");
+        other.insert(0, "B");
+        loaded.apply(other.diff(&loaded.state_vector()));
+        other.apply(loaded.diff(&other.state_vector()));
+
+        assert_eq!(loaded.value(), other.value());
+    }
+
+    #[test]
+    fn snapshot_matches_full_diff() {
+        let doc = edited_doc();
+        let loaded = Doc::from_state(5, &doc.encode_state_as_update()).unwrap();
+        let mut applied = Doc::new(5);
+        applied.apply(doc.diff(&StateVector::new()));
+
+        assert_eq!(loaded.value(), applied.value());
+        assert_eq!(order(&loaded), order(&applied));
+    }
+
+    #[test]
+    fn snapshot_keeps_pending_items() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let sv = a.state_vector();
+        a.insert(5, "!");
+        let mut b = Doc::new(2);
+        b.apply(a.diff(&sv));
+
+        let mut loaded = Doc::from_state(2, &b.encode_state_as_update()).unwrap();
+        assert_eq!(loaded.pending.len(), 1);
+
+        loaded.apply(a.diff(&loaded.state_vector()));
+        assert_eq!(loaded.value(), "hello!");
+    }
+
+    #[test]
+    fn invalid_snapshots_are_rejected() {
+        let bytes = edited_doc().encode_state_as_update();
+
+        assert!(Doc::from_state(1, &bytes[..bytes.len() - 1]).is_none());
+        assert!(Doc::from_state(1, &[1, 0, 0]).is_none());
+
+        // A lone item that doesn't start at clock 0 leaves a gap
+        let mut gap = Doc::new(1);
+        gap.insert(0, "abc");
+        gap.delete(0, 1);
+        let mut encoder = Encoder::new();
+        encoder.u64(1);
+        write_item(
+            &mut encoder,
+            &gap.items[&ID {
+                client: 1,
+                clock: 1,
+            }],
+        );
+        encoder.u8(0);
+        encoder.u64(0);
+        encoder.u64(0);
+        encoder.u64(0);
+        assert!(Doc::from_state(1, &encoder.into_bytes()).is_none());
+    }
+}
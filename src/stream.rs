@@ -0,0 +1,206 @@
+use crate::{BinaryEncode, ConflictResolver, Crdt, SharedDoc, Update};
+use futures_channel::mpsc::{self, UnboundedReceiver};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+impl<R: ConflictResolver> SharedDoc<R> {
+    /// Returns a stream yielding every following change to the document as an
+    /// encoded [`Update`]. Updates queue up without limit until the stream is
+    /// polled, so keep polling it or drop it.
+    pub fn updates(&self) -> UpdateStream<R> {
+        self.connect().0
+    }
+
+    /// Returns a sink that decodes and applies encoded updates.
+    pub fn sink(&self) -> UpdateSink<R> {
+        UpdateSink {
+            doc: self.clone(),
+            origin: self.next_origin(),
+        }
+    }
+
+    /// Returns a stream and sink pair for one connection: forward the stream to
+    /// the peer and the peer's messages into the sink. Updates that came in
+    /// through the sink are not sent back out on the stream.
+    ///
+    /// As with [`updates`](Self::updates), the stream buffers every update
+    /// until it is polled, and the sink applies on the calling thread; see
+    /// [`UpdateSink`].
+    pub fn connect(&self) -> (UpdateStream<R>, UpdateSink<R>) {
+        let origin = self.next_origin();
+        let (sender, receiver) = mpsc::unbounded();
+        let observer = self.observe_from(move |update, from| {
+            if from != Some(origin) {
+                // The stream may have been dropped already, it unsubscribes soon
+                let _ = sender.unbounded_send(update.encode());
+            }
+        });
+
+        let stream = UpdateStream {
+            doc: self.clone(),
+            observer,
+            receiver,
+        };
+        let sink = UpdateSink {
+            doc: self.clone(),
+            origin,
+        };
+        (stream, sink)
+    }
+}
+
+/// A [`Stream`] of encoded updates from a [`SharedDoc`], see
+/// [`SharedDoc::updates`]. Never ends while the document is alive.
+pub struct UpdateStream<R: ConflictResolver> {
+    doc: SharedDoc<R>,
+    observer: u64,
+    receiver: UnboundedReceiver<Vec<u8>>,
+}
+
+impl<R: ConflictResolver> Stream for UpdateStream<R> {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+impl<R: ConflictResolver> Drop for UpdateStream<R> {
+    fn drop(&mut self) {
+        self.doc.unobserve(self.observer);
+    }
+}
+
+/// A [`Sink`] applying encoded updates to a [`SharedDoc`], see
+/// [`SharedDoc::sink`]. Fails with [`io::ErrorKind::InvalidData`] on bytes
+/// that don't decode as an update.
+///
+/// Applying is synchronous: it takes the document's write lock and waits for
+/// earlier transactions to finish notifying observers, blocking the calling
+/// thread rather than yielding to the executor, and runs the document's
+/// observers before it returns. The sink is always ready, so it applies no
+/// backpressure either.
+pub struct UpdateSink<R: ConflictResolver> {
+    doc: SharedDoc<R>,
+    origin: u64,
+}
+
+impl<R: ConflictResolver> UpdateSink<R> {
+    /// Decodes and applies one encoded update. Blocks the calling thread
+    /// while it waits for the document, see [`UpdateSink`].
+    pub async fn apply(&self, bytes: &[u8]) -> io::Result<()> {
+        self.apply_now(bytes)
+    }
+
+    fn apply_now(&self, bytes: &[u8]) -> io::Result<()> {
+        let update = Update::decode(bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed update"))?;
+        self.doc
+            .transact_from(Some(self.origin), |txn| txn.apply(update));
+        Ok(())
+    }
+}
+
+impl<R: ConflictResolver> Sink<Vec<u8>> for UpdateSink<R> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, bytes: Vec<u8>) -> io::Result<()> {
+        self.apply_now(&bytes)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SequenceCrdt;
+    use futures::executor::block_on;
+    use futures::{SinkExt, StreamExt};
+
+    #[test]
+    fn stream_yields_encoded_local_updates() {
+        let doc = SharedDoc::new(1);
+        let mut updates = doc.updates();
+
+        doc.transact(|txn| txn.insert(0, "hello"));
+        doc.transact(|txn| txn.insert(5, "!"));
+
+        let replica = SharedDoc::new(2);
+        for _ in 0..2 {
+            let bytes = block_on(updates.next()).unwrap();
+            replica.apply(Update::decode(&bytes).unwrap());
+        }
+        assert_eq!(replica.value(), "hello!");
+    }
+
+    #[test]
+    fn forwarding_stream_into_sink_syncs_docs() {
+        let a = SharedDoc::new(1);
+        let b = SharedDoc::new(2);
+        let mut updates = a.updates();
+        let mut sink = b.sink();
+
+        a.transact(|txn| txn.insert(0, "hello"));
+        a.transact(|txn| txn.delete(0, 1));
+
+        block_on(async {
+            for _ in 0..2 {
+                let bytes = updates.next().await.unwrap();
+                sink.send(bytes).await.unwrap();
+            }
+        });
+        assert_eq!(b.value(), "ello");
+    }
+
+    #[test]
+    fn connection_does_not_echo_its_own_updates() {
+        let server = SharedDoc::new(0);
+        let (mut to_peer, from_peer) = server.connect();
+        let (mut to_other, _) = server.connect();
+
+        let mut peer = crate::Doc::new(1);
+        peer.insert(0, "hi");
+        block_on(from_peer.apply(&peer.diff(&Default::default()).encode())).unwrap();
+        server.transact(|txn| txn.insert(2, "!"));
+
+        // The other connection gets both changes, the peer only the server's
+        let first = block_on(to_other.next()).unwrap();
+        assert_eq!(Update::decode(&first).unwrap().items[0].content, "hi");
+        block_on(to_other.next()).unwrap();
+        let echo = block_on(to_peer.next()).unwrap();
+        assert_eq!(Update::decode(&echo).unwrap().items[0].content, "!");
+    }
+
+    #[test]
+    fn sink_rejects_malformed_updates() {
+        let doc = SharedDoc::new(1);
+        let mut sink = doc.sink();
+
+        let err = block_on(sink.send(vec![1, 2, 3])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn dropping_stream_unsubscribes() {
+        let doc = SharedDoc::new(1);
+        let updates = doc.updates();
+        let observer = updates.observer;
+        drop(updates);
+
+        assert!(!doc.unobserve(observer));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{ConflictResolver, Doc, ID, SequenceCrdt, YataResolver};
+use std::collections::HashMap;
+
+/// Content of the placeholder characters holding sub-document references.
+const EMBED: char = '\u{fffc}';
+
+/// Records that the character `id` stands for the document `guid`.
+///
+/// The parent only holds the reference. The sub-document's content has its
+/// own update stream, so it can be loaded, synced and dropped independently
+/// of the parent and of other sub-documents.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Subdoc {
+    pub id: ID,
+    pub guid: String,
+}
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Inserts a reference to the document `guid` at `pos`. It takes up one
+    /// character, which shows up as U+FFFC in the text and can be moved or
+    /// deleted like any other.
+    pub fn insert_subdoc(&mut self, pos: usize, guid: &str) {
+        let id = ID {
+            client: self.client_id,
+            clock: self.clock,
+        };
+        self.insert(pos, &EMBED.to_string());
+        self.subdocs.insert(id, guid.to_string());
+    }
+
+    /// Returns the GUIDs of the sub-documents referenced from the visible
+    /// text, in document order.
+    pub fn subdoc_guids(&self) -> Vec<&str> {
+        let mut referenced = Vec::new();
+        for item in self {
+            for offset in 0..item.len() as u64 {
+                let id = ID {
+                    client: item.id.client,
+                    clock: item.id.clock + offset,
+                };
+                referenced.extend(self.subdocs.get(&id).map(String::as_str));
+            }
+        }
+        referenced
+    }
+
+    /// Returns `true` if the visible text references the document `guid`.
+    pub fn references_subdoc(&self, guid: &str) -> bool {
+        self.subdocs
+            .iter()
+            .filter(|(_, referenced)| *referenced == guid)
+            .filter_map(|(id, _)| self.find_item(*id))
+            .any(|item| !self.items[&item].is_deleted)
+    }
+
+    /// All sub-document references, deleted ones included, ordered by ID.
+    pub(crate) fn subdoc_references(&self) -> impl Iterator<Item = Subdoc> + '_ {
+        self.subdocs.iter().map(|(id, guid)| Subdoc {
+            id: *id,
+            guid: guid.clone(),
+        })
+    }
+}
+
+/// Called with the GUID of a sub-document as it's loaded, to fill it.
+type LoadHook<R> = Box<dyn FnMut(&str, &mut Doc<R>) + Send>;
+/// Called with the GUID of a sub-document before it's unloaded.
+type UnloadHook<R> = Box<dyn FnMut(&str, &Doc<R>) + Send>;
+
+/// The sub-documents a replica has loaded, by GUID.
+///
+/// They're kept apart from the parent [`Doc`], so cloning or snapshotting the
+/// parent leaves them alone, and they use the resolver given here whatever the
+/// parent's is. [`on_load`](Self::on_load) fills a sub-document the first time
+/// it's loaded, e.g. from storage, and [`on_unload`](Self::on_unload) gets to
+/// persist it before it's dropped.
+pub struct Subdocs<R: ConflictResolver = YataResolver> {
+    client_id: u64,
+    resolver: R,
+    loaded: HashMap<String, Doc<R>>,
+    on_load: Option<LoadHook<R>>,
+    on_unload: Option<UnloadHook<R>>,
+}
+
+impl Subdocs<YataResolver> {
+    pub fn new(client_id: u64) -> Self {
+        Self::with_resolver(client_id, YataResolver)
+    }
+}
+
+impl<R: ConflictResolver + Clone> Subdocs<R> {
+    pub fn with_resolver(client_id: u64, resolver: R) -> Self {
+        Self {
+            client_id,
+            resolver,
+            loaded: HashMap::new(),
+            on_load: None,
+            on_unload: None,
+        }
+    }
+
+    /// Sets the hook filling each sub-document as it's loaded.
+    pub fn on_load(mut self, hook: impl FnMut(&str, &mut Doc<R>) + Send + 'static) -> Self {
+        self.on_load = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets the hook called with each sub-document before it's unloaded.
+    pub fn on_unload(mut self, hook: impl FnMut(&str, &Doc<R>) + Send + 'static) -> Self {
+        self.on_unload = Some(Box::new(hook));
+        self
+    }
+
+    /// Loads the sub-document `guid`, calling the load hook the first time.
+    /// Returns `None` if `parent` doesn't reference it.
+    ///
+    /// The sub-document is synced on its own: the parent's updates never
+    /// include its content.
+    pub fn load<P: ConflictResolver>(
+        &mut self,
+        parent: &Doc<P>,
+        guid: &str,
+    ) -> Option<&mut Doc<R>> {
+        if !parent.references_subdoc(guid) {
+            return None;
+        }
+
+        if !self.loaded.contains_key(guid) {
+            let mut subdoc = Doc::with_resolver(self.client_id, self.resolver.clone());
+            if let Some(hook) = &mut self.on_load {
+                hook(guid, &mut subdoc);
+            }
+            self.loaded.insert(guid.to_string(), subdoc);
+        }
+        self.loaded.get_mut(guid)
+    }
+
+    /// Returns the sub-document `guid` if it's loaded.
+    pub fn get(&self, guid: &str) -> Option<&Doc<R>> {
+        self.loaded.get(guid)
+    }
+
+    /// Like [`get`](Self::get), but mutable.
+    pub fn get_mut(&mut self, guid: &str) -> Option<&mut Doc<R>> {
+        self.loaded.get_mut(guid)
+    }
+
+    /// Returns the GUIDs of the loaded sub-documents, in no particular order.
+    pub fn loaded(&self) -> impl Iterator<Item = &str> {
+        self.loaded.keys().map(String::as_str)
+    }
+
+    /// Unloads the sub-document `guid` after passing it to the unload hook,
+    /// and hands it back. Returns `None` if it wasn't loaded.
+    pub fn unload(&mut self, guid: &str) -> Option<Doc<R>> {
+        let subdoc = self.loaded.remove(guid)?;
+        if let Some(hook) = &mut self.on_unload {
+            hook(guid, &subdoc);
+        }
+        Some(subdoc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sync;
+    use crate::{Crdt, RgaResolver, StateVector, merge_updates};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn references_sync_with_the_parent() {
+        let mut a = Doc::new(1);
+        let mut b = Doc::new(2);
+        a.insert(0, "pages: ");
+        a.insert_subdoc(7, "intro");
+        a.insert_subdoc(8, "outro");
+        sync(&mut a, &mut b);
+
+        assert_eq!(b.subdoc_guids(), ["intro", "outro"]);
+
+        b.delete(7, 1);
+        sync(&mut a, &mut b);
+        assert_eq!(a.subdoc_guids(), ["outro"]);
+        assert!(!a.references_subdoc("intro"));
+        assert!(Subdocs::new(1).load(&a, "intro").is_none());
+    }
+
+    #[test]
+    fn subdocs_load_lazily_and_sync_separately() {
+        let mut a = Doc::new(1);
+        let mut b = Doc::new(2);
+        a.insert_subdoc(0, "page");
+        sync(&mut a, &mut b);
+
+        let stored = Arc::new(Mutex::new(Vec::new()));
+        let saved = stored.clone();
+        let mut pages = Subdocs::new(1)
+            .on_load(|guid, doc| doc.insert(0, &format!("stored {guid}")))
+            .on_unload(move |guid, doc| {
+                saved.lock().unwrap().push((guid.to_string(), doc.value()))
+            });
+        let mut remote_pages = Subdocs::new(2);
+
+        let before = a.diff(&b.state_vector());
+        let page = pages.load(&a, "page").unwrap();
+        page.insert(11, " text");
+        // The parent's updates don't carry the page's content
+        assert_eq!(a.diff(&b.state_vector()), before);
+
+        let remote = remote_pages.load(&b, "page").unwrap();
+        let page = pages.get("page").unwrap();
+        remote.apply(page.diff(&remote.state_vector()));
+        assert_eq!(remote.value(), "stored page text");
+
+        // Loading again keeps what's there instead of calling the hook
+        let page = pages.load(&a, "page").unwrap();
+        assert_eq!(page.value(), "stored page text");
+
+        let unloaded = pages.unload("page").unwrap();
+        assert_eq!(unloaded.value(), "stored page text");
+        assert_eq!(
+            *stored.lock().unwrap(),
+            [("page".to_string(), "stored page text".to_string())]
+        );
+        assert_eq!(pages.loaded().count(), 0);
+        assert!(pages.unload("page").is_none());
+    }
+
+    #[test]
+    fn loaded_subdocs_stay_out_of_the_parent() {
+        let mut a = Doc::new(1);
+        a.insert_subdoc(0, "page");
+        let mut pages = Subdocs::with_resolver(1, RgaResolver);
+        pages.load(&a, "page").unwrap().insert(0, "text");
+
+        let copy = a.clone();
+        let loaded = Doc::from_state(1, &a.encode_state_as_update()).unwrap();
+        assert_eq!(copy.value(), "\u{fffc}");
+        assert!(loaded.references_subdoc("page"));
+        assert_eq!(pages.get("page").unwrap().value(), "text");
+    }
+
+    #[test]
+    fn repeated_references_are_kept_once() {
+        let mut a = Doc::new(1);
+        a.insert_subdoc(0, "page");
+        a.insert_subdoc(1, "page");
+        let update = a.diff(&StateVector::new());
+        let merged = merge_updates(&[update.clone(), update.clone()]);
+        assert_eq!(merged.subdocs, update.subdocs);
+
+        let mut b = Doc::new(2);
+        b.apply(update.clone());
+        b.apply(update);
+        assert_eq!(b.subdocs.len(), 2);
+
+        b.delete(0, 1);
+        assert!(b.references_subdoc("page"));
+        b.delete(0, 1);
+        assert!(!b.references_subdoc("page"));
+        assert!(Subdocs::new(2).load(&b, "page").is_none());
+    }
+}
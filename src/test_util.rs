@@ -0,0 +1,24 @@
+//! Helpers shared by the unit tests.
+
+use crate::{ConflictResolver, Crdt, Doc};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Returns a fresh path in the system temp directory, with nothing at it yet.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("tinycrdt-{}-{}-{}", name, std::process::id(), n));
+    let _ = fs::remove_dir_all(&path);
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Sends each document what the other is missing.
+pub(crate) fn sync<R: ConflictResolver>(a: &mut Doc<R>, b: &mut Doc<R>) {
+    let to_b = a.diff(&b.state_vector());
+    let to_a = b.diff(&a.state_vector());
+    b.apply(to_b);
+    a.apply(to_a);
+}
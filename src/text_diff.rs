@@ -0,0 +1,380 @@
+use crate::{ConflictResolver, DeltaOp, Doc, SequenceCrdt};
+
+impl<R: ConflictResolver> Doc<R> {
+    /// Replaces the visible text with `text` using as few inserts and deletes as
+    /// possible.
+    ///
+    /// Unchanged spans keep their items, and with them their IDs, so concurrent
+    /// edits and anything anchored to those characters (formatting, cursors)
+    /// survive the rewrite.
+    pub fn set_value(&mut self, text: &str) {
+        let old: Vec<char> = self.value().chars().collect();
+        let new: Vec<char> = text.chars().collect();
+
+        // Positions in the old text, so the edits can be made right to left
+        // against the index as it is now
+        let mut edits = Vec::new();
+        let mut pos = 0;
+        for op in diff_chars(&old, &new) {
+            match op {
+                DeltaOp::Retain { len, .. } => pos += len,
+                DeltaOp::Delete { len } => {
+                    edits.push((pos, op));
+                    pos += len;
+                }
+                DeltaOp::Insert { .. } => edits.push((pos, op)),
+            }
+        }
+        if edits.is_empty() {
+            return;
+        }
+
+        for (pos, op) in edits.into_iter().rev() {
+            match op {
+                DeltaOp::Insert { text, .. } => {
+                    self.insert_unindexed(pos, &text);
+                }
+                DeltaOp::Delete { len } => {
+                    self.delete_unindexed(pos, len);
+                }
+                DeltaOp::Retain { .. } => unreachable!(),
+            }
+        }
+        self.reindex();
+    }
+}
+
+/// Computes a minimal edit script turning `old` into `new` (Myers' O(ND)
+/// algorithm), expressed as unformatted delta operations.
+pub(crate) fn diff_chars(old: &[char], new: &[char]) -> Vec<DeltaOp> {
+    let mut ops = Vec::new();
+    myers(old, new, &mut ops);
+    ops
+}
+
+/// Appends a shortest edit script turning `a` into `b` to `ops`.
+///
+/// This is the linear space refinement of the algorithm: the middle snake of
+/// a shortest path splits the problem in two halves, which are solved the same
+/// way. Memory stays O(N + M) instead of a copy of the furthest reaching points
+/// per edit.
+fn myers(a: &[char], b: &[char], ops: &mut Vec<DeltaOp>) {
+    // Common prefix and suffix don't need the full algorithm
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    push_retain(ops, prefix);
+    if a.is_empty() {
+        if !b.is_empty() {
+            push(ops, DeltaOp::insert(&b.iter().collect::<String>()));
+        }
+    } else if b.is_empty() {
+        push(ops, DeltaOp::delete(a.len()));
+    } else {
+        let (x, y) = middle_snake(a, b);
+        myers(&a[..x], &b[..y], ops);
+        myers(&a[x..], &b[y..], ops);
+    }
+    push_retain(ops, suffix);
+}
+
+/// Returns a point on a shortest path from `(0, 0)` to `(a.len(), b.len())`,
+/// found by searching from both ends at once until the two searches overlap.
+///
+/// Both searches keep the furthest reaching x per diagonal `k = x - y`, the
+/// backward one in coordinates counted from the end. `a` and `b` must differ
+/// in their first and last elements, so the point is never one of the ends.
+fn middle_snake(a: &[char], b: &[char]) -> (usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let at = |k: isize| (k + max + 1) as usize;
+    let mut forward = vec![0isize; 2 * max as usize + 3];
+    let mut backward = forward.clone();
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                forward[at(k + 1)]
+            } else {
+                forward[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let start = (x, y);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[at(k)] = x;
+
+            // The same diagonal as seen by the backward search
+            let c = delta - k;
+            if odd && c.abs() < d && x + backward[at(c)] >= n {
+                return (start.0 as usize, start.1 as usize);
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                backward[at(k + 1)]
+            } else {
+                backward[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[at(k)] = x;
+
+            let c = delta - k;
+            if !odd && c.abs() <= d && x + forward[at(c)] >= n {
+                return ((n - x) as usize, (m - y) as usize);
+            }
+        }
+    }
+
+    unreachable!("the searches meet after at most half the edits each")
+}
+
+fn push_retain(ops: &mut Vec<DeltaOp>, len: usize) {
+    if len > 0 {
+        push(ops, DeltaOp::retain(len));
+    }
+}
+
+/// Appends `op`, merging it into the previous operation if they're the same kind.
+fn push(ops: &mut Vec<DeltaOp>, op: DeltaOp) {
+    match (ops.last_mut(), op) {
+        (Some(DeltaOp::Retain { len, .. }), DeltaOp::Retain { len: more, .. }) => *len += more,
+        (Some(DeltaOp::Delete { len }), DeltaOp::Delete { len: more }) => *len += more,
+        (Some(DeltaOp::Insert { text, .. }), DeltaOp::Insert { text: more, .. }) => {
+            text.push_str(&more)
+        }
+        (_, op) => ops.push(op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sync;
+    use crate::{Crdt, ID};
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    /// Applies an edit script to `old` the same way `set_value` does.
+    fn patch(old: &str, ops: &[DeltaOp]) -> String {
+        let old = chars(old);
+        let mut pos = 0;
+        let mut out = String::new();
+        for op in ops {
+            match op {
+                DeltaOp::Retain { len, .. } => {
+                    out.extend(&old[pos..pos + len]);
+                    pos += len;
+                }
+                DeltaOp::Insert { text, .. } => out.push_str(text),
+                DeltaOp::Delete { len } => pos += len,
+            }
+        }
+        out.extend(&old[pos..]);
+        out
+    }
+
+    /// Number of inserted plus deleted characters.
+    fn cost(ops: &[DeltaOp]) -> usize {
+        ops.iter()
+            .map(|op| match op {
+                DeltaOp::Retain { .. } => 0,
+                DeltaOp::Insert { text, .. } => text.chars().count(),
+                DeltaOp::Delete { len } => *len,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn diff_of_equal_strings_is_one_retain() {
+        assert_eq!(
+            diff_chars(&chars("hello"), &chars("hello")),
+            vec![DeltaOp::retain(5)]
+        );
+    }
+
+    #[test]
+    fn diff_from_and_to_empty() {
+        assert_eq!(
+            diff_chars(&chars(""), &chars("abc")),
+            vec![DeltaOp::insert("abc")]
+        );
+        assert_eq!(
+            diff_chars(&chars("abc"), &chars("")),
+            vec![DeltaOp::delete(3)]
+        );
+        assert!(diff_chars(&chars(""), &chars("")).is_empty());
+    }
+
+    #[test]
+    fn diff_replaces_middle() {
+        assert_eq!(
+            diff_chars(&chars("hello world"), &chars("hello rust world")),
+            vec![
+                DeltaOp::retain(6),
+                DeltaOp::insert("rust "),
+                DeltaOp::retain(5)
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_is_minimal() {
+        // The classic example from Myers' paper has an edit distance of 5
+        let ops = diff_chars(&chars("ABCABBA"), &chars("CBABAC"));
+
+        assert_eq!(cost(&ops), 5);
+        assert_eq!(patch("ABCABBA", &ops), "CBABAC");
+    }
+
+    #[test]
+    fn diff_patches_back_to_new_text() {
+        let cases = [
+            ("kitten", "sitting"),
+            ("the quick brown fox", "a quick brown dog"),
+            ("a🦀b🦀c", "🦀a🦀c"),
+            ("line one\nline two\n", "line zero\nline one\nline 2\n"),
+        ];
+
+        for (old, new) in cases {
+            let ops = diff_chars(&chars(old), &chars(new));
+            assert_eq!(patch(old, &ops), new, "{old:?} -> {new:?}");
+        }
+    }
+
+    #[test]
+    fn diff_is_minimal_on_random_inputs() {
+        let mut seed: u64 = 7;
+        let mut rand = move |max: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % max
+        };
+
+        for _ in 0..500 {
+            let old: Vec<char> = (0..rand(12)).map(|_| b"abc"[rand(3)] as char).collect();
+            let new: Vec<char> = (0..rand(12)).map(|_| b"abc"[rand(3)] as char).collect();
+
+            // Edit distance without substitutions, via the longest common subsequence
+            let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+            for i in 0..old.len() {
+                for j in 0..new.len() {
+                    lcs[i + 1][j + 1] = if old[i] == new[j] {
+                        lcs[i][j] + 1
+                    } else {
+                        lcs[i][j + 1].max(lcs[i + 1][j])
+                    };
+                }
+            }
+            let distance = old.len() + new.len() - 2 * lcs[old.len()][new.len()];
+
+            let ops = diff_chars(&old, &new);
+            let (old, new): (String, String) = (old.iter().collect(), new.iter().collect());
+            assert_eq!(cost(&ops), distance, "{old:?} -> {new:?}");
+            assert_eq!(patch(&old, &ops), new, "{old:?} -> {new:?}");
+        }
+    }
+
+    #[test]
+    fn set_value_rewrites_many_places_at_once() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "the quick brown fox jumps over the lazy dog");
+        doc.set_value("a quick red fox jumped over one lazy cat");
+
+        assert_eq!(doc.value(), "a quick red fox jumped over one lazy cat");
+        assert_eq!(doc.index, crate::index::PositionIndex::build(&doc));
+
+        let mut seed: u64 = 3;
+        let mut rand = move |max: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % max
+        };
+        for _ in 0..200 {
+            let text: String = (0..rand(30)).map(|_| b"ab c\n"[rand(5)] as char).collect();
+            doc.set_value(&text);
+
+            assert_eq!(doc.value(), text);
+            assert_eq!(doc.index, crate::index::PositionIndex::build(&doc));
+        }
+    }
+
+    #[test]
+    fn set_value_replaces_text() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.set_value("goodbye world!");
+
+        assert_eq!(doc.value(), "goodbye world!");
+    }
+
+    #[test]
+    fn set_value_with_same_text_is_noop() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        let clock_before = doc.clock;
+
+        doc.set_value("hello");
+
+        assert_eq!(doc.clock, clock_before);
+        assert_eq!(doc.items.len(), 1);
+    }
+
+    #[test]
+    fn set_value_keeps_ids_of_unchanged_text() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.set_value("hello, world");
+
+        // "hello" and " world" are still the original characters (1, 0..11)
+        let first = &doc.items[&ID {
+            client: 1,
+            clock: 0,
+        }];
+        assert_eq!(first.content, "hello");
+        assert!(!first.is_deleted);
+        let rest = &doc.items[&ID {
+            client: 1,
+            clock: 5,
+        }];
+        assert_eq!(rest.content, " world");
+        assert!(!rest.is_deleted);
+    }
+
+    #[test]
+    fn set_value_preserves_concurrent_edits() {
+        let mut a = Doc::new(1);
+        a.insert(0, "fn main() {}\n");
+        let mut b = Doc::new(2);
+        b.apply(a.diff(&b.state_vector()));
+
+        // An external tool rewrites the file on `a` while `b` keeps typing
+        a.set_value("fn main() {\n}\n");
+        b.insert(0, "// entry\n");
+
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.value(), b.value());
+        assert_eq!(a.value(), "// entry\nfn main() {\n}\n");
+    }
+}
@@ -0,0 +1,328 @@
+use crate::{Counter, DeleteSet, ID, Item, Mark, Move, Register, StateVector, Subdoc};
+use std::collections::{BTreeMap, HashMap};
+
+/// Everything one replica sends another to bring it up to date: the items it
+/// hasn't seen, the deletions, the formatting marks, the moves, the counters
+/// and registers, and the sub-document references.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Update {
+    pub items: Vec<Item>,
+    pub delete_set: DeleteSet,
+    pub marks: Vec<Mark>,
+    pub moves: Vec<Move>,
+    pub counters: BTreeMap<String, Counter>,
+    pub registers: BTreeMap<String, Register>,
+    pub subdocs: Vec<Subdoc>,
+}
+
+impl Update {
+    /// Returns `true` if applying the update would change nothing.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+            && self.delete_set.is_empty()
+            && self.marks.is_empty()
+            && self.moves.is_empty()
+            && self.counters.is_empty()
+            && self.registers.is_empty()
+            && self.subdocs.is_empty()
+    }
+}
+
+/// Combines several updates into one that has the same effect as applying all
+/// of them, without needing a [`Doc`](crate::Doc).
+///
+/// Items covering the same clock values (e.g. because they were sent twice, or
+/// split differently by different peers) are stored only once.
+pub fn merge_updates(updates: &[Update]) -> Update {
+    let mut by_client: HashMap<u64, Vec<&Item>> = HashMap::new();
+    for item in updates.iter().flat_map(|update| &update.items) {
+        by_client.entry(item.id.client).or_default().push(item);
+    }
+
+    let mut items = Vec::new();
+    for (_, mut client_items) in by_client {
+        // Earliest first, and the longest of several items starting at the same clock
+        client_items.sort_by_key(|item| (item.id.clock, std::cmp::Reverse(item.len())));
+
+        let mut covered = 0;
+        for item in client_items {
+            let Some(end) = item.end() else {
+                continue;
+            };
+            if end <= covered {
+                continue;
+            }
+            if item.id.clock < covered {
+                items.push(item.clone().split_off((covered - item.id.clock) as usize));
+            } else {
+                items.push(item.clone());
+            }
+            covered = end;
+        }
+    }
+    items.sort_by_key(|item| item.id);
+
+    let mut delete_set = DeleteSet::new();
+    let mut marks: Vec<Mark> = Vec::new();
+    let mut moves: Vec<Move> = Vec::new();
+    let mut counters: BTreeMap<String, Counter> = BTreeMap::new();
+    let mut registers: BTreeMap<String, Register> = BTreeMap::new();
+    let mut subdocs: BTreeMap<ID, Subdoc> = BTreeMap::new();
+    for update in updates {
+        delete_set.merge(&update.delete_set);
+        for mark in &update.marks {
+            if !marks.iter().any(|m| m.id == mark.id) {
+                marks.push(mark.clone());
+            }
+        }
+        for mv in &update.moves {
+            if !moves.iter().any(|m| m.id == mv.id) {
+                moves.push(mv.clone());
+            }
+        }
+        for subdoc in &update.subdocs {
+            subdocs.entry(subdoc.id).or_insert_with(|| subdoc.clone());
+        }
+        for (name, counter) in &update.counters {
+            counters.entry(name.clone()).or_default().merge(counter);
+        }
+        for (name, register) in &update.registers {
+            registers.entry(name.clone()).or_default().merge(register);
+        }
+    }
+
+    Update {
+        items,
+        delete_set,
+        marks,
+        moves,
+        counters,
+        registers,
+        subdocs: subdocs.into_values().collect(),
+    }
+}
+
+/// Returns the part of `update` that a peer at `remote` hasn't seen yet, like
+/// [`Crdt::diff`](crate::Crdt::diff) does for a document.
+pub fn diff_update(update: &Update, remote: &StateVector) -> Update {
+    let mut items: Vec<Item> = update
+        .items
+        .iter()
+        .filter_map(|item| unseen(item, remote))
+        .collect();
+    items.sort_by_key(|item| item.id);
+
+    Update {
+        items,
+        delete_set: update.delete_set.clone(),
+        marks: update.marks.clone(),
+        moves: update.moves.clone(),
+        counters: update.counters.clone(),
+        registers: update.registers.clone(),
+        subdocs: update.subdocs.clone(),
+    }
+}
+
+/// Returns the state a document would reach by applying `update` to an empty
+/// document: for each client, the last clock of the run of items starting at
+/// clock 0. Items after a gap would wait in `pending`, so they don't count.
+pub fn state_vector_from_update(update: &Update) -> StateVector {
+    let mut by_client: HashMap<u64, Vec<(u64, u64)>> = HashMap::new();
+    for item in update.items.iter().filter(|item| item.end().is_some()) {
+        by_client
+            .entry(item.id.client)
+            .or_default()
+            .push((item.id.clock, item.len() as u64));
+    }
+
+    let mut state_vector = StateVector::new();
+    for (client, mut ranges) in by_client {
+        ranges.sort_unstable();
+
+        let mut next = 0;
+        for (clock, len) in ranges {
+            if clock > next {
+                break;
+            }
+            next = next.max(clock.saturating_add(len));
+        }
+
+        if next > 0 {
+            state_vector.insert(client, next - 1);
+        }
+    }
+    state_vector
+}
+
+/// Returns the part of `item` with clocks past what `remote` has seen, without
+/// its local neighbour pointers, or `None` if `remote` has all of it.
+pub(crate) fn unseen(item: &Item, remote: &StateVector) -> Option<Item> {
+    let known = remote.get(&item.id.client).map_or(0, |clock| clock + 1);
+    let end = item.end()?;
+    if end <= known {
+        return None;
+    }
+
+    let mut item = if item.id.clock < known {
+        item.clone().split_off((known - item.id.clock) as usize)
+    } else {
+        item.clone()
+    };
+    item.left = None;
+    item.right = None;
+    Some(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Attributes, Crdt, Doc, ID, SequenceCrdt};
+
+    fn id(client: u64, clock: u64) -> ID {
+        ID { client, clock }
+    }
+
+    /// Records every change of `doc` made by `edit` as a separate update.
+    fn record(doc: &mut Doc, updates: &mut Vec<Update>, edit: impl FnOnce(&mut Doc)) {
+        let sv = doc.state_vector();
+        edit(doc);
+        updates.push(doc.diff(&sv));
+    }
+
+    fn edit_history() -> (Doc, Vec<Update>) {
+        let mut doc = Doc::new(1);
+        let mut updates = Vec::new();
+        record(&mut doc, &mut updates, |d| d.insert(0, "hello"));
+        record(&mut doc, &mut updates, |d| d.insert(5, " world"));
+        record(&mut doc, &mut updates, |d| d.insert(2, "XY"));
+        record(&mut doc, &mut updates, |d| d.delete(0, 1));
+        record(&mut doc, &mut updates, |d| {
+            let mut attributes = Attributes::new();
+            attributes.insert("bold".into(), Some("true".into()));
+            d.format(0, 3, &attributes);
+        });
+        (doc, updates)
+    }
+
+    #[test]
+    fn merged_updates_apply_like_the_originals() {
+        let (doc, updates) = edit_history();
+        let merged = merge_updates(&updates);
+
+        let mut replica = Doc::new(2);
+        replica.apply(merged.clone());
+
+        assert_eq!(replica.value(), doc.value());
+        assert_eq!(replica.runs(), doc.runs());
+        assert_eq!(merged.marks.len(), 1);
+    }
+
+    #[test]
+    fn merge_removes_duplicate_items() {
+        let (_, updates) = edit_history();
+        let twice: Vec<Update> = updates.iter().chain(&updates).cloned().collect();
+
+        assert_eq!(merge_updates(&twice), merge_updates(&updates));
+    }
+
+    #[test]
+    fn merge_trims_overlapping_items() {
+        let mut a = Doc::new(1);
+        a.insert(0, "hello");
+        let whole = a.diff(&StateVector::new());
+        let tail = a.diff(&StateVector::from([(1, 1)]));
+
+        let merged = merge_updates(&[tail, whole]);
+
+        assert_eq!(merged.items.len(), 1);
+        assert_eq!(merged.items[0].content, "hello");
+    }
+
+    #[test]
+    fn merge_keeps_items_after_a_gap() {
+        let (doc, updates) = edit_history();
+        let later = merge_updates(&updates[1..]);
+
+        let mut replica = Doc::new(2);
+        replica.apply(later);
+        assert_eq!(replica.value(), "");
+
+        replica.apply(updates[0].clone());
+        assert_eq!(replica.value(), doc.value());
+    }
+
+    #[test]
+    fn diff_update_drops_known_items() {
+        let (doc, updates) = edit_history();
+        let merged = merge_updates(&updates);
+
+        let diff = diff_update(&merged, &StateVector::from([(1, 6)]));
+
+        assert!(diff.items.iter().all(|item| item.id.clock >= 7));
+        assert_eq!(diff.items[0].id, id(1, 7));
+        assert_eq!(diff.delete_set, merged.delete_set);
+
+        let mut replica = Doc::new(2);
+        replica.apply(diff_update(&merged, &StateVector::new()));
+        assert_eq!(replica.value(), doc.value());
+    }
+
+    #[test]
+    fn diff_update_matches_doc_diff() {
+        let (doc, updates) = edit_history();
+        let sv = StateVector::from([(1, 3)]);
+
+        let from_update = diff_update(&merge_updates(&updates), &sv);
+        let from_doc = doc.diff(&sv);
+
+        let mut a = Doc::new(2);
+        a.apply(updates[0].clone());
+        a.apply(from_update);
+        let mut b = Doc::new(2);
+        b.apply(updates[0].clone());
+        b.apply(from_doc);
+        assert_eq!(a.value(), b.value());
+    }
+
+    #[test]
+    fn state_vector_from_update_matches_doc() {
+        let (doc, updates) = edit_history();
+
+        assert_eq!(
+            state_vector_from_update(&merge_updates(&updates)),
+            doc.state_vector()
+        );
+        assert!(state_vector_from_update(&Update::default()).is_empty());
+    }
+
+    #[test]
+    fn state_vector_from_update_stops_at_gaps() {
+        let (_, updates) = edit_history();
+        let merged = merge_updates(&[updates[0].clone(), updates[2].clone()]);
+
+        assert_eq!(
+            state_vector_from_update(&merged),
+            StateVector::from([(1, 4)])
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn update_round_trips_through_json() {
+        let (doc, updates) = edit_history();
+        let update = merge_updates(&updates);
+
+        let json = serde_json::to_string(&update).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["items"][0]["content"], update.items[0].content);
+        assert_eq!(serde_json::from_str::<Update>(&json).unwrap(), update);
+
+        let sv = serde_json::to_value(doc.state_vector()).unwrap();
+        assert_eq!(sv, serde_json::json!({ "1": 12 }));
+        assert_eq!(
+            serde_json::to_value(id(1, 7)).unwrap(),
+            serde_json::json!({ "client": 1, "clock": 7 })
+        );
+    }
+}
@@ -0,0 +1,684 @@
+use crate::encoding::{Decoder, Encoder};
+use crate::protocol::{Link, Message, invalid_data};
+use crate::server::Hosted;
+use crate::yjs;
+use crate::{BinaryEncode, ConflictResolver, Server, SharedDoc, StateVector, Update, YataResolver};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tungstenite::WebSocket;
+use tungstenite::handshake::server::{ErrorResponse, Request};
+
+const MESSAGE_SYNC: u64 = 0;
+const MESSAGE_AWARENESS: u64 = 1;
+const MESSAGE_QUERY_AWARENESS: u64 = 3;
+
+const SYNC_STEP1: u64 = 0;
+const SYNC_STEP2: u64 = 1;
+const SYNC_UPDATE: u64 = 2;
+
+/// A message of the y-websocket protocol: a varint message type, for sync
+/// messages a varint sync step, and a length-prefixed payload.
+///
+/// State vectors and updates are in Yjs' v1 encoding, so browser clients
+/// using y-websocket can connect as long as they edit nothing but plain text
+/// in `ydoc.getText("text")`. Updates holding anything else, e.g. formatting
+/// or other shared types, don't decode. Only text and deletions cross over:
+/// marks, moves, counters, registers and sub-document references are left
+/// out, and characters outside the Basic Multilingual Plane become U+FFFD.
+/// Client IDs must fit into 53 bits for JavaScript to read them.
+///
+/// Awareness payloads are passed along without being interpreted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebSocketMessage {
+    SyncStep1(StateVector),
+    SyncStep2(Update),
+    Update(Update),
+    Awareness(Vec<u8>),
+    QueryAwareness,
+}
+
+impl WebSocketMessage {
+    fn from_sync(message: Message) -> Option<Self> {
+        match message {
+            Message::SyncStep1(state_vector) => Some(Self::SyncStep1(state_vector)),
+            Message::SyncStep2(update) => Some(Self::SyncStep2(update)),
+            Message::Update(update) => Some(Self::Update(update)),
+            Message::Hello(_) => None,
+        }
+    }
+
+    fn into_sync(self) -> Option<Message> {
+        match self {
+            Self::SyncStep1(state_vector) => Some(Message::SyncStep1(state_vector)),
+            Self::SyncStep2(update) => Some(Message::SyncStep2(update)),
+            Self::Update(update) => Some(Message::Update(update)),
+            Self::Awareness(_) | Self::QueryAwareness => None,
+        }
+    }
+}
+
+impl BinaryEncode for WebSocketMessage {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        match self {
+            Self::SyncStep1(state_vector) => {
+                encoder.u64(MESSAGE_SYNC);
+                encoder.u64(SYNC_STEP1);
+                encoder.bytes(&yjs::encode_state_vector(state_vector));
+            }
+            Self::SyncStep2(update) => {
+                encoder.u64(MESSAGE_SYNC);
+                encoder.u64(SYNC_STEP2);
+                encoder.bytes(&yjs::encode_update(update));
+            }
+            Self::Update(update) => {
+                encoder.u64(MESSAGE_SYNC);
+                encoder.u64(SYNC_UPDATE);
+                encoder.bytes(&yjs::encode_update(update));
+            }
+            Self::Awareness(state) => {
+                encoder.u64(MESSAGE_AWARENESS);
+                encoder.bytes(state);
+            }
+            Self::QueryAwareness => encoder.u64(MESSAGE_QUERY_AWARENESS),
+        }
+        encoder.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(bytes);
+        let message = match decoder.u64()? {
+            MESSAGE_SYNC => match decoder.u64()? {
+                SYNC_STEP1 => Self::SyncStep1(yjs::decode_state_vector(decoder.bytes()?)?),
+                SYNC_STEP2 => Self::SyncStep2(yjs::decode_update(decoder.bytes()?)?),
+                SYNC_UPDATE => Self::Update(yjs::decode_update(decoder.bytes()?)?),
+                _ => return None,
+            },
+            MESSAGE_AWARENESS => Self::Awareness(decoder.bytes()?.to_vec()),
+            MESSAGE_QUERY_AWARENESS => Self::QueryAwareness,
+            _ => return None,
+        };
+        decoder.is_empty().then_some(message)
+    }
+}
+
+/// The latest awareness state of each WebSocket connection to a document.
+#[derive(Default)]
+pub(crate) struct Awareness {
+    next_peer: u64,
+    peers: HashMap<u64, Peer>,
+}
+
+struct Peer {
+    sender: Sender<Event>,
+    state: Option<Vec<u8>>,
+}
+
+impl Awareness {
+    /// Adds a connection. Awareness updates from other connections, starting
+    /// with their current states, are sent to `sender`. Returns the peer's id.
+    fn join(&mut self, sender: Sender<Event>) -> u64 {
+        for state in self.states() {
+            let _ = sender.send(Event::Awareness(state));
+        }
+
+        let id = self.next_peer;
+        self.next_peer += 1;
+        self.peers.insert(
+            id,
+            Peer {
+                sender,
+                state: None,
+            },
+        );
+        id
+    }
+
+    /// Records the state of `peer` and passes it on to all other connections.
+    fn update(&mut self, peer: u64, state: Vec<u8>) {
+        for (id, other) in &self.peers {
+            if *id != peer {
+                let _ = other.sender.send(Event::Awareness(state.clone()));
+            }
+        }
+        if let Some(peer) = self.peers.get_mut(&peer) {
+            peer.state = Some(state);
+        }
+    }
+
+    /// Sends the states of all other connections to `peer` again.
+    fn replay(&self, peer: u64) {
+        if let Some(to) = self.peers.get(&peer) {
+            for (id, other) in &self.peers {
+                if let (true, Some(state)) = (*id != peer, &other.state) {
+                    let _ = to.sender.send(Event::Awareness(state.clone()));
+                }
+            }
+        }
+    }
+
+    fn states(&self) -> Vec<Vec<u8>> {
+        self.peers
+            .values()
+            .filter_map(|peer| peer.state.clone())
+            .collect()
+    }
+
+    fn leave(&mut self, peer: u64) {
+        self.peers.remove(&peer);
+    }
+}
+
+impl Server {
+    /// Accepts WebSocket connections, serving each on its own thread and
+    /// skipping any that fail to be accepted. The request path names the document, as y-websocket's
+    /// `ws://host/room` URLs do.
+    ///
+    /// Awareness states are relayed between the connections of a document and
+    /// replayed to new ones. The server can't read them, so clients rely on
+    /// awareness timeouts to notice peers that went away.
+    pub fn serve_websocket(&self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming().flatten() {
+            let server = self.clone();
+            thread::spawn(move || {
+                // A broken connection only affects its own client
+                let _ = server.handle_websocket(stream);
+            });
+        }
+        Ok(())
+    }
+
+    fn handle_websocket(&self, stream: TcpStream) -> io::Result<()> {
+        let (events, received) = mpsc::channel();
+        read_into(stream.try_clone()?, events.clone());
+        let result = self.serve_socket(Socket::new(stream.try_clone()?, received), events);
+
+        // Also ends the reader thread
+        let _ = stream.shutdown(Shutdown::Both);
+        result
+    }
+
+    // The handshake callback's error type is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
+    fn serve_socket(&self, socket: Socket, events: Sender<Event>) -> io::Result<()> {
+        let mut hosted = None;
+        let mut socket = tungstenite::accept_hdr(socket, |request: &Request, response| {
+            let name = request.uri().path().trim_start_matches('/');
+            match self.hosted(name) {
+                Ok(found) => {
+                    hosted = Some(found);
+                    Ok(response)
+                }
+                Err(err) => Err(ErrorResponse::new(Some(err.to_string()))),
+            }
+        })
+        .map_err(|err| io::Error::other(err.to_string()))?;
+        let hosted = hosted.expect("accepted without a document");
+        socket.get_mut().handshaking = false;
+
+        let greeting = vec![Message::SyncStep1(hosted.doc.state_vector())];
+        let link = Link::detached(hosted.doc.clone(), greeting, events.clone());
+        let peer = lock(&hosted.awareness).join(events);
+
+        let result = pump(&mut socket, |message| {
+            on_server_message(&hosted, &link, peer, message)
+        })
+        .map(|_| ());
+
+        lock(&hosted.awareness).leave(peer);
+        link.finish();
+        result
+    }
+}
+
+fn on_server_message(
+    hosted: &Hosted,
+    link: &Link<YataResolver>,
+    peer: u64,
+    message: WebSocketMessage,
+) -> io::Result<bool> {
+    match message {
+        WebSocketMessage::Awareness(state) => lock(&hosted.awareness).update(peer, state),
+        WebSocketMessage::QueryAwareness => lock(&hosted.awareness).replay(peer),
+        message => {
+            let changes = !matches!(message, WebSocketMessage::SyncStep1(_));
+            link.handle(message.into_sync().expect("sync message"))?;
+            if changes {
+                hosted.save()?;
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Keeps a [`SharedDoc`] in sync with a document served by
+/// [`Server::serve_websocket`], and exchanges awareness states with the other
+/// clients of that document.
+pub struct WebSocketClient<R: ConflictResolver = YataResolver> {
+    doc: SharedDoc<R>,
+    events: Sender<Event>,
+    received: Mutex<Receiver<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<R: ConflictResolver + Send + Sync + 'static> WebSocketClient<R> {
+    /// Connects `doc` to the document called `name` on the server at `addr`
+    /// (`host:port`), i.e. to `ws://{addr}/{name}`.
+    ///
+    /// Returns once the server has sent its state, so `doc` already holds the
+    /// server's content.
+    pub fn connect(addr: &str, name: &str, doc: SharedDoc<R>) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let (events, incoming) = mpsc::channel();
+        read_into(stream.try_clone()?, events.clone());
+
+        let socket = Socket::new(stream.try_clone()?, incoming);
+        let connected = tungstenite::client(format!("ws://{addr}/{name}"), socket)
+            .map_err(|err| io::Error::other(err.to_string()));
+        let mut socket = match connected {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                let _ = stream.shutdown(Shutdown::Both);
+                return Err(err);
+            }
+        };
+        socket.get_mut().handshaking = false;
+
+        let greeting = vec![Message::SyncStep1(doc.state_vector())];
+        let link = Link::detached(doc.clone(), greeting, events.clone());
+        let (received_in, received) = mpsc::channel();
+
+        // Wait for the server's half of the handshake
+        let handshake = pump(&mut socket, |message| {
+            let synced = matches!(message, WebSocketMessage::SyncStep2(_));
+            on_client_message(&link, &received_in, message)?;
+            Ok(synced)
+        });
+        let handshake = handshake.and_then(|synced| {
+            synced.then_some(()).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed during handshake",
+                )
+            })
+        });
+        if let Err(err) = handshake {
+            link.finish();
+            let _ = stream.shutdown(Shutdown::Both);
+            return Err(err);
+        }
+
+        let thread = thread::spawn(move || {
+            let _ = pump(&mut socket, |message| {
+                on_client_message(&link, &received_in, message).map(|_| false)
+            });
+            link.finish();
+            let _ = stream.shutdown(Shutdown::Both);
+        });
+
+        Ok(Self {
+            doc,
+            events,
+            received: Mutex::new(received),
+            thread: Some(thread),
+        })
+    }
+}
+
+fn on_client_message<R: ConflictResolver>(
+    link: &Link<R>,
+    received: &Sender<Vec<u8>>,
+    message: WebSocketMessage,
+) -> io::Result<()> {
+    match message {
+        WebSocketMessage::Awareness(state) => {
+            let _ = received.send(state);
+        }
+        // Our state is sent whenever it changes, there is nothing to repeat
+        WebSocketMessage::QueryAwareness => {}
+        message => link.handle(message.into_sync().expect("sync message"))?,
+    }
+    Ok(())
+}
+
+impl<R: ConflictResolver> WebSocketClient<R> {
+    pub fn doc(&self) -> &SharedDoc<R> {
+        &self.doc
+    }
+
+    /// Sends an awareness update to the other clients of the document.
+    pub fn send_awareness(&self, state: Vec<u8>) {
+        let _ = self.events.send(Event::Awareness(state));
+    }
+
+    /// Waits up to `timeout` for an awareness update from another client.
+    pub fn recv_awareness(&self, timeout: Duration) -> Option<Vec<u8>> {
+        lock(&self.received).recv_timeout(timeout).ok()
+    }
+
+    /// Sends any changes not yet written and disconnects. Dropping the client
+    /// does the same.
+    pub fn close(self) {}
+}
+
+impl<R: ConflictResolver> Drop for WebSocketClient<R> {
+    fn drop(&mut self) {
+        // Queued behind whatever is still to be sent
+        let _ = self.events.send(Event::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Something a connection's thread has to deal with.
+enum Event {
+    /// Bytes arrived from the peer.
+    Received(Vec<u8>),
+    /// The peer closed the connection, or reading from it failed.
+    Closed,
+    /// A sync message to send.
+    Sync(Message),
+    /// An awareness state to send.
+    Awareness(Vec<u8>),
+    /// Close the connection once everything queued before has been sent.
+    Stop,
+}
+
+impl From<Message> for Event {
+    fn from(message: Message) -> Self {
+        Self::Sync(message)
+    }
+}
+
+/// The stream a [`WebSocket`] runs on: writes go straight to the connection,
+/// reads are served from the bytes a reader thread passed on.
+///
+/// Once the handshake is done, reading past those bytes would block. The
+/// connection's thread then waits for the next [`Event`], which wakes it for
+/// incoming and outgoing messages alike.
+struct Socket {
+    stream: TcpStream,
+    events: Receiver<Event>,
+    buffer: VecDeque<u8>,
+    closed: bool,
+    /// Reads wait for bytes during the handshake, which tungstenite runs to
+    /// completion.
+    handshaking: bool,
+}
+
+impl Socket {
+    fn new(stream: TcpStream, events: Receiver<Event>) -> Self {
+        Self {
+            stream,
+            events,
+            buffer: VecDeque::new(),
+            closed: false,
+            handshaking: true,
+        }
+    }
+
+    /// Waits for the next event. A connection whose every sender is gone has
+    /// nothing left to do.
+    fn next_event(&self) -> Event {
+        self.events.recv().unwrap_or(Event::Stop)
+    }
+
+    fn receive(&mut self, event: Event) {
+        match event {
+            Event::Received(bytes) => self.buffer.extend(bytes),
+            Event::Closed => self.closed = true,
+            // Nothing else is sent before the handshake is done
+            _ => {}
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.handshaking && self.buffer.is_empty() && !self.closed {
+            let event = self.next_event();
+            if matches!(event, Event::Stop) {
+                self.closed = true;
+            }
+            self.receive(event);
+        }
+
+        if self.buffer.is_empty() && !self.closed {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.buffer.read(buf)
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Passes everything read from `stream` on to `events` on a thread of its own,
+/// until the connection is closed or shut down.
+fn read_into(mut stream: TcpStream, events: Sender<Event>) {
+    thread::spawn(move || {
+        let mut buf = vec![0; 16 << 10];
+        loop {
+            let event = match stream.read(&mut buf) {
+                Ok(0) | Err(_) => Event::Closed,
+                Ok(n) => Event::Received(buf[..n].to_vec()),
+            };
+            let closed = matches!(event, Event::Closed);
+            if events.send(event).is_err() || closed {
+                break;
+            }
+        }
+    });
+}
+
+/// Runs a connection: passes incoming messages to `on_message` until it
+/// returns `true`, and sends queued sync messages and awareness states, until
+/// the peer disconnects or [`Event::Stop`] comes up. Returns whether
+/// `on_message` ended it.
+///
+/// The thread sleeps until there is something to do.
+fn pump(
+    socket: &mut WebSocket<Socket>,
+    mut on_message: impl FnMut(WebSocketMessage) -> io::Result<bool>,
+) -> io::Result<bool> {
+    loop {
+        // Everything that arrived so far
+        loop {
+            match socket.read() {
+                Ok(tungstenite::Message::Binary(bytes)) => {
+                    let message = WebSocketMessage::decode(&bytes)
+                        .ok_or_else(|| invalid_data("malformed message"))?;
+                    if on_message(message)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(tungstenite::Message::Close(_)) => {
+                    let _ = socket.flush();
+                    return Ok(false);
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                    break;
+                }
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    return Ok(false);
+                }
+                Err(err) => return Err(into_io(err)),
+            }
+        }
+
+        let outgoing = match socket.get_ref().next_event() {
+            Event::Sync(message) => WebSocketMessage::from_sync(message),
+            Event::Awareness(state) => Some(WebSocketMessage::Awareness(state)),
+            Event::Stop => {
+                let _ = socket.close(None);
+                let _ = socket.flush();
+                return Ok(false);
+            }
+            event => {
+                socket.get_mut().receive(event);
+                None
+            }
+        };
+        if let Some(message) = outgoing {
+            socket
+                .send(tungstenite::Message::Binary(message.encode().into()))
+                .map_err(|err| io::Error::other(err.to_string()))?;
+        }
+    }
+}
+
+fn into_io(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        err => io::Error::other(err.to_string()),
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use crate::{Crdt, Doc, SequenceCrdt};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    /// Starts a WebSocket server on a free localhost port.
+    fn start(dir: &PathBuf) -> (Server, String) {
+        let server = Server::new(dir).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let serving = server.clone();
+        thread::spawn(move || serving.serve_websocket(listener));
+        (server, addr)
+    }
+
+    /// Waits up to five seconds for `doc` to read `expected`.
+    fn wait_for(doc: &SharedDoc, expected: &str) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while doc.value() != expected {
+            assert!(Instant::now() < deadline, "got {:?}", doc.value());
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn framing_matches_y_websocket() {
+        assert_eq!(
+            WebSocketMessage::SyncStep1(StateVector::new()).encode(),
+            [0, 0, 1, 0]
+        );
+        assert_eq!(
+            WebSocketMessage::Awareness(vec![7, 8]).encode(),
+            [1, 2, 7, 8]
+        );
+        assert_eq!(WebSocketMessage::QueryAwareness.encode(), [3]);
+
+        // A Yjs client with ID 1 sending its SyncStep2 after typing "a"
+        let mut doc = Doc::new(1);
+        doc.insert(0, "a");
+        let yjs = [
+            0, 1, 14, 1, 1, 1, 0, 4, 1, 4, b't', b'e', b'x', b't', 1, b'a', 0,
+        ];
+        let update = doc.diff(&StateVector::new());
+        assert_eq!(WebSocketMessage::SyncStep2(update.clone()).encode(), yjs);
+        assert_eq!(
+            WebSocketMessage::decode(&yjs),
+            Some(WebSocketMessage::SyncStep2(update))
+        );
+        assert_eq!(
+            WebSocketMessage::decode(&[0, 0, 3, 1, 1, 1]),
+            Some(WebSocketMessage::SyncStep1(StateVector::from([(1, 0)])))
+        );
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello");
+        let update = doc.diff(&StateVector::new());
+        let messages = [
+            WebSocketMessage::SyncStep1(StateVector::from([(1, 4)])),
+            WebSocketMessage::SyncStep2(update.clone()),
+            WebSocketMessage::Update(update),
+            WebSocketMessage::Awareness(b"{\"cursor\":3}".to_vec()),
+            WebSocketMessage::QueryAwareness,
+        ];
+
+        for message in messages {
+            assert_eq!(WebSocketMessage::decode(&message.encode()), Some(message));
+        }
+        assert!(WebSocketMessage::decode(&[0, 5, 0]).is_none());
+        assert!(WebSocketMessage::decode(&[2]).is_none());
+    }
+
+    #[test]
+    fn clients_sync_over_websocket() {
+        let dir = temp_path("sync");
+        let (server, addr) = start(&dir);
+        server
+            .doc("notes")
+            .unwrap()
+            .transact(|txn| txn.insert(0, "hi"));
+
+        let a = WebSocketClient::connect(&addr, "notes", SharedDoc::new(1)).unwrap();
+        let b = WebSocketClient::connect(&addr, "notes", SharedDoc::new(2)).unwrap();
+        assert_eq!(a.doc().value(), "hi");
+
+        a.doc().transact(|txn| txn.insert(2, " there"));
+        wait_for(b.doc(), "hi there");
+        b.doc().transact(|txn| txn.delete(0, 3));
+        wait_for(a.doc(), "there");
+
+        a.close();
+        b.close();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn awareness_is_relayed_and_replayed() {
+        let dir = temp_path("awareness");
+        let (_server, addr) = start(&dir);
+
+        let a = WebSocketClient::connect(&addr, "notes", SharedDoc::new(1)).unwrap();
+        let b = WebSocketClient::connect(&addr, "notes", SharedDoc::new(2)).unwrap();
+        a.send_awareness(b"a is here".to_vec());
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(b.recv_awareness(timeout).unwrap(), b"a is here");
+
+        // A client joining later gets the current states right away
+        let c = WebSocketClient::connect(&addr, "notes", SharedDoc::new(3)).unwrap();
+        assert_eq!(c.recv_awareness(timeout).unwrap(), b"a is here");
+        assert!(a.recv_awareness(Duration::from_millis(50)).is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_room_is_rejected() {
+        let dir = temp_path("invalid");
+        let (_server, addr) = start(&dir);
+
+        assert!(WebSocketClient::connect(&addr, ".hidden", SharedDoc::new(1)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,388 @@
+use crate::encoding::{
+    Decoder, Encoder, read_state_vector, read_update, write_state_vector, write_update,
+};
+use crate::server::check_name;
+use crate::{BinaryEncode, Crdt, Doc, StateVector, Transaction, Update, UpdateLog, YataResolver};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::hash::BuildHasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A message of the workspace sync protocol, which syncs every document of a
+/// [`Workspace`] over a single connection.
+///
+/// Both sides open with [`SyncStep1`](WorkspaceMessage::SyncStep1) holding
+/// the state vector of each of their documents, and answer the other's with
+/// [`SyncStep2`](WorkspaceMessage::SyncStep2) holding what it is missing,
+/// including documents it doesn't have at all. After that, every change is
+/// sent as an [`Update`](WorkspaceMessage::Update) of the document it belongs
+/// to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkspaceMessage {
+    SyncStep1(BTreeMap<String, StateVector>),
+    SyncStep2(BTreeMap<String, Update>),
+    Update(String, Update),
+}
+
+/// Named documents sharing one client ID, synced together and optionally
+/// persisted together in a directory.
+///
+/// The workspace doesn't do any I/O for syncing: pass what the peer sends to
+/// [`handle`](Self::handle), and send it the messages returned by
+/// [`sync_step1`](Self::sync_step1), [`handle`](Self::handle) and
+/// [`transact`](Self::transact).
+#[derive(Debug)]
+pub struct Workspace {
+    client_id: u64,
+    docs: BTreeMap<String, Doc>,
+    /// The directory the workspace is persisted in, and the log of each
+    /// document saved so far.
+    storage: Option<(PathBuf, HashMap<String, UpdateLog>)>,
+}
+
+impl Workspace {
+    /// Creates an empty workspace that lives in memory only.
+    pub fn new(client_id: u64) -> Self {
+        Self {
+            client_id,
+            docs: BTreeMap::new(),
+            storage: None,
+        }
+    }
+
+    /// Opens the workspace persisted in `dir`, creating it if needed, and
+    /// loads every document in it.
+    ///
+    /// Every open picks a fresh random client ID, which all documents share
+    /// until the workspace is dropped. Edits sent to peers but not yet saved
+    /// when the process stops are gone from the directory, so reusing the
+    /// earlier ID would hand out the same IDs again for different content.
+    /// The directory must not be opened by two processes at once.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let client_id = random_client_id();
+
+        let mut docs = BTreeMap::new();
+        let mut logs = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "log") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if check_name(name).is_err() {
+                continue;
+            }
+
+            let mut doc = Doc::new(client_id);
+            let log = UpdateLog::open(&path, &mut doc)?;
+            docs.insert(name.to_owned(), doc);
+            logs.insert(name.to_owned(), log);
+        }
+
+        Ok(Self {
+            client_id,
+            docs,
+            storage: Some((dir, logs)),
+        })
+    }
+
+    /// The client ID every document of the workspace edits with.
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    /// Returns the names of the documents, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.docs.keys().map(String::as_str)
+    }
+
+    pub fn doc(&self, name: &str) -> Option<&Doc> {
+        self.docs.get(name)
+    }
+
+    /// Runs `f` on the document called `name`, creating it on first use.
+    /// Returns what `f` returned, along with the message to send the peer if
+    /// anything changed.
+    ///
+    /// Names follow the same rules as for a [`Server`](crate::Server).
+    pub fn transact<T>(
+        &mut self,
+        name: &str,
+        f: impl FnOnce(&mut Transaction<'_, YataResolver>) -> T,
+    ) -> io::Result<(T, Option<WorkspaceMessage>)> {
+        let doc = self.doc_mut(name)?;
+        let mut txn = Transaction::new(doc);
+        let result = f(&mut txn);
+        let message = txn
+            .changes()
+            .map(|update| WorkspaceMessage::Update(name.to_owned(), update));
+        Ok((result, message))
+    }
+
+    /// Returns the message that starts syncing with a peer.
+    pub fn sync_step1(&self) -> WorkspaceMessage {
+        WorkspaceMessage::SyncStep1(
+            self.docs
+                .iter()
+                .map(|(name, doc)| (name.clone(), doc.state_vector()))
+                .collect(),
+        )
+    }
+
+    /// Handles a message from the peer and returns the reply to send, if any.
+    ///
+    /// Updates for documents the workspace doesn't have yet create them.
+    /// Fails, leaving the workspace unchanged, if the message names a
+    /// document with an invalid name.
+    pub fn handle(&mut self, message: WorkspaceMessage) -> io::Result<Option<WorkspaceMessage>> {
+        match message {
+            WorkspaceMessage::SyncStep1(remote) => {
+                let empty = StateVector::new();
+                let updates = self
+                    .docs
+                    .iter()
+                    .map(|(name, doc)| (name.clone(), doc.diff(remote.get(name).unwrap_or(&empty))))
+                    .filter(|(_, update)| !update.is_empty())
+                    .collect();
+                Ok(Some(WorkspaceMessage::SyncStep2(updates)))
+            }
+            WorkspaceMessage::SyncStep2(updates) => {
+                for name in updates.keys() {
+                    check_name(name)?;
+                }
+                for (name, update) in updates {
+                    self.doc_mut(&name)?.apply(update);
+                }
+                Ok(None)
+            }
+            WorkspaceMessage::Update(name, update) => {
+                self.doc_mut(&name)?.apply(update);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Saves what changed in every document since the last save. Does nothing
+    /// for a workspace that lives in memory only.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some((dir, logs)) = &mut self.storage else {
+            return Ok(());
+        };
+
+        for (name, doc) in &self.docs {
+            let log = match logs.get_mut(name) {
+                Some(log) => log,
+                None => {
+                    // Opening replays the log, which is empty for a new document
+                    let path = dir.join(format!("{name}.log"));
+                    let log = UpdateLog::open(path, &mut Doc::new(self.client_id))?;
+                    logs.entry(name.clone()).or_insert(log)
+                }
+            };
+            log.save(doc)?;
+        }
+        Ok(())
+    }
+
+    /// The directory the workspace is persisted in, if any.
+    pub fn dir(&self) -> Option<&Path> {
+        self.storage.as_ref().map(|(dir, _)| dir.as_path())
+    }
+
+    fn doc_mut(&mut self, name: &str) -> io::Result<&mut Doc> {
+        check_name(name)?;
+        let client_id = self.client_id;
+        Ok(self
+            .docs
+            .entry(name.to_owned())
+            .or_insert_with(|| Doc::new(client_id)))
+    }
+}
+
+/// Picks a client ID from the randomly seeded hasher of the standard library.
+fn random_client_id() -> u64 {
+    RandomState::new().hash_one(SystemTime::now())
+}
+
+impl BinaryEncode for WorkspaceMessage {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        match self {
+            WorkspaceMessage::SyncStep1(state_vectors) => {
+                encoder.u8(0);
+                encoder.u64(state_vectors.len() as u64);
+                for (name, state_vector) in state_vectors {
+                    encoder.string(name);
+                    write_state_vector(&mut encoder, state_vector);
+                }
+            }
+            WorkspaceMessage::SyncStep2(updates) => {
+                encoder.u8(1);
+                encoder.u64(updates.len() as u64);
+                for (name, update) in updates {
+                    encoder.string(name);
+                    write_update(&mut encoder, update);
+                }
+            }
+            WorkspaceMessage::Update(name, update) => {
+                encoder.u8(2);
+                encoder.string(name);
+                write_update(&mut encoder, update);
+            }
+        }
+        encoder.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(bytes);
+        let message = match decoder.u8()? {
+            0 => WorkspaceMessage::SyncStep1(
+                decoder
+                    .list(|d| Some((d.string()?, read_state_vector(d)?)))?
+                    .into_iter()
+                    .collect(),
+            ),
+            1 => WorkspaceMessage::SyncStep2(
+                decoder
+                    .list(|d| Some((d.string()?, read_update(d)?)))?
+                    .into_iter()
+                    .collect(),
+            ),
+            2 => WorkspaceMessage::Update(decoder.string()?, read_update(&mut decoder)?),
+            _ => return None,
+        };
+        decoder.is_empty().then_some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SequenceCrdt;
+    use crate::test_util::temp_path;
+
+    /// Runs the handshake between `a` and `b`, sending every message through
+    /// its binary encoding.
+    fn sync(a: &mut Workspace, b: &mut Workspace) {
+        let send = |message: WorkspaceMessage| {
+            WorkspaceMessage::decode(&message.encode()).expect("message should round trip")
+        };
+        let step1_a = send(a.sync_step1());
+        let step1_b = send(b.sync_step1());
+        let step2_b = b.handle(step1_a).unwrap().unwrap();
+        let step2_a = a.handle(step1_b).unwrap().unwrap();
+        assert_eq!(a.handle(send(step2_b)).unwrap(), None);
+        assert_eq!(b.handle(send(step2_a)).unwrap(), None);
+    }
+
+    #[test]
+    fn handshake_syncs_every_document() {
+        let mut a = Workspace::new(1);
+        let mut b = Workspace::new(2);
+        a.transact("notes", |doc| doc.insert(0, "from a")).unwrap();
+        a.transact("shared", |doc| doc.insert(0, "a")).unwrap();
+        b.transact("shared", |doc| doc.insert(0, "b")).unwrap();
+        b.transact("todo", |doc| doc.insert(0, "from b")).unwrap();
+
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.names().collect::<Vec<_>>(), ["notes", "shared", "todo"]);
+        assert_eq!(b.names().collect::<Vec<_>>(), ["notes", "shared", "todo"]);
+        for name in ["notes", "shared", "todo"] {
+            assert_eq!(a.doc(name).unwrap().value(), b.doc(name).unwrap().value());
+        }
+        assert_eq!(b.doc("notes").unwrap().value(), "from a");
+    }
+
+    #[test]
+    fn changes_are_sent_per_document() {
+        let mut a = Workspace::new(1);
+        let mut b = Workspace::new(2);
+        sync(&mut a, &mut b);
+
+        let ((), message) = a.transact("page", |doc| doc.insert(0, "hi")).unwrap();
+        let Some(WorkspaceMessage::Update(name, update)) = message.clone() else {
+            panic!("expected an update, got {message:?}");
+        };
+        assert_eq!(name, "page");
+        assert_eq!(update.items.len(), 1);
+        b.handle(message.unwrap()).unwrap();
+        assert_eq!(b.doc("page").unwrap().value(), "hi");
+
+        let (value, message) = a.transact("page", |doc| doc.value()).unwrap();
+        assert_eq!(value, "hi");
+        assert_eq!(message, None);
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        let mut workspace = Workspace::new(1);
+
+        assert!(workspace.transact("../escape", |_| ()).is_err());
+        let message = WorkspaceMessage::Update("".into(), Update::default());
+        assert!(workspace.handle(message).is_err());
+        assert_eq!(workspace.names().count(), 0);
+    }
+
+    #[test]
+    fn workspace_is_persisted() {
+        let dir = temp_path("persist");
+        let client_id = {
+            let mut workspace = Workspace::open(&dir).unwrap();
+            workspace.transact("a", |doc| doc.insert(0, "one")).unwrap();
+            workspace.save().unwrap();
+            workspace.transact("a", |doc| doc.insert(3, "!")).unwrap();
+            workspace.transact("b", |doc| doc.insert(0, "two")).unwrap();
+            workspace.save().unwrap();
+            workspace.client_id()
+        };
+
+        let mut workspace = Workspace::open(&dir).unwrap();
+        assert_ne!(workspace.client_id(), client_id);
+        assert_eq!(workspace.dir(), Some(dir.as_path()));
+        assert_eq!(workspace.doc("a").unwrap().value(), "one!");
+        assert_eq!(workspace.doc("b").unwrap().value(), "two");
+
+        workspace.transact("a", |doc| doc.insert(0, ">")).unwrap();
+        assert_eq!(workspace.doc("a").unwrap().value(), ">one!");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn edits_sent_before_a_crash_are_not_reused() {
+        let dir = temp_path("crash");
+        let mut peer = Workspace::new(1);
+        {
+            let mut workspace = Workspace::open(&dir).unwrap();
+            workspace
+                .transact("a", |doc| doc.insert(0, "saved "))
+                .unwrap();
+            workspace.save().unwrap();
+            sync(&mut workspace, &mut peer);
+            let ((), message) = workspace
+                .transact("a", |doc| doc.insert(6, "sent"))
+                .unwrap();
+            peer.handle(message.unwrap()).unwrap();
+            // Dropped without saving, as in a crash
+        }
+
+        let mut workspace = Workspace::open(&dir).unwrap();
+        let ((), message) = workspace
+            .transact("a", |doc| doc.insert(6, "again"))
+            .unwrap();
+        peer.handle(message.unwrap()).unwrap();
+        sync(&mut workspace, &mut peer);
+
+        let value = workspace.doc("a").unwrap().value();
+        assert_eq!(value, peer.doc("a").unwrap().value());
+        assert!(value.contains("sent") && value.contains("again"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,837 @@
+use crate::encoding::{
+    Decoder, Encoder, read_delete_set, read_state_vector, read_update, write_delete_set,
+    write_state_vector, write_update,
+};
+use crate::{BinaryEncode, Crdt, DeleteSet, Doc, ID, SequenceCrdt, StateVector, Update};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Stands in for a child node in the sequence of its parent's children.
+const SLOT: char = '\u{fffc}';
+
+/// What a node of an [`XmlFragment`] is.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum XmlKind {
+    /// An element with the given name, holding attributes and child nodes.
+    Element(String),
+    /// A text node, holding a [`Doc`].
+    Text,
+}
+
+/// Records that a node was created.
+///
+/// A client numbers its nodes from 0, so node IDs can be tracked with a
+/// [`StateVector`] like item IDs. `slot` is the character standing in for the
+/// node among the children of `parent`, or of the top level for `None`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XmlNode {
+    pub id: ID,
+    pub parent: Option<ID>,
+    pub slot: ID,
+    pub kind: XmlKind,
+}
+
+/// Sets attribute `key` of the element `node`, or removes it for a `None`
+/// value.
+///
+/// As for [`Mark`](crate::Mark)s, `id.clock` is a Lamport timestamp: when the
+/// same attribute is set concurrently, the highest `(clock, client)` wins.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XmlAttribute {
+    pub id: ID,
+    pub node: ID,
+    pub key: String,
+    pub value: Option<String>,
+}
+
+/// Everything an [`XmlFragment`] has seen: the nodes, the state and deletions
+/// of the children or text of each node still in the tree (the top level for
+/// `None`), and the latest attribute clock of each client.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XmlStateVector {
+    pub nodes: StateVector,
+    pub docs: Vec<(Option<ID>, StateVector, DeleteSet)>,
+    pub attributes: StateVector,
+}
+
+/// Everything one fragment sends another to bring it up to date: the nodes it
+/// hasn't seen, changes to the children or text of each node still in the
+/// tree, and the attributes written since.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XmlUpdate {
+    pub nodes: Vec<XmlNode>,
+    pub docs: Vec<(Option<ID>, Update)>,
+    pub attributes: Vec<XmlAttribute>,
+}
+
+/// A replicated tree of elements and text nodes, for ProseMirror-style nested
+/// documents.
+///
+/// Nodes are addressed by ID, with `None` standing for the top level. The
+/// children of an element are a [`Doc`] holding one placeholder character per
+/// child, so concurrently inserted children are ordered like concurrently typed
+/// text. Text nodes are a [`Doc`] as well, edited through
+/// [`text_mut`](Self::text_mut).
+#[derive(Debug, Clone)]
+pub struct XmlFragment {
+    pub client_id: u64,
+    nodes: HashMap<ID, XmlNode>,
+    /// The children of the top level and of every element, and the text of
+    /// every text node.
+    docs: HashMap<Option<ID>, Doc>,
+    /// The node behind each slot, keyed by parent and slot.
+    slots: HashMap<(Option<ID>, ID), ID>,
+    attributes: HashMap<(ID, String), XmlAttribute>,
+    /// The highest attribute clock seen from each client, overwritten
+    /// attributes included.
+    attribute_state: StateVector,
+}
+
+impl XmlFragment {
+    pub fn new(client_id: u64) -> Self {
+        Self {
+            client_id,
+            nodes: HashMap::new(),
+            docs: HashMap::from([(None, Doc::new(client_id))]),
+            slots: HashMap::new(),
+            attributes: HashMap::new(),
+            attribute_state: StateVector::new(),
+        }
+    }
+
+    /// Inserts an empty element named `name` as child `index` of `parent`.
+    /// Returns its ID, or `None` if `parent` isn't an element or `name` isn't
+    /// a valid XML name.
+    pub fn insert_element(&mut self, parent: Option<ID>, index: usize, name: &str) -> Option<ID> {
+        if !is_name(name) {
+            return None;
+        }
+        self.create(parent, index, XmlKind::Element(name.to_string()))
+    }
+
+    /// Inserts a text node holding `text` as child `index` of `parent`.
+    /// Returns its ID, or `None` if `parent` isn't an element.
+    pub fn insert_text(&mut self, parent: Option<ID>, index: usize, text: &str) -> Option<ID> {
+        let id = self.create(parent, index, XmlKind::Text)?;
+        self.docs
+            .get_mut(&Some(id))
+            .expect("node should have a doc")
+            .insert(0, text);
+        Some(id)
+    }
+
+    /// Removes `len` children of `parent` starting at `index`, along with
+    /// everything inside them.
+    pub fn remove(&mut self, parent: Option<ID>, index: usize, len: usize) {
+        if self.is_container(parent) {
+            self.docs
+                .get_mut(&parent)
+                .expect("container should have a doc")
+                .delete(index, len);
+        }
+    }
+
+    /// Returns the children of `parent` in order. Children whose node hasn't
+    /// arrived yet are left out.
+    pub fn children(&self, parent: Option<ID>) -> Vec<ID> {
+        if !self.is_container(parent) {
+            return Vec::new();
+        }
+
+        let mut children = Vec::new();
+        for item in &self.docs[&parent] {
+            for offset in 0..item.len() as u64 {
+                let slot = ID {
+                    client: item.id.client,
+                    clock: item.id.clock + offset,
+                };
+                children.extend(self.slots.get(&(parent, slot)));
+            }
+        }
+        children
+    }
+
+    pub fn kind(&self, node: ID) -> Option<&XmlKind> {
+        self.nodes.get(&node).map(|node| &node.kind)
+    }
+
+    /// Returns the name of the element `node`.
+    pub fn name(&self, node: ID) -> Option<&str> {
+        match self.kind(node)? {
+            XmlKind::Element(name) => Some(name),
+            XmlKind::Text => None,
+        }
+    }
+
+    /// Sets attribute `key` of the element `node` to `value`. Does nothing if
+    /// `node` isn't an element or `key` isn't a valid XML name.
+    pub fn set_attribute(&mut self, node: ID, key: &str, value: &str) {
+        self.write_attribute(node, key, Some(value.to_string()));
+    }
+
+    /// Removes attribute `key` of the element `node`.
+    pub fn remove_attribute(&mut self, node: ID, key: &str) {
+        self.write_attribute(node, key, None);
+    }
+
+    pub fn attribute(&self, node: ID, key: &str) -> Option<&str> {
+        self.attributes
+            .get(&(node, key.to_string()))?
+            .value
+            .as_deref()
+    }
+
+    /// Returns the attributes of the element `node`, sorted by key.
+    pub fn attributes(&self, node: ID) -> BTreeMap<&str, &str> {
+        self.attributes
+            .values()
+            .filter(|attribute| attribute.node == node)
+            .filter_map(|attribute| Some((attribute.key.as_str(), attribute.value.as_deref()?)))
+            .collect()
+    }
+
+    /// Returns the text of the text node `node`.
+    pub fn text(&self, node: ID) -> Option<&Doc> {
+        self.is_text(node).then(|| &self.docs[&Some(node)])
+    }
+
+    /// Returns the text of the text node `node` for editing.
+    pub fn text_mut(&mut self, node: ID) -> Option<&mut Doc> {
+        if self.is_text(node) {
+            self.docs.get_mut(&Some(node))
+        } else {
+            None
+        }
+    }
+
+    /// Serialises the visible tree as XML, with attributes sorted by key.
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        self.render_children(None, &mut xml);
+        xml
+    }
+
+    pub fn state_vector(&self) -> XmlStateVector {
+        let mut docs: Vec<_> = self
+            .live()
+            .into_iter()
+            .filter_map(|node| {
+                let doc = self.docs.get(&node)?;
+                Some((node, doc.state_vector(), doc.delete_set.clone()))
+            })
+            .collect();
+        docs.sort_unstable_by_key(|(node, _, _)| *node);
+
+        XmlStateVector {
+            nodes: self.node_state(),
+            docs,
+            attributes: self.attribute_state.clone(),
+        }
+    }
+
+    /// Returns everything `remote` hasn't seen: the nodes it doesn't have, the
+    /// unseen changes to the children or text of each node still in the tree,
+    /// and the attributes written since.
+    ///
+    /// Removed nodes stay removed, so what happens inside them is left out.
+    pub fn diff(&self, remote: &XmlStateVector) -> XmlUpdate {
+        let is_known = |id: &ID, state: &StateVector| {
+            state
+                .get(&id.client)
+                .is_some_and(|clock| *clock >= id.clock)
+        };
+        let mut nodes: Vec<XmlNode> = self
+            .nodes
+            .values()
+            .filter(|node| !is_known(&node.id, &remote.nodes))
+            .cloned()
+            .collect();
+        nodes.sort_by_key(|node| node.id);
+
+        let remote_docs: HashMap<&Option<ID>, (&StateVector, &DeleteSet)> = remote
+            .docs
+            .iter()
+            .map(|(node, sv, deleted)| (node, (sv, deleted)))
+            .collect();
+        let (empty_sv, empty_ds) = (StateVector::new(), DeleteSet::new());
+        let mut docs: Vec<(Option<ID>, Update)> = self
+            .live()
+            .into_iter()
+            .filter_map(|node| {
+                let doc = self.docs.get(&node)?;
+                let (sv, deleted) = remote_docs
+                    .get(&node)
+                    .copied()
+                    .unwrap_or((&empty_sv, &empty_ds));
+                let mut update = doc.diff(sv);
+                update.delete_set = doc.delete_set.difference(deleted);
+                Some((node, update))
+            })
+            .filter(|(_, update)| !update.is_empty())
+            .collect();
+        docs.sort_by_key(|(node, _)| *node);
+
+        let mut attributes: Vec<XmlAttribute> = self
+            .attributes
+            .values()
+            .filter(|attribute| !is_known(&attribute.id, &remote.attributes))
+            .cloned()
+            .collect();
+        attributes.sort_by_key(|attribute| attribute.id);
+
+        XmlUpdate {
+            nodes,
+            docs,
+            attributes,
+        }
+    }
+
+    /// Integrates a remote update. Changes to nodes that haven't arrived yet
+    /// are kept and show up once they do. Elements and attributes whose names
+    /// aren't valid XML names are dropped, as are nodes that would end up
+    /// inside themselves.
+    pub fn apply(&mut self, update: XmlUpdate) {
+        for node in update.nodes {
+            if let XmlKind::Element(name) = &node.kind
+                && !is_name(name)
+            {
+                continue;
+            }
+            if self.is_ancestor(node.id, node.parent) {
+                continue;
+            }
+            self.add(node);
+        }
+
+        let client_id = self.client_id;
+        for (node, update) in update.docs {
+            self.docs
+                .entry(node)
+                .or_insert_with(|| Doc::new(client_id))
+                .apply(update);
+        }
+
+        for attribute in update.attributes {
+            if is_name(&attribute.key) {
+                self.merge_attribute(attribute);
+            }
+        }
+    }
+
+    /// Creates a node and its slot among the children of `parent`.
+    fn create(&mut self, parent: Option<ID>, index: usize, kind: XmlKind) -> Option<ID> {
+        if !self.is_container(parent) {
+            return None;
+        }
+
+        let id = ID {
+            client: self.client_id,
+            clock: self
+                .node_state()
+                .get(&self.client_id)
+                .map_or(0, |clock| clock + 1),
+        };
+
+        let children = self.docs.get_mut(&parent)?;
+        let slot = ID {
+            client: children.client_id,
+            clock: children.clock,
+        };
+        children.insert(index, &SLOT.to_string());
+
+        self.add(XmlNode {
+            id,
+            parent,
+            slot,
+            kind,
+        });
+        Some(id)
+    }
+
+    fn add(&mut self, node: XmlNode) {
+        if self.nodes.contains_key(&node.id) {
+            return;
+        }
+
+        let client_id = self.client_id;
+        self.docs
+            .entry(Some(node.id))
+            .or_insert_with(|| Doc::new(client_id));
+        self.slots.insert((node.parent, node.slot), node.id);
+        self.nodes.insert(node.id, node);
+    }
+
+    fn write_attribute(&mut self, node: ID, key: &str, value: Option<String>) {
+        if self.name(node).is_none() || !is_name(key) {
+            return;
+        }
+
+        let clock = self
+            .attribute_state
+            .values()
+            .map(|clock| clock + 1)
+            .max()
+            .unwrap_or(0);
+        self.merge_attribute(XmlAttribute {
+            id: ID {
+                client: self.client_id,
+                clock,
+            },
+            node,
+            key: key.to_string(),
+            value,
+        });
+    }
+
+    /// Keeps `attribute` if it wins over the current value of its key.
+    fn merge_attribute(&mut self, attribute: XmlAttribute) {
+        let seen = self
+            .attribute_state
+            .entry(attribute.id.client)
+            .or_insert(attribute.id.clock);
+        *seen = (*seen).max(attribute.id.clock);
+
+        let key = (attribute.node, attribute.key.clone());
+        let wins = self.attributes.get(&key).is_none_or(|current| {
+            (attribute.id.clock, attribute.id.client) > (current.id.clock, current.id.client)
+        });
+        if wins {
+            self.attributes.insert(key, attribute);
+        }
+    }
+
+    /// Returns, for each client, the last node it created that we have.
+    fn node_state(&self) -> StateVector {
+        let mut state = StateVector::new();
+        for id in self.nodes.keys() {
+            let clock = state.entry(id.client).or_insert(id.clock);
+            *clock = (*clock).max(id.clock);
+        }
+        state
+    }
+
+    /// Returns `true` if `ancestor` is `node` or one of the nodes above it.
+    fn is_ancestor(&self, ancestor: ID, mut node: Option<ID>) -> bool {
+        let mut seen = HashSet::new();
+        while let Some(id) = node {
+            if id == ancestor || !seen.insert(id) {
+                return true;
+            }
+            node = self.nodes.get(&id).and_then(|node| node.parent);
+        }
+        false
+    }
+
+    /// The top level and every node still in the tree, i.e. not removed
+    /// itself or along with an ancestor.
+    fn live(&self) -> Vec<Option<ID>> {
+        let mut live = vec![None];
+        let mut seen = HashSet::new();
+        let mut next = 0;
+        while let Some(&node) = live.get(next) {
+            for child in self.children(node) {
+                if seen.insert(child) {
+                    live.push(Some(child));
+                }
+            }
+            next += 1;
+        }
+        live
+    }
+
+    /// Returns `true` for the top level and for elements.
+    fn is_container(&self, node: Option<ID>) -> bool {
+        node.is_none_or(|node| self.name(node).is_some())
+    }
+
+    fn is_text(&self, node: ID) -> bool {
+        self.kind(node) == Some(&XmlKind::Text)
+    }
+
+    fn render_children(&self, parent: Option<ID>, xml: &mut String) {
+        for child in self.children(parent) {
+            match &self.nodes[&child].kind {
+                XmlKind::Text => escape(&self.docs[&Some(child)].value(), xml),
+                XmlKind::Element(name) => {
+                    xml.push('<');
+                    xml.push_str(name);
+                    for (key, value) in self.attributes(child) {
+                        xml.push(' ');
+                        xml.push_str(key);
+                        xml.push_str("=\"");
+                        escape(value, xml);
+                        xml.push('"');
+                    }
+                    xml.push('>');
+                    self.render_children(Some(child), xml);
+                    xml.push_str("</");
+                    xml.push_str(name);
+                    xml.push('>');
+                }
+            }
+        }
+    }
+}
+
+/// Returns `true` if `name` can be used as an element or attribute name, so
+/// it can be written out as it is.
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_alphabetic() || matches!(first, '_' | ':'))
+        && chars.all(|ch| ch.is_alphanumeric() || matches!(ch, '_' | ':' | '-' | '.'))
+}
+
+/// Appends `text` to `xml`, escaping the characters XML reserves.
+fn escape(text: &str, xml: &mut String) {
+    for ch in text.chars() {
+        match ch {
+            '&' => xml.push_str("&amp;"),
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '"' => xml.push_str("&quot;"),
+            _ => xml.push(ch),
+        }
+    }
+}
+
+fn write_node(encoder: &mut Encoder, node: &XmlNode) {
+    encoder.id(&node.id);
+    encoder.option(node.parent.as_ref(), Encoder::id);
+    encoder.id(&node.slot);
+    match &node.kind {
+        XmlKind::Element(name) => {
+            encoder.u8(0);
+            encoder.string(name);
+        }
+        XmlKind::Text => encoder.u8(1),
+    }
+}
+
+fn read_node(decoder: &mut Decoder) -> Option<XmlNode> {
+    Some(XmlNode {
+        id: decoder.id()?,
+        parent: decoder.option(Decoder::id)?,
+        slot: decoder.id()?,
+        kind: match decoder.u8()? {
+            0 => XmlKind::Element(decoder.string()?),
+            1 => XmlKind::Text,
+            _ => return None,
+        },
+    })
+}
+
+fn write_attribute(encoder: &mut Encoder, attribute: &XmlAttribute) {
+    encoder.id(&attribute.id);
+    encoder.id(&attribute.node);
+    encoder.string(&attribute.key);
+    encoder.option(attribute.value.as_ref(), |e, value| e.string(value));
+}
+
+fn read_attribute(decoder: &mut Decoder) -> Option<XmlAttribute> {
+    Some(XmlAttribute {
+        id: decoder.id()?,
+        node: decoder.id()?,
+        key: decoder.string()?,
+        value: decoder.option(Decoder::string)?,
+    })
+}
+
+impl BinaryEncode for XmlUpdate {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+
+        encoder.u64(self.nodes.len() as u64);
+        for node in &self.nodes {
+            write_node(&mut encoder, node);
+        }
+        encoder.u64(self.docs.len() as u64);
+        for (node, update) in &self.docs {
+            encoder.option(node.as_ref(), Encoder::id);
+            write_update(&mut encoder, update);
+        }
+        encoder.u64(self.attributes.len() as u64);
+        for attribute in &self.attributes {
+            write_attribute(&mut encoder, attribute);
+        }
+
+        encoder.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(bytes);
+        let update = XmlUpdate {
+            nodes: decoder.list(read_node)?,
+            docs: decoder.list(|d| Some((d.option(Decoder::id)?, read_update(d)?)))?,
+            attributes: decoder.list(read_attribute)?,
+        };
+        decoder.is_empty().then_some(update)
+    }
+}
+
+impl BinaryEncode for XmlStateVector {
+    fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+
+        write_state_vector(&mut encoder, &self.nodes);
+        encoder.u64(self.docs.len() as u64);
+        for (node, state_vector, delete_set) in &self.docs {
+            encoder.option(node.as_ref(), Encoder::id);
+            write_state_vector(&mut encoder, state_vector);
+            write_delete_set(&mut encoder, delete_set);
+        }
+        write_state_vector(&mut encoder, &self.attributes);
+
+        encoder.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder::new(bytes);
+        let state_vector = XmlStateVector {
+            nodes: read_state_vector(&mut decoder)?,
+            docs: decoder.list(|d| {
+                Some((
+                    d.option(Decoder::id)?,
+                    read_state_vector(d)?,
+                    read_delete_set(d)?,
+                ))
+            })?,
+            attributes: read_state_vector(&mut decoder)?,
+        };
+        decoder.is_empty().then_some(state_vector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync(a: &mut XmlFragment, b: &mut XmlFragment) {
+        let to_b = a.diff(&b.state_vector());
+        let to_a = b.diff(&a.state_vector());
+        b.apply(to_b);
+        a.apply(to_a);
+    }
+
+    #[test]
+    fn builds_a_tree() {
+        let mut fragment = XmlFragment::new(1);
+        let p = fragment.insert_element(None, 0, "p").unwrap();
+        fragment.set_attribute(p, "class", "intro");
+        fragment.insert_text(Some(p), 0, "Tom & Jerry ");
+        let strong = fragment.insert_element(Some(p), 1, "strong").unwrap();
+        fragment.insert_text(Some(strong), 0, "<3");
+
+        assert_eq!(
+            fragment.to_xml(),
+            r#"<p class="intro">Tom &amp; Jerry <strong>&lt;3</strong></p>"#
+        );
+        assert_eq!(fragment.name(p), Some("p"));
+        assert_eq!(fragment.children(None), vec![p]);
+    }
+
+    #[test]
+    fn text_nodes_have_no_children() {
+        let mut fragment = XmlFragment::new(1);
+        let text = fragment.insert_text(None, 0, "hi").unwrap();
+
+        assert_eq!(fragment.insert_element(Some(text), 0, "p"), None);
+        assert_eq!(fragment.name(text), None);
+        fragment.set_attribute(text, "class", "x");
+        assert!(fragment.attributes(text).is_empty());
+        assert_eq!(fragment.to_xml(), "hi");
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let mut a = XmlFragment::new(1);
+        let mut b = XmlFragment::new(2);
+        let p = a.insert_element(None, 0, "p").unwrap();
+        let text = a.insert_text(Some(p), 0, "hello").unwrap();
+        sync(&mut a, &mut b);
+
+        a.text_mut(text).unwrap().insert(5, " world");
+        a.set_attribute(p, "align", "left");
+        b.set_attribute(p, "align", "right");
+        b.insert_element(None, 1, "hr");
+        b.text_mut(text).unwrap().insert(0, "oh, ");
+        sync(&mut a, &mut b);
+
+        assert_eq!(
+            a.to_xml(),
+            r#"<p align="right">oh, hello world</p><hr></hr>"#
+        );
+        assert_eq!(a.to_xml(), b.to_xml());
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_subtree() {
+        let mut a = XmlFragment::new(1);
+        let mut b = XmlFragment::new(2);
+        let list = a.insert_element(None, 0, "ul").unwrap();
+        let item = a.insert_element(Some(list), 0, "li").unwrap();
+        a.insert_text(Some(item), 0, "one");
+        a.insert_element(None, 1, "p");
+        sync(&mut a, &mut b);
+
+        a.remove(None, 0, 1);
+        b.insert_text(Some(item), 1, " more");
+        a.remove_attribute(list, "missing");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.to_xml(), "<p></p>");
+        assert_eq!(b.to_xml(), "<p></p>");
+    }
+
+    #[test]
+    fn removed_attributes_stay_removed() {
+        let mut a = XmlFragment::new(1);
+        let mut b = XmlFragment::new(2);
+        let p = a.insert_element(None, 0, "p").unwrap();
+        a.set_attribute(p, "class", "intro");
+        sync(&mut a, &mut b);
+
+        b.remove_attribute(p, "class");
+        sync(&mut a, &mut b);
+
+        assert_eq!(a.attribute(p, "class"), None);
+        assert_eq!(a.to_xml(), "<p></p>");
+    }
+
+    #[test]
+    fn updates_round_trip() {
+        let mut a = XmlFragment::new(1);
+        let p = a.insert_element(None, 0, "p").unwrap();
+        a.set_attribute(p, "class", "intro");
+        let text = a.insert_text(Some(p), 0, "hello").unwrap();
+
+        let mut b = XmlFragment::new(2);
+        let sv = XmlStateVector::decode(&b.state_vector().encode()).unwrap();
+        let update = a.diff(&sv);
+        assert_eq!(XmlUpdate::decode(&update.encode()), Some(update.clone()));
+        b.apply(update);
+        b.text_mut(text).unwrap().delete(0, 1);
+        a.apply(b.diff(&a.state_vector()));
+        assert_eq!(b.to_xml(), a.to_xml());
+
+        let sv = b.state_vector();
+        assert_eq!(XmlStateVector::decode(&sv.encode()), Some(sv.clone()));
+        let update = a.diff(&sv);
+        assert!(update.nodes.is_empty());
+        assert!(update.docs.is_empty());
+        assert!(update.attributes.is_empty());
+        assert_eq!(XmlUpdate::decode(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn diff_sends_only_new_attributes() {
+        let mut a = XmlFragment::new(1);
+        let mut b = XmlFragment::new(2);
+        let p = a.insert_element(None, 0, "p").unwrap();
+        a.set_attribute(p, "class", "intro");
+        a.set_attribute(p, "id", "first");
+        sync(&mut a, &mut b);
+
+        a.set_attribute(p, "class", "outro");
+        let update = a.diff(&b.state_vector());
+
+        assert_eq!(update.attributes.len(), 1);
+        assert_eq!(update.attributes[0].value.as_deref(), Some("outro"));
+        b.apply(update);
+        assert_eq!(b.to_xml(), r#"<p class="outro" id="first"></p>"#);
+    }
+
+    #[test]
+    fn removed_nodes_are_left_out_of_sync() {
+        let mut a = XmlFragment::new(1);
+        let list = a.insert_element(None, 0, "ul").unwrap();
+        let item = a.insert_element(Some(list), 0, "li").unwrap();
+        let text = a.insert_text(Some(item), 0, "one").unwrap();
+        a.remove(None, 0, 1);
+
+        let sv = a.state_vector();
+        assert_eq!(sv.docs.len(), 1);
+        let update = a.diff(&XmlFragment::new(2).state_vector());
+        assert_eq!(update.nodes.len(), 3);
+        assert_eq!(update.docs.len(), 1);
+
+        let mut b = XmlFragment::new(2);
+        b.apply(update);
+        assert_eq!(b.to_xml(), "");
+        assert!(b.text(text).unwrap().is_empty());
+    }
+
+    #[test]
+    fn diff_leaves_out_deletions_the_remote_has() {
+        let mut a = XmlFragment::new(1);
+        let mut b = XmlFragment::new(2);
+        let p = a.insert_element(None, 0, "p").unwrap();
+        let text = a.insert_text(Some(p), 0, "hello").unwrap();
+        a.insert_element(None, 1, "hr").unwrap();
+        a.remove(None, 1, 1);
+        a.text_mut(text).unwrap().delete(0, 1);
+        sync(&mut a, &mut b);
+
+        assert!(a.diff(&b.state_vector()).docs.is_empty());
+
+        b.text_mut(text).unwrap().delete(0, 1);
+        let update = b.diff(&a.state_vector());
+        assert_eq!(update.docs.len(), 1);
+        assert_eq!(update.docs[0].1.delete_set.iter().count(), 1);
+        a.apply(update);
+        assert_eq!(a.to_xml(), "<p>llo</p>");
+    }
+
+    #[test]
+    fn nodes_inside_themselves_are_rejected() {
+        let mut a = XmlFragment::new(1);
+        let p = a.insert_element(None, 0, "p").unwrap();
+        let mut update = a.diff(&XmlStateVector::default());
+        let slot = update.nodes[0].slot;
+        let node = |clock, parent| XmlNode {
+            id: ID { client: 2, clock },
+            parent: Some(ID {
+                client: 2,
+                clock: parent,
+            }),
+            slot,
+            kind: XmlKind::Element("div".into()),
+        };
+        update.nodes.extend([node(0, 0), node(1, 2), node(2, 1)]);
+
+        let mut b = XmlFragment::new(3);
+        b.apply(update);
+        b.insert_element(Some(p), 0, "span").unwrap();
+
+        assert_eq!(b.to_xml(), "<p><span></span></p>");
+        assert_eq!(b.state_vector().docs.len(), 3);
+        let mut c = XmlFragment::new(4);
+        c.apply(b.diff(&c.state_vector()));
+        assert_eq!(c.to_xml(), b.to_xml());
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        let mut a = XmlFragment::new(1);
+        assert_eq!(a.insert_element(None, 0, "p onclick=\"x\""), None);
+        assert_eq!(a.insert_element(None, 0, "1p"), None);
+        assert_eq!(a.insert_element(None, 0, ""), None);
+        let p = a.insert_element(None, 0, "svg:g").unwrap();
+        a.set_attribute(p, "a=\"b\"", "c");
+        a.set_attribute(p, "data-x", "1");
+
+        let mut update = a.diff(&XmlStateVector::default());
+        update.nodes[0].kind = XmlKind::Element("<script>".into());
+        update.attributes[0].key = "x y".into();
+        let mut b = XmlFragment::new(2);
+        b.apply(update);
+
+        assert_eq!(a.to_xml(), r#"<svg:g data-x="1"></svg:g>"#);
+        assert_eq!(b.to_xml(), "");
+    }
+}
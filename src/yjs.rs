@@ -0,0 +1,402 @@
+use crate::encoding::{Decoder, Encoder};
+use crate::{DeleteSet, ID, Item, StateVector, Update};
+use std::collections::BTreeMap;
+
+/// Name of the `Y.Text` that Yjs peers edit, i.e. `ydoc.getText("text")`.
+pub(crate) const YJS_TEXT: &str = "text";
+
+/// Info bits of a struct: whether it has a left and a right origin, and
+/// whether it is a map entry. The low five bits say what it holds.
+const HAS_ORIGIN: u8 = 0x80;
+const HAS_RIGHT_ORIGIN: u8 = 0x40;
+const HAS_PARENT_SUB: u8 = 0x20;
+const CONTENT: u8 = 0x1f;
+
+const CONTENT_GC: u8 = 0;
+const CONTENT_DELETED: u8 = 1;
+const CONTENT_STRING: u8 = 4;
+const CONTENT_SKIP: u8 = 10;
+
+/// Stand-in for characters that take up a different number of clock values
+/// here and in Yjs, and content of deleted structs.
+const REPLACEMENT: char = '\u{fffd}';
+
+/// Deleted clock values a single update may fill with stand-ins, so a few
+/// bytes claiming a huge deletion can't take up all memory.
+const MAX_DELETED: u64 = 1 << 24;
+
+/// Encodes `state_vector` in Yjs' v1 encoding, which counts the clock values
+/// of each client rather than naming the last one.
+pub(crate) fn encode_state_vector(state_vector: &StateVector) -> Vec<u8> {
+    let mut clients: Vec<_> = state_vector.iter().collect();
+    clients.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut encoder = Encoder::new();
+    encoder.u64(clients.len() as u64);
+    for (client, clock) in clients {
+        encoder.u64(*client);
+        encoder.u64(clock + 1);
+    }
+    encoder.into_bytes()
+}
+
+pub(crate) fn decode_state_vector(bytes: &[u8]) -> Option<StateVector> {
+    let mut decoder = Decoder::new(bytes);
+    let mut state_vector = StateVector::new();
+    for _ in 0..decoder.u64()? {
+        let client = decoder.u64()?;
+        if let Some(clock) = decoder.u64()?.checked_sub(1) {
+            state_vector.insert(client, clock);
+        }
+    }
+    decoder.is_empty().then_some(state_vector)
+}
+
+/// Encodes the items and deletions of `update` in Yjs' v1 encoding, as
+/// insertions into [`YJS_TEXT`]. Marks, moves, counters, registers and
+/// sub-documents have no counterpart there and are left out.
+///
+/// Yjs counts UTF-16 code units where tinycrdt counts characters, so
+/// characters outside the Basic Multilingual Plane are sent as U+FFFD.
+pub(crate) fn encode_update(update: &Update) -> Vec<u8> {
+    let mut clients: BTreeMap<u64, Vec<&Item>> = BTreeMap::new();
+    for item in update.items.iter().filter(|item| item.end().is_some()) {
+        clients.entry(item.id.client).or_default().push(item);
+    }
+
+    let mut encoder = Encoder::new();
+    encoder.u64(clients.len() as u64);
+    for (client, mut items) in clients.into_iter().rev() {
+        items.sort_by_key(|item| item.id.clock);
+
+        // Each client's structs cover its clock range without gaps, so gaps
+        // and overlaps are made up for here
+        let start = items[0].id.clock;
+        let mut structs = Vec::new();
+        let mut next = start;
+        for item in items {
+            let end = item.end().expect("overflowing items were left out");
+            if end <= next {
+                continue;
+            }
+            if item.id.clock > next {
+                structs.push(Struct::Skip(item.id.clock - next));
+                structs.push(Struct::Item(item.clone()));
+            } else if item.id.clock < next {
+                let mut item = item.clone();
+                structs.push(Struct::Item(
+                    item.split_off((next - item.id.clock) as usize),
+                ));
+            } else {
+                structs.push(Struct::Item(item.clone()));
+            }
+            next = end;
+        }
+
+        encoder.u64(structs.len() as u64);
+        encoder.u64(client);
+        encoder.u64(start);
+        for s in &structs {
+            write_struct(&mut encoder, s);
+        }
+    }
+
+    let mut deleted: BTreeMap<u64, Vec<(u64, u64)>> = BTreeMap::new();
+    for (client, clock, len) in update.delete_set.iter() {
+        deleted.entry(client).or_default().push((clock, len));
+    }
+    encoder.u64(deleted.len() as u64);
+    for (client, mut ranges) in deleted.into_iter().rev() {
+        ranges.sort_unstable();
+        encoder.u64(client);
+        encoder.u64(ranges.len() as u64);
+        for (clock, len) in ranges {
+            encoder.u64(clock);
+            encoder.u64(len);
+        }
+    }
+    encoder.into_bytes()
+}
+
+/// Decodes an update in Yjs' v1 encoding. Returns `None` if it is malformed or
+/// holds anything but text inserted into [`YJS_TEXT`], e.g. formatting,
+/// embeds or other shared types.
+///
+/// Characters outside the Basic Multilingual Plane take up two clock values in
+/// Yjs, so each becomes two U+FFFD. Deleted content arrives without its text
+/// and is filled in with U+FFFD as well.
+pub(crate) fn decode_update(bytes: &[u8]) -> Option<Update> {
+    let mut decoder = Decoder::new(bytes);
+    let mut items = Vec::new();
+    let mut delete_set = DeleteSet::new();
+    let mut filled = 0u64;
+    let mut deleted = |id: ID, len: u64, delete_set: &mut DeleteSet| {
+        filled = filled
+            .checked_add(len)
+            .filter(|filled| *filled <= MAX_DELETED)?;
+        delete_set.insert(id, len);
+        Some(REPLACEMENT.to_string().repeat(len as usize))
+    };
+
+    for _ in 0..decoder.u64()? {
+        let structs = decoder.u64()?;
+        let client = decoder.u64()?;
+        let mut clock = decoder.u64()?;
+        for _ in 0..structs {
+            let id = ID { client, clock };
+            let info = decoder.u8()?;
+            let (origin_left, origin_right, content) = match info & CONTENT {
+                CONTENT_SKIP => {
+                    clock = clock.checked_add(decoder.u64()?)?;
+                    continue;
+                }
+                CONTENT_GC => (None, None, deleted(id, decoder.u64()?, &mut delete_set)?),
+                kind => {
+                    let origin_left = match info & HAS_ORIGIN {
+                        0 => None,
+                        _ => Some(decoder.id()?),
+                    };
+                    let origin_right = match info & HAS_RIGHT_ORIGIN {
+                        0 => None,
+                        _ => Some(decoder.id()?),
+                    };
+                    // Only insertions into the root text, no map entries or
+                    // children of other types
+                    if origin_left.is_none()
+                        && origin_right.is_none()
+                        && (info & HAS_PARENT_SUB != 0
+                            || decoder.u64()? != 1
+                            || decoder.string()? != YJS_TEXT)
+                    {
+                        return None;
+                    }
+
+                    let content = match kind {
+                        CONTENT_STRING => decoder
+                            .string()?
+                            .chars()
+                            .flat_map(|ch| match ch.len_utf16() {
+                                1 => [Some(ch), None],
+                                _ => [Some(REPLACEMENT), Some(REPLACEMENT)],
+                            })
+                            .flatten()
+                            .collect(),
+                        CONTENT_DELETED => deleted(id, decoder.u64()?, &mut delete_set)?,
+                        _ => return None,
+                    };
+                    (origin_left, origin_right, content)
+                }
+            };
+
+            let item = Item {
+                id,
+                origin_left,
+                origin_right,
+                left: None,
+                right: None,
+                content,
+                is_deleted: false,
+            };
+            if item.content.is_empty() {
+                return None;
+            }
+            clock = item.end()?;
+            items.push(item);
+        }
+    }
+
+    for _ in 0..decoder.u64()? {
+        let client = decoder.u64()?;
+        for _ in 0..decoder.u64()? {
+            let clock = decoder.u64()?;
+            delete_set.insert(ID { client, clock }, decoder.u64()?);
+        }
+    }
+
+    decoder.is_empty().then_some(Update {
+        items,
+        delete_set,
+        ..Update::default()
+    })
+}
+
+/// What a client's clock values are taken up by.
+enum Struct {
+    Item(Item),
+    /// Clock values the update doesn't include.
+    Skip(u64),
+}
+
+fn write_struct(encoder: &mut Encoder, s: &Struct) {
+    let item = match s {
+        Struct::Item(item) => item,
+        Struct::Skip(len) => {
+            encoder.u8(CONTENT_SKIP);
+            encoder.u64(*len);
+            return;
+        }
+    };
+
+    let mut info = CONTENT_STRING;
+    if item.origin_left.is_some() {
+        info |= HAS_ORIGIN;
+    }
+    if item.origin_right.is_some() {
+        info |= HAS_RIGHT_ORIGIN;
+    }
+    encoder.u8(info);
+    if let Some(origin) = &item.origin_left {
+        encoder.id(origin);
+    }
+    if let Some(origin) = &item.origin_right {
+        encoder.id(origin);
+    }
+    if item.origin_left.is_none() && item.origin_right.is_none() {
+        // The parent, named by its key as a root type
+        encoder.u64(1);
+        encoder.string(YJS_TEXT);
+    }
+
+    let content: String = item
+        .content
+        .chars()
+        .map(|ch| match ch.len_utf16() {
+            1 => ch,
+            _ => REPLACEMENT,
+        })
+        .collect();
+    encoder.string(&content);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Crdt, Doc, SequenceCrdt};
+
+    #[test]
+    fn matches_yjs_encoding() {
+        // `Y.encodeStateAsUpdate` of a doc with client ID 1 after
+        // `getText("text").insert(0, "a")`
+        let yjs = [1, 1, 1, 0, 4, 1, 4, b't', b'e', b'x', b't', 1, b'a', 0];
+
+        let mut doc = Doc::new(1);
+        doc.insert(0, "a");
+        let update = doc.diff(&StateVector::new());
+        assert_eq!(encode_update(&update), yjs);
+        assert_eq!(decode_update(&yjs), Some(update));
+
+        assert_eq!(encode_state_vector(&doc.state_vector()), [1, 1, 1]);
+        assert_eq!(decode_state_vector(&[1, 1, 1]), Some(doc.state_vector()));
+    }
+
+    #[test]
+    fn yjs_updates_apply() {
+        let mut doc = Doc::new(1);
+        doc.insert(0, "hello world");
+        doc.delete(0, 6);
+        let mut other = Doc::new(2);
+        other.apply(decode_update(&encode_update(&doc.diff(&StateVector::new()))).unwrap());
+        assert_eq!(other.value(), "world");
+
+        other.insert(5, "!");
+        let update = other.diff(&doc.state_vector());
+        doc.apply(decode_update(&encode_update(&update)).unwrap());
+        assert_eq!(doc.value(), "world!");
+    }
+
+    #[test]
+    fn clock_units_follow_yjs() {
+        // "a😀b" inserted by client 7, whose emoji takes two clock values,
+        // followed by "c" typed after the "b"
+        let mut encoder = Encoder::new();
+        encoder.u64(1);
+        encoder.u64(2);
+        encoder.u64(7);
+        encoder.u64(0);
+        encoder.u8(CONTENT_STRING);
+        encoder.u64(1);
+        encoder.string(YJS_TEXT);
+        encoder.string("a😀b");
+        encoder.u8(CONTENT_STRING | HAS_ORIGIN);
+        encoder.id(&ID {
+            client: 7,
+            clock: 3,
+        });
+        encoder.string("c");
+        encoder.u64(0);
+
+        let mut doc = Doc::new(1);
+        doc.apply(decode_update(&encoder.into_bytes()).unwrap());
+        assert_eq!(doc.value(), "a\u{fffd}\u{fffd}bc");
+        assert_eq!(doc.state_vector(), StateVector::from([(7, 4)]));
+
+        let mut local = Doc::new(1);
+        local.insert(0, "😀");
+        let bytes = encode_update(&local.diff(&StateVector::new()));
+        assert_eq!(decode_update(&bytes).unwrap().items[0].content, "\u{fffd}");
+    }
+
+    #[test]
+    fn deleted_content_is_filled_in() {
+        let mut encoder = Encoder::new();
+        encoder.u64(1);
+        encoder.u64(2);
+        encoder.u64(7);
+        encoder.u64(0);
+        encoder.u8(CONTENT_STRING);
+        encoder.u64(1);
+        encoder.string(YJS_TEXT);
+        encoder.string("ab");
+        encoder.u8(CONTENT_DELETED | HAS_ORIGIN);
+        encoder.id(&ID {
+            client: 7,
+            clock: 1,
+        });
+        encoder.u64(3);
+        encoder.u64(0);
+
+        let mut doc = Doc::new(1);
+        doc.apply(decode_update(&encoder.into_bytes()).unwrap());
+        assert_eq!(doc.value(), "ab");
+        assert_eq!(doc.state_vector(), StateVector::from([(7, 4)]));
+    }
+
+    #[test]
+    fn unsupported_content_is_rejected() {
+        // A map entry, a `Y.Text` with another name and formatting
+        let map = [
+            1,
+            1,
+            1,
+            0,
+            0x20 | 8,
+            1,
+            1,
+            b'm',
+            1,
+            b'k',
+            1,
+            119,
+            1,
+            b'v',
+            0,
+        ];
+        let named = [1, 1, 1, 0, 4, 1, 1, b'x', 1, b'a', 0];
+        let format = [
+            1, 1, 1, 0, 6, 1, 4, b't', b'e', b'x', b't', 1, b'b', 4, b't', b'r', b'u', b'e', 0,
+        ];
+        for bytes in [&map[..], &named, &format] {
+            assert_eq!(decode_update(bytes), None);
+        }
+
+        let mut huge = Encoder::new();
+        huge.u64(1);
+        huge.u64(1);
+        huge.u64(1);
+        huge.u64(0);
+        huge.u8(CONTENT_GC);
+        huge.u64(u64::MAX / 2);
+        huge.u64(0);
+        assert_eq!(decode_update(&huge.into_bytes()), None);
+    }
+}